@@ -1,27 +1,56 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::thread;
-use std::time::Duration;
-
-use anyhow::Result;
-
-use crate::{RunCategory, SplitType};
-use crate::game::{ConsoleGame, EmulatorGame, Game, GameState};
-use crate::lss::{LiveSplit, TimerPhase};
-use crate::platform::{Platform, PlatformRef};
-use crate::splits::Event;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+
+use crate::{RunCategory, SplitType, plain_segment_name};
+use crate::lss::RetryPolicy;
+use crate::game::{Boss, EmulatorGame, Game, GameBackendOptions, GameBackendType, GameState, Item, KnockState, Map, MatchScore, Stage};
+use crate::output::{EventOutput, OutputEvent, SplitReason};
+use crate::platform::{EmulatorSearchOptions, Platform, PlatformRef};
+use crate::run_info::RunInfoCache;
+use crate::split_file::GAME_NAME;
+use crate::splits::{BOSS_DEFEAT_ROOMS, Event, LoadedRoute, RouteAction, RouteEntry, RouteFile, segment_names, stage_range};
+use crate::summary::RunStats;
+use crate::timer::{TimerBackend, TimerBackendType, TimerPhase};
 
 const CONNECTION_RETRY_DURATION: Duration = Duration::from_millis(1000);
 const GAME_RETRY_DURATION: Duration = Duration::from_millis(5000);
-const PROCESS_REFRESH_INTERVAL: Duration = Duration::from_millis(2000);
+/// Ports to look for LiveSplit Server on when we weren't told which one it's using, starting with
+/// its default
+const LIVE_SPLIT_PORTS: RangeInclusive<u16> = 16834..=16843;
+/// How many rounds of failing to find LiveSplit to go through between reminders of where we're
+/// looking
+const LIVE_SPLIT_WAIT_HINT_ROUNDS: u32 = 15;
+pub const PROCESS_REFRESH_INTERVAL: Duration = Duration::from_millis(2000);
 
 const SECOND_ROOM: (u16, u16) = (0, 1);
 const FINAL_BOSS_ROOM: (u16, u16) = (8, 7);
-
-const LIVE_SPLIT_KEEP_ALIVE: i32 = 334; // ~5 seconds at the default update frequency
+/// How far ahead in the route to look for a room when the player goes off the route and comes back
+const MAX_MISSED_ROUTE_EVENTS: usize = 3;
+/// How soon the player has to go back through a door for the split to be undone
+const BACKTRACK_WINDOW: Duration = Duration::from_secs(5);
+
+/// How many segments out of line with the route LiveSplit's segment names are checked for
+const MAX_SEGMENT_DRIFT: usize = 5;
+const LIVE_SPLIT_SYNC_PERIOD: i32 = 334; // ~5 seconds at the default update frequency
+/// How often to check that LiveSplit is still there
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const MATCH_SCORE_REPORT_PERIOD: i32 = 67; // ~1 second at the default update frequency
+const IGT_MODE_SYNC_PERIOD: i32 = 7; // ~100 ms at the default update frequency
 
 const SPLIT_TYPE_VARIABLE_NAME: &str = "GaleriansSplitType";
 const RUN_CATEGORY_VARIABLE_NAME: &str = "GaleriansCategory";
+const ROOM_VARIABLE_NAME: &str = "GaleriansRoom";
+const NEXT_SPLIT_VARIABLE_NAME: &str = "GaleriansNextSplit";
+const STATUS_VARIABLE_NAME: &str = "GaleriansStatus";
 
 #[derive(Debug, Clone)]
 struct KeepAliveCounter {
@@ -62,11 +91,19 @@ enum ConnectionState {
 }
 
 impl ConnectionState {
-    fn advance(&mut self) {
-        *self = match self {
+    const fn next(&self) -> Self {
+        match self {
             Self::LiveSplitPending => Self::GamePending,
             _ => Self::Connected,
-        };
+        }
+    }
+
+    const fn as_str(&self) -> &'static str {
+        match self {
+            Self::LiveSplitPending => "live_split_pending",
+            Self::GamePending => "game_pending",
+            Self::Connected => "connected",
+        }
     }
 }
 
@@ -75,6 +112,13 @@ enum RunState {
     NotStarted,
     Intro,
     Active,
+    /// The player returned to the main menu in practice mode and the splits were rewound to the
+    /// start of the stage; waiting for them to get back into the game
+    StageRestart,
+    /// The player returned to the main menu and may load a save to continue the run from wherever
+    /// it was made, or the autosplitter was started while LiveSplit already had a run going;
+    /// waiting to work out where the player is in the route
+    Resuming,
     Finished,
 }
 
@@ -86,20 +130,64 @@ impl RunState {
     const fn is_active(&self) -> bool {
         matches!(self, Self::Intro | Self::Active)
     }
+
+    const fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotStarted => "not_started",
+            Self::Intro => "intro",
+            Self::Active => "active",
+            Self::StageRestart => "stage_restart",
+            Self::Resuming => "resuming",
+            Self::Finished => "finished",
+        }
+    }
 }
 
-fn wait_for_live_split(port: u16) -> LiveSplit {
+/// What to do when the player returns to the main menu during a run
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum SaveLoadBehavior {
+    /// Reset the run
+    Reset,
+    /// Keep the timer running, and if the player loads a save, continue the splits from the room
+    /// the save was made in
+    Resume,
+}
+
+/// Wait for LiveSplit to be available and connect to it. If we weren't given a port, the usual
+/// ports are tried in turn. Returns the connection and the port it was made on.
+fn wait_for_live_split(backend: TimerBackendType, host: &str, port: Option<u16>, retry_policy: RetryPolicy) -> (Box<dyn TimerBackend>, u16) {
+    let ports: Vec<u16> = match port {
+        Some(port) => vec![port],
+        // the named pipe doesn't have a port, so there's nothing to look for
+        None if backend == TimerBackendType::Pipe => vec![*LIVE_SPLIT_PORTS.start()],
+        None => LIVE_SPLIT_PORTS.collect(),
+    };
+
     log::info!("Waiting for LiveSplit server...");
+    let mut rounds = 0;
     loop {
-        if let Ok(live_split) = LiveSplit::create(port) {
-            return live_split;
+        for &port in &ports {
+            if let Ok(live_split) = backend.open(host, port, retry_policy) {
+                if ports.len() > 1 {
+                    log::info!("Successfully connected to LiveSplit on port {port}");
+                } else {
+                    log::info!("Successfully connected to LiveSplit");
+                }
+                return (live_split, port);
+            }
         }
 
+        rounds += 1;
+        if rounds % LIVE_SPLIT_WAIT_HINT_ROUNDS == 0 && backend != TimerBackendType::Pipe {
+            let (first, last) = (ports[0], ports[ports.len() - 1]);
+            let ports = if first == last { format!("port {first}") } else { format!("ports {first}-{last}") };
+            log::warn!("Still no LiveSplit server on {host} {ports}. Make sure the server is started, or give its port with --live-split-port.");
+        }
         thread::sleep(CONNECTION_RETRY_DURATION);
     }
 }
 
-fn get_live_split_split_type(live_split: &mut LiveSplit) -> Result<Option<SplitType>> {
+fn get_live_split_split_type(live_split: &mut dyn TimerBackend) -> Result<Option<SplitType>> {
     let Some(str_split_type) = live_split.get_custom_variable_value(SPLIT_TYPE_VARIABLE_NAME)? else {
         return Ok(None);
     };
@@ -112,7 +200,32 @@ fn get_live_split_split_type(live_split: &mut LiveSplit) -> Result<Option<SplitT
     Ok(Some(split_type))
 }
 
-fn get_live_split_run_category(live_split: &mut LiveSplit) -> Result<Option<RunCategory>> {
+fn infer_live_split_split_type(live_split: &mut dyn TimerBackend) -> Result<Option<SplitType>> {
+    if let Some(names) = live_split.get_split_names()? {
+        match SplitType::from_segment_names(&names) {
+            Some(split_type) => {
+                log::info!("Inferred split type {} from the names of the segments", split_type.as_str());
+                return Ok(Some(split_type));
+            }
+            None => log::debug!("No split type matches the names of the segments"),
+        }
+    }
+
+    let Some(segment_count) = live_split.get_segment_count()? else {
+        log::debug!("LiveSplit server did not report the number of segments");
+        return Ok(None);
+    };
+
+    let split_type = usize::try_from(segment_count).ok().and_then(SplitType::from_segment_count);
+    match split_type {
+        Some(split_type) => log::info!("Inferred split type {} from the number of segments ({segment_count})", split_type.as_str()),
+        None => log::debug!("No split type matches the number of segments ({segment_count})"),
+    }
+
+    Ok(split_type)
+}
+
+fn get_live_split_run_category(live_split: &mut dyn TimerBackend) -> Result<Option<RunCategory>> {
     let Some(str_run_category) = live_split.get_custom_variable_value(RUN_CATEGORY_VARIABLE_NAME)? else {
         return Ok(None);
     };
@@ -125,16 +238,172 @@ fn get_live_split_run_category(live_split: &mut LiveSplit) -> Result<Option<RunC
     Ok(Some(run_category))
 }
 
+/// Work out from LiveSplit's splits whether this is a console run, if possible
+fn live_split_is_console(live_split: &mut dyn TimerBackend) -> Result<Option<bool>> {
+    let split_type = match get_live_split_split_type(live_split)? {
+        Some(split_type) => Some(split_type),
+        None => infer_live_split_split_type(live_split)?,
+    };
+    Ok(split_type.map(|split_type| split_type.is_console()))
+}
+
+fn confirm(question: &str) -> bool {
+    eprint!("{question} [y/N] ");
+    let mut response = String::new();
+    if io::stderr().flush().and_then(|_| io::stdin().read_line(&mut response)).is_err() {
+        return false;
+    }
+    matches!(response.trim(), "y" | "Y" | "yes")
+}
+
+/// Make sure the backend has everything it needs before we try to connect. If console assets are
+/// missing and an emulator is running, offer to use the emulator instead.
+fn check_backend_assets(game_backend: GameBackendType, platform: &PlatformRef) -> Result<GameBackendType> {
+    let missing = game_backend.missing_assets();
+    if missing.is_empty() {
+        return Ok(game_backend);
+    }
+
+    log::error!("The {} backend can't start because these files are missing or unreadable:", game_backend.as_str());
+    for path in &missing {
+        log::error!("  {path}");
+    }
+
+    if game_backend.is_console()
+        && EmulatorGame::try_connect(platform).is_some()
+        && confirm("An emulator with the game loaded is running. Use it with emulator splits instead?")
+    {
+        return Ok(GameBackendType::Emulator);
+    }
+
+    bail!("Missing assets for the {} backend; make sure the assets directory is next to the autosplitter", game_backend.as_str());
+}
+
+#[derive(Debug, Clone)]
+pub struct AutoSplitterConfig {
+    pub update_frequency: Duration,
+    pub live_split_host: String,
+    pub live_split_port: Option<u16>,
+    pub live_split_retry_policy: RetryPolicy,
+    pub timer_backend: TimerBackendType,
+    pub game_backend: Option<GameBackendType>,
+    pub backend_options: GameBackendOptions,
+    pub emulator_search: EmulatorSearchOptions,
+    pub requested_split_type: Option<SplitType>,
+    pub route: Option<RouteFile>,
+    pub il_stage: Option<Stage>,
+    pub requested_run_category: Option<RunCategory>,
+    pub stage_resets: bool,
+    pub save_loads: SaveLoadBehavior,
+    pub no_auto_reset: bool,
+    pub split_only: bool,
+    pub start_stop_only: bool,
+    pub restore_run: bool,
+    pub checklist: bool,
+    pub hot_swap: bool,
+    pub split_cooldown: Duration,
+    pub unsplit_backtrack: bool,
+    pub strict_segments: bool,
+    pub game_time: GameTimeOptions,
+    pub reports: ReportOptions,
+    pub output: EventOutput,
+}
+
+/// How LiveSplit's game time is driven
+#[derive(Debug, Clone, Default)]
+pub struct GameTimeOptions {
+    /// Keep LiveSplit's game time paused and drive it entirely from the in-game timer
+    pub igt_mode: bool,
+    /// How far the video capture lags behind the console. Only matters when the runner starts the
+    /// timer themselves.
+    pub capture_latency: Duration,
+}
+
+/// What the autosplitter reports about the run besides splitting
+#[derive(Debug, Clone, Default)]
+pub struct ReportOptions {
+    /// Keep LiveSplit custom variables up to date with the autosplitter's state so they can be
+    /// shown in the layout
+    pub layout_variables: bool,
+    /// Where to save a report of each completed run
+    pub run_report_dir: Option<PathBuf>,
+}
+
+/// Undoing door splits when the player goes straight back through the door
+#[derive(Debug, Default)]
+struct Backtrack {
+    enabled: bool,
+    /// The room the player left at the last door split, and when
+    room: Option<((u16, u16), Instant)>,
+}
+
+/// Setting LiveSplit's game time from the in-game timer
+#[derive(Debug)]
+struct IgtSync {
+    enabled: bool,
+    period: KeepAliveCounter,
+}
+
+impl IgtSync {
+    fn should_sync(&mut self) -> bool {
+        self.enabled && self.period.should_check()
+    }
+}
+
+/// Holding game time back at the start of the run to make up for capture latency
+#[derive(Debug, Default)]
+struct LatencyCompensation {
+    capture_latency: Duration,
+    /// Whether we've held back game time in the current run
+    is_compensated: bool,
+    /// When to let game time run again. From then on, it stays that far behind real time for the
+    /// rest of the run.
+    hold_until: Option<Instant>,
+}
+
+/// What we've told LiveSplit about the run through custom variables and run metadata
+#[derive(Debug, Default)]
+struct Published {
+    /// Whether to keep the custom variables for the layout up to date
+    layout_variables: bool,
+    /// The values we last gave LiveSplit for each custom variable, so we only send changes
+    variables: HashMap<&'static str, String>,
+    /// The split type and run category we last set in LiveSplit's run metadata
+    metadata: Option<(SplitType, RunCategory)>,
+}
+
+/// Statistics for the current run and where to save the report when it's done
+#[derive(Debug, Default)]
+struct RunSummaryState {
+    stats: RunStats,
+    report_dir: Option<PathBuf>,
+}
+
 #[derive(Debug)]
 pub struct AutoSplitter {
     connection_state: ConnectionState,
     update_frequency: Duration,
-    live_split: LiveSplit,
+    live_split: Box<dyn TimerBackend>,
     game: Box<dyn Game>,
+    game_backend: GameBackendType,
+    /// Whether the user told us which backend to use, in which case we never switch to another one
+    is_backend_requested: bool,
+    backend_options: GameBackendOptions,
+    /// Whether to switch between emulator and console when the game is lost
+    hot_swap: bool,
     platform: PlatformRef,
     run_state: RunState,
     last_room: (u16, u16),
-    live_split_keep_alive: KeepAliveCounter,
+    /// How long after a room change to ignore anything that would cause another split
+    split_cooldown: Duration,
+    last_room_change: Option<Instant>,
+    /// Number of doors the player has gone through since the run started
+    door_count: u32,
+    backtrack: Backtrack,
+    live_split_sync: KeepAliveCounter,
+    last_heartbeat: Instant,
+    match_score_report: KeepAliveCounter,
+    igt: IgtSync,
     requested_split_type: Option<SplitType>,
     effective_split_type: Option<SplitType>,
     last_reported_split_type: Option<SplitType>,
@@ -142,35 +411,143 @@ pub struct AutoSplitter {
     effective_run_category: Option<RunCategory>,
     last_reported_run_category: Option<RunCategory>,
     splits: Option<&'static [Event]>,
+    /// The route file's entries for the events in `splits`, with their notes and actions. This is
+    /// empty for the built-in routes, which split on every event.
+    split_entries: &'static [RouteEntry],
+    /// Route loaded from a file, which is used instead of the split type's route
+    custom_route: Option<LoadedRoute>,
+    /// Which path the player has taken at each of the route file's branches, 0 being the main path
+    branch_choices: Vec<usize>,
+    /// Where `splits` starts in the full route, for individual level runs
+    route_offset: usize,
+    /// Number of route events without a segment in LiveSplit that the player has passed since
+    /// LiveSplit's last split, which is what puts the route ahead of LiveSplit's split index
+    checkpoints_passed: i64,
+    /// LiveSplit's segment count and the route's expected segment count the last time we compared
+    /// them, so we only check again when one of them changes
+    checked_segments: Option<(i64, Option<usize>)>,
+    /// LiveSplit's segment count, if it doesn't match the route
+    segment_mismatch: Option<i64>,
+    /// When LiveSplit doesn't have the same number of segments as the route, stop splitting once
+    /// the route gets to LiveSplit's last segment instead of ending the run early
+    strict_segments: bool,
+    /// Whether we've held back a split because the route ran past LiveSplit's last segment
+    is_past_segments: bool,
+    latency: LatencyCompensation,
+    /// The stage being run, for individual level runs
+    il_stage: Option<Stage>,
+    /// Whether we've seen the main menu since the last individual level run, so that a run only
+    /// starts when a save is loaded
+    il_armed: bool,
+    stage_resets: bool,
+    save_loads: SaveLoadBehavior,
+    /// Practice mode: never reset the run just because the player returned to the main menu
+    no_auto_reset: bool,
+    /// Leave starting and resetting the timer to the runner and only ever split
+    split_only: bool,
+    /// Only start the timer at new game and stop it at the end, leaving the splits in between to
+    /// the runner
+    start_stop_only: bool,
+    published: Published,
+    restore_run: bool,
+    checklist: bool,
+    /// Whether we've already refused to start the run for the current new game start
+    refused_start: bool,
+    /// The split index LiveSplit was at the last time we checked, so we can put it back if
+    /// LiveSplit goes away
+    last_split_index: i64,
+    /// The split index whose event we've been seeing and for how many updates in a row
+    event_streak: (i64, u32),
+    /// When the event for the current split was first seen, so we can tell how long it took to split
+    event_first_seen: Instant,
+    /// When the current update started reading the game state
+    update_started: Instant,
+    summary: RunSummaryState,
+    current_stage: Option<Stage>,
+    stage_start_split_index: i64,
+    knock_state: Option<KnockState>,
+    /// The boss we've seen with HP left while waiting for it to be defeated
+    boss_seen_alive: Option<Boss>,
+    /// The flag we've seen set while waiting for it to be cleared
+    flag_seen_set: Option<(Stage, u32)>,
+    /// The item we've seen in the inventory while waiting for it to be used
+    item_seen_held: Option<Item>,
+    /// Every item the player has picked up since the run started, including ones they've used
+    items_collected: Vec<Item>,
+    /// With randomizer splits, the key items and defeated bosses that have been split on
+    randomizer_progress: (Vec<Item>, Vec<Boss>),
+    run_info: RunInfoCache,
+    output: EventOutput,
 }
 
 impl AutoSplitter {
-    pub fn create(
-        update_frequency: Duration,
-        live_split_port: u16,
-        capture_device: i32,
-        force_calibrate: bool,
-        requested_split_type: Option<SplitType>,
-        requested_run_category: Option<RunCategory>,
-    ) -> Result<Self> {
-        let mut live_split = wait_for_live_split(live_split_port);
-        let is_console = match requested_split_type {
-            Some(split_type) => split_type.is_console(),
-            None => {
-                match get_live_split_split_type(&mut live_split)? {
-                    Some(split_type) => split_type.is_console(),
-                    None => false,
-                }
-            }
+    pub fn create(config: AutoSplitterConfig) -> Result<Self> {
+        let AutoSplitterConfig {
+            update_frequency,
+            live_split_host,
+            live_split_port,
+            live_split_retry_policy,
+            timer_backend,
+            game_backend,
+            mut backend_options,
+            emulator_search,
+            mut requested_split_type,
+            route,
+            il_stage,
+            requested_run_category,
+            stage_resets,
+            save_loads,
+            no_auto_reset,
+            split_only,
+            start_stop_only,
+            restore_run,
+            checklist,
+            hot_swap,
+            split_cooldown,
+            unsplit_backtrack,
+            strict_segments,
+            game_time,
+            reports,
+            output,
+        } = config;
+
+        let (mut live_split, live_split_port) = wait_for_live_split(timer_backend, &live_split_host, live_split_port, live_split_retry_policy);
+        let run_info = RunInfoCache::start(timer_backend, live_split_host, live_split_port, live_split_retry_policy);
+        run_info.refresh();
+        let mut is_backend_requested = game_backend.is_some();
+        let game_backend = match (game_backend, &route, requested_split_type) {
+            (Some(game_backend), _, _) => game_backend,
+            (None, Some(route), _) => GameBackendType::for_run(route.console),
+            (None, None, Some(split_type)) => GameBackendType::for_run(split_type.is_console()),
+            (None, None, None) => GameBackendType::for_run(live_split_is_console(live_split.as_mut())?.unwrap_or(false)),
         };
+        log::debug!("Using the {} game backend", game_backend.as_str());
 
-        let platform = Rc::new(RefCell::new(Platform::new(PROCESS_REFRESH_INTERVAL)));
+        let platform = Rc::new(RefCell::new(Platform::new(PROCESS_REFRESH_INTERVAL, emulator_search)));
 
-        let game: Box<dyn Game> = if is_console {
-            Box::new(ConsoleGame::connect(capture_device, force_calibrate)?)
-        } else {
-            Box::new(EmulatorGame::connect(&platform))
-        };
+        let checked_backend = check_backend_assets(game_backend, &platform)?;
+        if checked_backend != game_backend {
+            log::info!("Falling back to the {} backend", checked_backend.as_str());
+            requested_split_type = requested_split_type.map(|split_type| split_type.emulator_equivalent());
+            // switching back would only run into the same missing assets
+            is_backend_requested = true;
+        }
+        let game_backend = checked_backend;
+
+        let game = game_backend.connect(&backend_options, &platform, true)?;
+        // calibration only needs to be forced or previewed the first time we connect
+        backend_options.force_calibrate = false;
+        backend_options.preview_calibration = false;
+
+        if game_time.igt_mode && game.igt().is_none() {
+            bail!("The in-game time can't be read with this game backend, so --igt can't be used");
+        }
+        if let Some(route) = &route && route.has_event(|event| matches!(event, Event::BossDefeated(boss) if game.boss_health(*boss).is_none())) {
+            bail!("Boss HP can't be read with this game backend, so the route's boss_defeated events would never split");
+        }
+        if let Some(route) = &route && game.cutscene_id().is_none() && route.has_event(|event| matches!(event, Event::Cutscene(_))) {
+            bail!("Cutscenes can't be detected with this game backend, so the route's cutscene events would never split");
+        }
 
         log::info!("Autosplitter is ready to go");
 
@@ -179,11 +556,22 @@ impl AutoSplitter {
             update_frequency,
             live_split,
             game,
+            game_backend,
+            is_backend_requested,
+            backend_options,
+            hot_swap,
             platform,
             run_state: RunState::NotStarted,
             last_room: (0, 0),
+            split_cooldown,
+            last_room_change: None,
+            door_count: 0,
+            backtrack: Backtrack { enabled: unsplit_backtrack, room: None },
             // need to trigger LiveSplit sync on first update so split type is set
-            live_split_keep_alive: KeepAliveCounter::new(LIVE_SPLIT_KEEP_ALIVE).with_trigger_on_start(),
+            live_split_sync: KeepAliveCounter::new(LIVE_SPLIT_SYNC_PERIOD).with_trigger_on_start(),
+            last_heartbeat: Instant::now(),
+            match_score_report: KeepAliveCounter::new(MATCH_SCORE_REPORT_PERIOD),
+            igt: IgtSync { enabled: game_time.igt_mode, period: KeepAliveCounter::new(IGT_MODE_SYNC_PERIOD) },
             requested_split_type,
             effective_split_type: None,
             last_reported_split_type: None,
@@ -191,6 +579,42 @@ impl AutoSplitter {
             effective_run_category: None,
             last_reported_run_category: None,
             splits: None,
+            split_entries: &[],
+            branch_choices: route.as_ref().map_or_else(Vec::new, |route| vec![0; route.branches.len()]),
+            custom_route: route.map(LoadedRoute::new),
+            route_offset: 0,
+            checkpoints_passed: 0,
+            checked_segments: None,
+            segment_mismatch: None,
+            strict_segments,
+            is_past_segments: false,
+            latency: LatencyCompensation { capture_latency: game_time.capture_latency, ..LatencyCompensation::default() },
+            il_stage,
+            il_armed: false,
+            stage_resets,
+            save_loads,
+            no_auto_reset,
+            split_only,
+            start_stop_only,
+            published: Published { layout_variables: reports.layout_variables, ..Published::default() },
+            restore_run,
+            checklist,
+            refused_start: false,
+            last_split_index: -1,
+            event_streak: (-1, 0),
+            event_first_seen: Instant::now(),
+            update_started: Instant::now(),
+            summary: RunSummaryState { report_dir: reports.run_report_dir, ..RunSummaryState::default() },
+            current_stage: None,
+            stage_start_split_index: 0,
+            knock_state: None,
+            boss_seen_alive: None,
+            flag_seen_set: None,
+            item_seen_held: None,
+            items_collected: Vec::new(),
+            randomizer_progress: (Vec::new(), Vec::new()),
+            run_info,
+            output,
         })
     }
 
@@ -198,29 +622,626 @@ impl AutoSplitter {
         (self.game.map_id(), self.game.room_id())
     }
 
-    pub fn split(&mut self) -> Result<()> {
+    fn set_run_state(&mut self, run_state: RunState) {
+        if run_state == RunState::NotStarted && self.run_state.is_started() && self.run_state != RunState::Finished {
+            self.summary.stats.reset();
+        }
+
+        if self.run_state != run_state {
+            self.run_state = run_state;
+            self.output.emit(OutputEvent::RunState { state: run_state.as_str() });
+        }
+    }
+
+    fn set_connection_state(&mut self, connection_state: ConnectionState) {
+        if self.connection_state != connection_state {
+            self.connection_state = connection_state;
+            self.output.emit(OutputEvent::ConnectionState { state: connection_state.as_str() });
+
+            // there's no one to tell if LiveSplit is what we lost
+            let status = match connection_state {
+                ConnectionState::LiveSplitPending => None,
+                ConnectionState::GamePending => Some("Waiting for game"),
+                ConnectionState::Connected => Some("Connected"),
+            };
+            if let Some(status) = status && let Err(e) = self.publish_variable(STATUS_VARIABLE_NAME, String::from(status)) {
+                log::debug!("Failed to update the status variable: {e}");
+            }
+        }
+    }
+
+    /// Set a LiveSplit custom variable for the layout, if it's changed since we last set it
+    fn publish_variable(&mut self, variable_name: &'static str, value: String) -> Result<()> {
+        if !self.published.layout_variables || self.published.variables.get(variable_name) == Some(&value) {
+            return Ok(());
+        }
+
+        self.live_split.set_custom_variable(variable_name, &value)?;
+        self.published.variables.insert(variable_name, value);
+        Ok(())
+    }
+
+    /// Show the player's room and the next split in LiveSplit's custom variables
+    fn publish_progress(&mut self, route_index: i64) -> Result<()> {
+        if !self.published.layout_variables {
+            return Ok(());
+        }
+
+        let (map, room) = self.current_room();
+        let room = match Map::from_id(map) {
+            Some(map) => format!("{map:?} {room}"),
+            None => format!("{map} {room}"),
+        };
+        self.publish_variable(ROOM_VARIABLE_NAME, room)?;
+
+        let next_split = match self.splits {
+            Some(_) => self.event_name(route_index).unwrap_or_else(|| String::from("-")),
+            None if self.effective_split_type == Some(SplitType::Randomizer) => String::from("Any key item or boss"),
+            None => String::from("Any door"),
+        };
+        self.publish_variable(NEXT_SPLIT_VARIABLE_NAME, next_split)
+    }
+
+    pub fn split(&mut self, reason: SplitReason) -> Result<()> {
         if self.run_state == RunState::Finished {
             return Ok(());
         }
 
         if self.run_state == RunState::NotStarted {
-            self.run_state = RunState::Intro;
+            self.set_run_state(RunState::Intro);
             self.last_room = (0, 0);
+            self.current_stage = None;
+            self.stage_start_split_index = 0;
+            self.knock_state = None;
+            self.door_count = 0;
+            self.boss_seen_alive = None;
+            self.flag_seen_set = None;
+            self.item_seen_held = None;
+            self.items_collected.clear();
+            if self.branch_choices.iter().any(|choice| *choice != 0) {
+                self.branch_choices.fill(0);
+                if let Some(split_type) = self.effective_split_type {
+                    self.apply_route(split_type);
+                }
+            }
+            self.randomizer_progress = (Vec::new(), Vec::new());
+            self.is_past_segments = false;
+            self.backtrack.room = None;
+            self.summary.stats.start();
+        }
+
+        if self.split_only {
+            if reason == SplitReason::RunStart {
+                log::debug!("Leaving the timer for the runner to start");
+                return Ok(());
+            }
+
+            // startorsplit would start the timer if it isn't running
+            if self.live_split.get_timer_phase()? != TimerPhase::Running {
+                log::debug!("Not splitting because the timer isn't running");
+                return Ok(());
+            }
+        }
+
+        if self.start_stop_only {
+            match reason {
+                SplitReason::RunStart => (),
+                SplitReason::FinalBoss | SplitReason::StageEnd => self.skip_to_last_split()?,
+                SplitReason::RoomChange | SplitReason::RouteEvent | SplitReason::ItemAcquired | SplitReason::BossDefeated => return Ok(()),
+            }
+        }
+
+        if self.strict_segments && let Some(segment_count) = self.segment_mismatch {
+            match reason {
+                SplitReason::RunStart => (),
+                SplitReason::FinalBoss | SplitReason::StageEnd => self.skip_to_last_split()?,
+                _ => if self.live_split.get_split_index()? >= segment_count - 1 {
+                    if !self.is_past_segments {
+                        log::warn!("The route has run past the last segment in LiveSplit; no more splits until the end of the run");
+                        self.is_past_segments = true;
+                    }
+                    return Ok(());
+                },
+            }
+        }
+
+        if log::log_enabled!(log::Level::Debug) {
+            let split_index = self.live_split.get_split_index()?;
+            if let Some(segment) = self.run_info.segment(split_index) {
+                log::debug!(
+                    "Completed segment {} (comparison: {})",
+                    segment.name,
+                    segment.comparison_time.as_deref().unwrap_or("-"),
+                );
+            }
+        }
+
+        let name = if reason == SplitReason::RouteEvent {
+            let split_index = self.live_split.get_split_index()?;
+            self.event_name(self.route_index(split_index))
+        } else {
+            None
+        };
+        if let Some(name) = &name {
+            log::debug!("Split on {name}");
+        }
+
+        self.live_split.split()?;
+        if reason == SplitReason::RunStart && self.igt.enabled {
+            self.start_igt_mode()?;
+        }
+        if reason == SplitReason::RoomChange {
+            self.backtrack.room = Some((self.last_room, Instant::now()));
+        }
+        if reason != SplitReason::RunStart {
+            let seen = if reason == SplitReason::RouteEvent { self.event_first_seen } else { self.update_started };
+            self.summary.stats.split(seen.elapsed());
+        }
+        self.last_split_index += 1;
+        self.checkpoints_passed = 0;
+        self.run_info.update_current_segment();
+        if let Err(e) = self.game.save_split_screenshot(self.last_split_index) {
+            log::warn!("Failed to save split screenshot: {e}");
+        }
+
+        let (map, room) = self.current_room();
+        self.output.emit(OutputEvent::Split { reason, map, room, name });
+        Ok(())
+    }
+
+    /// A readable name for the route event at the given route index: the route file's note for it
+    /// if it has one, or a description of the event if not
+    fn event_name(&self, route_index: i64) -> Option<String> {
+        let index = usize::try_from(route_index).ok()?;
+        let event = self.splits?.get(index)?;
+        Some(match self.split_entries.get(index).and_then(|entry| entry.note.as_ref()) {
+            Some(note) => note.clone(),
+            None => event.to_string(),
+        })
+    }
+
+    fn route_action(&self, route_index: i64) -> RouteAction {
+        usize::try_from(route_index).ok().and_then(|index| self.split_entries.get(index)).map_or(RouteAction::Split, |entry| entry.action)
+    }
+
+    /// Where the player is in the route, given LiveSplit's split index. Route events without a
+    /// segment in LiveSplit don't move the split index, so they're counted separately.
+    fn route_index(&self, split_index: i64) -> i64 {
+        if split_index < 0 {
+            return split_index;
+        }
+
+        self.segment_start(split_index) + self.checkpoints_passed
+    }
+
+    /// The first route event for LiveSplit's split index, not counting any events without a
+    /// segment that the player has passed since
+    fn segment_start(&self, split_index: i64) -> i64 {
+        let mut segments = 0;
+        for (index, entry) in self.split_entries.iter().enumerate() {
+            if segments == split_index {
+                return index as i64;
+            }
+            if entry.action.has_segment() {
+                segments += 1;
+            }
+        }
+
+        // anything past the end of the route's entries is a split
+        self.split_entries.len() as i64 + split_index - segments
+    }
+
+    /// Number of LiveSplit segments before the given route event
+    fn segments_before(&self, route_index: usize) -> i64 {
+        let entries = &self.split_entries[..route_index.min(self.split_entries.len())];
+        let segments = entries.iter().filter(|entry| entry.action.has_segment()).count();
+        (segments + route_index.saturating_sub(self.split_entries.len())) as i64
+    }
+
+    /// Skip or unsplit in LiveSplit so that the next route event is the one at the given index
+    fn move_to_route_index(&mut self, route_index: usize) -> Result<()> {
+        let target = self.segments_before(route_index);
+        let split_index = self.live_split.get_split_index()?;
+        for _ in split_index..target {
+            self.live_split.skip_split()?;
+        }
+        for _ in target..split_index {
+            self.live_split.unsplit()?;
+        }
+
+        self.last_split_index = target;
+        // events without a segment between the start of the target segment and the route index
+        // have already been passed
+        self.checkpoints_passed = route_index as i64 - self.segment_start(target);
+        Ok(())
+    }
+
+    /// Do whatever the route says to do for the route event the player just reached
+    fn take_route_action(&mut self, route_index: i64) -> Result<()> {
+        let action = self.route_action(route_index);
+        if action != RouteAction::Split && let Some(name) = self.event_name(route_index) {
+            log::info!("Reached {name} ({})", action.as_str());
+        }
+
+        match action {
+            RouteAction::Split => return self.split(SplitReason::RouteEvent),
+            RouteAction::Skip => {
+                self.live_split.skip_split()?;
+                self.last_split_index += 1;
+                self.checkpoints_passed = 0;
+                return Ok(());
+            }
+            // in IGT mode, game time only moves when the in-game timer does
+            RouteAction::PauseGameTime | RouteAction::ResumeGameTime if self.igt.enabled => (),
+            RouteAction::PauseGameTime => self.live_split.pause_game_time()?,
+            RouteAction::ResumeGameTime => self.live_split.resume_game_time()?,
+            RouteAction::Log => (),
+        }
+
+        self.checkpoints_passed += 1;
+        Ok(())
+    }
+
+    /// Undo the last door split because the player went right back through the door
+    fn unsplit(&mut self) -> Result<()> {
+        self.live_split.unsplit()?;
+        self.last_split_index -= 1;
+        self.summary.stats.unsplit();
+        self.backtrack.room = None;
+
+        let (map, room) = self.current_room();
+        self.output.emit(OutputEvent::Unsplit { map, room });
+        Ok(())
+    }
+
+    /// Whether the player just went back into the room they left at the last door split
+    fn is_backtrack(&self, room: (u16, u16)) -> bool {
+        self.backtrack.enabled
+            && self.splits.is_none()
+            && self.backtrack.room.is_some_and(|(left, split_at)| left == room && split_at.elapsed() < BACKTRACK_WINDOW)
+    }
+
+    /// Start an individual level run once the player loads a save in the stage being run
+    fn check_il_start(&mut self, stage: Stage) -> Result<()> {
+        if self.game.is_at_main_menu() || self.game.is_new_game_start() {
+            self.il_armed = true;
+            return Ok(());
+        }
+
+        let current_room = self.current_room();
+        if !self.il_armed || Map::from_id(current_room.0).map(|map| map.stage()) != Some(stage) {
+            return Ok(());
+        }
+
+        self.il_armed = false;
+        if self.run_state == RunState::Finished {
+            self.reset()?;
+        }
+        log::info!("Stage {stage:?} run starting");
+        self.split(SplitReason::RunStart)?;
+        self.set_run_state(RunState::Active);
+        self.last_room = current_room;
+
+        // the save might not be right at the start of the stage
+        if let Some(splits) = self.splits
+            && let Some(index) = self.infer_route_index(splits)
+        {
+            self.move_to_route_index(index)?;
         }
-        self.live_split.split()
+
+        Ok(())
+    }
+
+    /// Skip past any splits the runner hasn't done yet so that the next split stops the timer
+    fn skip_to_last_split(&mut self) -> Result<()> {
+        let Some(segment_count) = self.run_info.info().segment_count else {
+            log::warn!("Couldn't get the number of segments from LiveSplit; the timer may not stop");
+            return Ok(());
+        };
+
+        let split_index = self.live_split.get_split_index()?;
+        for _ in split_index..segment_count - 1 {
+            self.live_split.skip_split()?;
+        }
+
+        Ok(())
     }
 
     pub fn reset(&mut self) -> Result<()> {
+        if self.split_only && self.run_state.is_started() {
+            log::debug!("Leaving the timer for the runner to reset");
+            self.set_run_state(RunState::NotStarted);
+            return Ok(());
+        }
+
         if self.run_state.is_started() {
             self.live_split.reset()?;
-            self.run_state = RunState::NotStarted;
+            self.run_info.refresh();
+            self.set_run_state(RunState::NotStarted);
+            self.output.emit(OutputEvent::Reset);
+        }
+
+        Ok(())
+    }
+
+    /// Rewind the splits to the start of the current stage without resetting the timer
+    fn stage_reset(&mut self) -> Result<()> {
+        let split_index = self.live_split.get_split_index()?;
+        for _ in self.stage_start_split_index..split_index {
+            self.live_split.unsplit()?;
+        }
+        self.summary.stats.restart_stage();
+        self.set_run_state(RunState::StageRestart);
+        Ok(())
+    }
+
+    fn stage_reset_target(&self) -> Option<Stage> {
+        if !self.stage_resets || self.run_state != RunState::Active {
+            return None;
+        }
+
+        // there's no point in a partial reset in stage A since that's the start of the run anyway
+        self.current_stage.filter(|stage| *stage != Stage::A)
+    }
+
+    /// Check whether the player has already done a route event, for events that leave a lasting
+    /// mark on the game state. Returns None for events that can't be checked after the fact.
+    fn is_event_done(&self, event: &Event) -> Option<bool> {
+        match event {
+            Event::Flag(stage, flag, _) => Some(self.game.flag(*stage, *flag)),
+            Event::Item(item) => Some(self.game.has_item(*item)),
+            Event::Knock(state) => self.game.knock_state().map(|knock_state| knock_state >= *state),
+            Event::DoorCount(doors) => Some(self.door_count >= *doors),
+            Event::ItemsCollected(items) => Some(self.items_collected.len() >= *items as usize),
+            // a flag that isn't set or an item that isn't held doesn't tell us whether it ever was
+            Event::Room(..)
+            | Event::Rooms(_)
+            | Event::FlagCleared(..)
+            | Event::ItemUsed(_)
+            | Event::BossDefeated(_)
+            | Event::Cutscene(_) => None,
+            Event::All(events) => {
+                let done: Vec<_> = events.iter().map(|event| self.is_event_done(event)).collect();
+                if done.contains(&Some(false)) {
+                    Some(false)
+                } else if done.iter().all(|done| *done == Some(true)) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+            Event::Any(events) => {
+                let done: Vec<_> = events.iter().map(|event| self.is_event_done(event)).collect();
+                if done.contains(&Some(true)) {
+                    Some(true)
+                } else if done.iter().all(|done| *done == Some(false)) {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Work out which split the player is up to from where they are and what they've done so far.
+    /// Rooms often appear in the route more than once, so we go with the visit to the current room
+    /// that best agrees with which flags are set and which items the player has.
+    fn infer_route_index(&self, splits: &[Event]) -> Option<usize> {
+        let current_room = self.current_room();
+        let done: Vec<_> = splits.iter().map(|event| self.is_event_done(event)).collect();
+
+        let index = splits
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| event.matches_room(current_room))
+            .min_by_key(|(i, _)| {
+                let not_done_before = done[..*i].iter().filter(|done| **done == Some(false)).count();
+                let done_after = done[i + 1..].iter().filter(|done| **done == Some(true)).count();
+                not_done_before + done_after
+            })
+            .map(|(i, _)| i)?;
+
+        // we're already in the room, so the next split is the one after it, unless the player has
+        // already done some of what comes after it
+        let skipped = done[index + 1..].iter().take_while(|done| **done == Some(true)).count();
+        Some(index + 1 + skipped)
+    }
+
+    /// Whether returning to the main menu leaves the run waiting for the player to get back into the
+    /// game rather than resetting it
+    fn waits_at_main_menu(&self) -> bool {
+        self.no_auto_reset || (self.save_loads == SaveLoadBehavior::Resume && self.run_state == RunState::Active)
+    }
+
+    /// Continue the run after the player loaded a save or the autosplitter was restarted, moving
+    /// the splits to where the player is now
+    fn resume_run(&mut self) -> Result<()> {
+        let current_room = self.current_room();
+        self.last_room = current_room;
+        self.current_stage = None;
+        self.set_run_state(RunState::Active);
+
+        let Some(splits) = self.splits else {
+            log::info!("Resuming run");
+            return Ok(());
+        };
+        let Some(target_index) = self.infer_route_index(splits) else {
+            log::warn!("Player is not in a room in the route; splits will not line up with the run");
+            return Ok(());
+        };
+
+        log::info!("Resuming run from route event {target_index}");
+        self.move_to_route_index(target_index)
+    }
+
+    /// Keep track of where in the splits the player entered the stage they're currently in
+    fn track_stage(&mut self, map_id: u16) -> Result<()> {
+        let Some(stage) = Map::from_id(map_id).map(|map| map.stage()) else {
+            return Ok(());
+        };
+
+        if self.current_stage != Some(stage) {
+            log::debug!("Entered stage {stage:?}");
+            self.current_stage = Some(stage);
+            self.summary.stats.enter_stage(stage);
+            self.stage_start_split_index = self.live_split.get_split_index()?;
+        }
+
+        Ok(())
+    }
+
+    /// Skip ahead in the splits to the point in the route where the given room is
+    fn skip_to_room(&mut self, room: (u16, u16)) -> Result<()> {
+        let Some(splits) = self.splits else {
+            return Ok(());
+        };
+
+        let Some(index) = splits.iter().position(|event| event.matches_room(room)) else {
+            log::warn!("Starting room is not in the route; splits will not line up with the run");
+            return Ok(());
+        };
+
+        // we're already in the room, so the next split is the one after it
+        self.move_to_route_index(index + 1)
+    }
+
+    fn track_knock(&mut self) {
+        let knock_state = self.game.knock_state();
+        if knock_state != self.knock_state {
+            if let Some(state) = knock_state {
+                log::debug!("Knock state: {state:?}");
+                self.output.emit(OutputEvent::Knock { state: state.as_str() });
+            }
+            self.knock_state = knock_state;
+        }
+    }
+
+    fn track_items(&mut self) {
+        // we can't see the inventory on console
+        if self.game_backend.is_console() {
+            return;
+        }
+
+        for item in Item::all() {
+            if !self.items_collected.contains(&item) && self.game.has_item(item) {
+                self.items_collected.push(item);
+                log::debug!("Collected {item:?} ({} items)", self.items_collected.len());
+            }
+        }
+    }
+
+    /// Find whether the player has picked up a new key item or beaten a new boss since the last
+    /// randomizer split. Files don't count, since they aren't needed to finish the game.
+    fn randomizer_split_reason(&mut self, current_room: (u16, u16)) -> Option<SplitReason> {
+        let (items_split, bosses_split) = &mut self.randomizer_progress;
+        if let Some(item) = self.items_collected.iter().find(|item| !item.is_file() && !items_split.contains(item)) {
+            items_split.push(*item);
+            return Some(SplitReason::ItemAcquired);
+        }
+
+        // we can only tell a boss was beaten by the player getting past them
+        if self.last_room != current_room
+            && let Some((boss, _)) = BOSS_DEFEAT_ROOMS.iter().find(|(boss, event)| event.matches_room(current_room) && !bosses_split.contains(boss))
+        {
+            log::debug!("{boss:?} defeated");
+            bosses_split.push(*boss);
+            return Some(SplitReason::BossDefeated);
+        }
+
+        None
+    }
+
+    /// Items the player still needs to pick up for the run to count as 100%
+    fn missing_items(&self) -> Vec<Item> {
+        Item::all().filter(|item| !self.items_collected.contains(item)).collect()
+    }
+
+    /// Hand LiveSplit's game time over to the in-game timer for the run that just started. Game
+    /// time stays paused in LiveSplit so it doesn't drift between syncs.
+    fn start_igt_mode(&mut self) -> Result<()> {
+        self.live_split.init_game_time()?;
+        self.live_split.pause_game_time()?;
+        self.sync_game_time()
+    }
+
+    /// When the runner starts the timer themselves, every split we make is late by however long the
+    /// capture takes to show us the game. Make up for it by holding game time back by that much at
+    /// the start of the run, so game time splits land where they would have without the delay.
+    /// When we start the timer, the start is just as late as the splits, so there's nothing to do.
+    fn compensate_capture_latency(&mut self) -> Result<()> {
+        if !self.split_only || self.igt.enabled || self.latency.capture_latency.is_zero() {
+            return Ok(());
+        }
+
+        if self.live_split.get_timer_phase()? == TimerPhase::NotRunning {
+            self.latency.is_compensated = false;
+            self.latency.hold_until = None;
+        } else if !self.latency.is_compensated {
+            log::debug!("Holding game time back {} ms for capture latency", self.latency.capture_latency.as_millis());
+            self.live_split.init_game_time()?;
+            self.live_split.pause_game_time()?;
+            self.latency.is_compensated = true;
+            self.latency.hold_until = Some(Instant::now() + self.latency.capture_latency);
+        } else if self.latency.hold_until.is_some_and(|until| Instant::now() >= until) {
+            self.live_split.resume_game_time()?;
+            self.latency.hold_until = None;
         }
 
         Ok(())
     }
 
+    /// Copy the in-game time to LiveSplit's game time, if the game can tell us what it is
+    fn sync_game_time(&mut self) -> Result<()> {
+        let Some(igt) = self.game.igt() else {
+            return Ok(());
+        };
+
+        self.live_split.set_game_time(igt)
+    }
+
+    /// Report how close recent capture frames came to matching each image we're looking for
+    fn report_match_scores(&self) {
+        let mut best_scores: Vec<MatchScore> = Vec::new();
+        for score in self.game.match_score_history().into_iter().flatten() {
+            match best_scores.iter_mut().find(|best| best.target == score.target) {
+                Some(best) => best.score = best.score.max(score.score),
+                None => best_scores.push(score),
+            }
+        }
+
+        if !best_scores.is_empty() {
+            self.output.emit(OutputEvent::MatchScores { scores: best_scores });
+        }
+    }
+
+    fn report_run_summary(&mut self) {
+        // older servers don't have getfinaltime, but the current time is the final time once the
+        // run is over
+        let final_time = match self.live_split.get_final_time() {
+            Ok(Some(final_time)) => Ok(final_time),
+            _ => self.live_split.get_current_time(),
+        };
+        let final_time = match final_time {
+            Ok(final_time) => Some(final_time),
+            Err(e) => {
+                log::debug!("Failed to get final time from LiveSplit: {e}");
+                None
+            }
+        };
+
+        let summary = self.summary.stats.finish(final_time, self.game.load_time(), self.game.igt());
+        summary.log();
+        if let Some(run_report_dir) = &self.summary.report_dir {
+            match summary.save(run_report_dir) {
+                Ok(path) => log::info!("Saved run report to {}", path.display()),
+                Err(e) => log::warn!("Failed to save run report: {e}"),
+            }
+        }
+        self.output.emit(OutputEvent::RunSummary { summary });
+    }
+
     fn conn_fail(&mut self, new_state: ConnectionState) -> Result<()> {
-        self.connection_state = new_state;
+        self.set_connection_state(new_state);
 
         if self.connection_state == ConnectionState::GamePending {
             log::warn!("Lost game; resetting and waiting for a recognized game to be loaded...");
@@ -246,16 +1267,74 @@ impl AutoSplitter {
 
     fn set_split_type(&mut self, split_type: SplitType) {
         self.effective_split_type = Some(split_type);
-        self.splits = split_type.splits();
+        self.apply_route(split_type);
+        self.output.emit(OutputEvent::SplitType { split_type: split_type.as_str() });
+    }
+
+    /// Pick the route to split on for the split type, the route file and the branches taken in it,
+    /// and the stage being run
+    fn apply_route(&mut self, split_type: SplitType) {
+        let (route, entries) = match &mut self.custom_route {
+            Some(custom_route) => {
+                let (route, entries) = custom_route.route(&self.branch_choices);
+                (Some(route), entries)
+            }
+            None => (split_type.splits(), &[][..]),
+        };
+        let range = match (route, self.il_stage) {
+            (Some(route), Some(stage)) => stage_range(route, stage),
+            (Some(route), None) => 0..route.len(),
+            (None, _) => 0..0,
+        };
+        self.route_offset = range.start;
+        self.splits = route.map(|route| &route[range.clone()]);
+        self.split_entries = entries.get(range).unwrap_or(&[]);
+    }
+
+    /// If the player is at one of the route file's branches and has started down one of the
+    /// alternative paths instead of the main one, switch to that path for the rest of the run
+    fn take_branch(&mut self, route_index: i64) {
+        let Some(custom_route) = &self.custom_route else {
+            return;
+        };
+        let position = route_index + self.route_offset as i64;
+        let Some(branch_index) = custom_route.branch_starts(&self.branch_choices).iter().position(|start| *start as i64 == position) else {
+            return;
+        };
+        if self.branch_choices[branch_index] != 0 {
+            return;
+        }
+        let first_events: Vec<_> = custom_route.branches()[branch_index]
+            .alternatives
+            .iter()
+            .map(|alternative| alternative.first().map(|entry| entry.event))
+            .collect();
+
+        let main_event = self.splits.and_then(|splits| splits.get(usize::try_from(route_index).ok()?).copied());
+        if main_event.is_some_and(|event| self.is_event_seen(&event)) {
+            return;
+        }
+        for (i, event) in first_events.iter().enumerate() {
+            if event.is_some_and(|event| self.is_event_seen(&event)) {
+                log::info!("Player took alternative {} at branch {}", i + 1, branch_index + 1);
+                self.branch_choices[branch_index] = i + 1;
+                if let Some(split_type) = self.effective_split_type {
+                    self.apply_route(split_type);
+                }
+                self.event_streak = (-1, 0);
+                return;
+            }
+        }
     }
 
     fn set_run_category(&mut self, run_category: RunCategory) {
         self.effective_run_category = Some(run_category);
         self.game.set_run_category(run_category);
+        self.output.emit(OutputEvent::RunCategory { run_category: run_category.as_str() });
     }
     
     fn get_live_split_split_type(&mut self) -> Result<Option<SplitType>> {
-        get_live_split_split_type(&mut self.live_split)
+        get_live_split_split_type(self.live_split.as_mut())
     }
     
     fn sync_split_type(&mut self) -> Result<()> {
@@ -263,8 +1342,13 @@ impl AutoSplitter {
 
         match (self.requested_split_type, self.effective_split_type, live_split_split_type) {
             (None, None, None) => {
-                log::warn!("No split type was specified by either the user or the splits; defaulting to all-doors");
-                self.set_split_type(SplitType::AllDoors);
+                match infer_live_split_split_type(self.live_split.as_mut())? {
+                    Some(split_type) => self.set_split_type(split_type),
+                    None => {
+                        log::warn!("No split type was specified by either the user or the splits; defaulting to all-doors");
+                        self.set_split_type(SplitType::AllDoors);
+                    }
+                }
             }
             (None, None, Some(split_type)) => {
                 log::info!("Split type {} detected from LiveSplit splits", split_type.as_str());
@@ -315,7 +1399,7 @@ impl AutoSplitter {
     }
 
     fn sync_run_category(&mut self) -> Result<()> {
-        let live_split_run_category = get_live_split_run_category(&mut self.live_split)?;
+        let live_split_run_category = get_live_split_run_category(self.live_split.as_mut())?;
 
         match (self.requested_run_category, self.effective_run_category, live_split_run_category) {
             (None, None, None) => {
@@ -369,25 +1453,154 @@ impl AutoSplitter {
         Ok(())
     }
 
+    /// Fill in LiveSplit's game name, category name, and custom variables if they don't match the
+    /// split type and run category we're using, so a fresh LiveSplit with blank splits ends up
+    /// with the right metadata. This is only done between runs so it can't disturb a run in
+    /// progress.
+    fn sync_run_metadata(&mut self) -> Result<()> {
+        let Some(split_type) = self.effective_split_type else {
+            return Ok(());
+        };
+        if self.run_state != RunState::NotStarted {
+            return Ok(());
+        }
+
+        let run_category = self.effective_run_category.unwrap_or(RunCategory::AnyPercent);
+        let metadata = (split_type, run_category);
+        if self.published.metadata == Some(metadata)
+            || (self.last_reported_split_type == Some(split_type) && self.last_reported_run_category == Some(run_category)) {
+            return Ok(());
+        }
+
+        log::info!("Setting LiveSplit's run metadata to {} ({})", run_category.as_str(), split_type.as_str());
+        self.live_split.set_game_name(GAME_NAME)?;
+        self.live_split.set_category_name(run_category.as_str())?;
+        self.live_split.set_custom_variable(SPLIT_TYPE_VARIABLE_NAME, split_type.as_str())?;
+        self.live_split.set_custom_variable(RUN_CATEGORY_VARIABLE_NAME, run_category.as_str())?;
+        // if the server ignores any of these, we'll keep seeing the old values, so don't try again
+        // until the split type or category changes
+        self.published.metadata = Some(metadata);
+
+        Ok(())
+    }
+
     fn sync_with_live_split(&mut self) -> Result<()> {
-        self.run_state = match self.live_split.get_timer_phase()? {
+        let run_state = match self.live_split.get_timer_phase()? {
             TimerPhase::NotRunning => RunState::NotStarted,
             TimerPhase::Ended => RunState::Finished,
+            // LiveSplit doesn't know anything about our practice state
+            _ if matches!(self.run_state, RunState::StageRestart | RunState::Resuming) => self.run_state,
+            // LiveSplit was already running when we started, so the autosplitter must have been
+            // restarted in the middle of a run. that's true even if nothing has been split yet.
+            _ if self.run_state == RunState::NotStarted => {
+                log::info!("LiveSplit already has a run in progress; picking it up from wherever the player is");
+                self.summary.stats.start();
+                RunState::Resuming
+            }
             _ => if self.run_state != RunState::Active && self.live_split.get_split_index()? == 0 {
                 RunState::Intro
             } else {
                 RunState::Active
             },
         };
+        self.set_run_state(run_state);
 
         self.sync_split_type()?;
         self.sync_run_category()?;
+        self.sync_run_metadata()?;
+        self.check_segments()?;
+
+        Ok(())
+    }
+
+    /// Number of segments LiveSplit's splits should have for the current route, if it's known
+    fn expected_segment_count(&self) -> Option<usize> {
+        if self.custom_route.is_some() || self.il_stage.is_some() {
+            // the last split of the run is on defeating the final boss or reaching the next stage,
+            // which isn't in the route
+            self.splits.map(|route| self.segments_before(route.len()) as usize + 1)
+        } else {
+            self.effective_split_type.and_then(|split_type| split_type.segment_count())
+        }
+    }
+
+    /// Warn if LiveSplit's splits don't line up with the route. Otherwise, every split after the
+    /// point where they differ would be on the wrong segment without anything to say so.
+    fn check_segments(&mut self) -> Result<()> {
+        let Some(segment_count) = self.live_split.get_segment_count()? else {
+            return Ok(());
+        };
+        let expected = self.expected_segment_count();
+        if self.checked_segments == Some((segment_count, expected)) {
+            return Ok(());
+        }
+        self.checked_segments = Some((segment_count, expected));
+        self.segment_mismatch = None;
+
+        let Some(expected) = expected else {
+            return Ok(());
+        };
+        if segment_count != expected as i64 {
+            log::warn!("LiveSplit has {segment_count} segments, but the route has {expected}. Your splits won't line up with the route!");
+            if self.strict_segments {
+                log::warn!("Splitting will stop if the route gets past the last segment in LiveSplit");
+            }
+            self.segment_mismatch = Some(segment_count);
+            return Ok(());
+        }
+
+        self.check_segment_names()
+    }
+
+    /// Warn if the names of LiveSplit's segments match the route better when shifted a few
+    /// segments, which means a segment is missing or extra somewhere along the way
+    fn check_segment_names(&mut self) -> Result<()> {
+        let (Some(route), None) = (self.splits, self.il_stage) else {
+            return Ok(());
+        };
+        let Some(names) = self.live_split.get_split_names()? else {
+            return Ok(());
+        };
+
+        let names: Vec<_> = names.iter().map(|name| plain_segment_name(name)).collect();
+        let route_names: Vec<_> = segment_names(route, self.split_entries).iter().map(|name| plain_segment_name(name)).collect();
+        let matches_shifted_by = |shift: usize| names.iter().skip(shift).zip(&route_names).filter(|(name, route_name)| name == route_name).count();
+        let matches_shifted_back = |shift: usize| names.iter().zip(route_names.iter().skip(shift)).filter(|(name, route_name)| name == route_name).count();
+
+        let aligned = matches_shifted_by(0);
+        for shift in 1..=MAX_SEGMENT_DRIFT {
+            if matches_shifted_by(shift) > aligned {
+                log::warn!("LiveSplit's segment names match the route better {shift} segment(s) later. Your splits may have extra segments!");
+                return Ok(());
+            }
+            if matches_shifted_back(shift) > aligned {
+                log::warn!("LiveSplit's segment names match the route better {shift} segment(s) earlier. Your splits may be missing segments!");
+                return Ok(());
+            }
+        }
 
         Ok(())
     }
 
+    /// Ping LiveSplit if it's been a while since we last did, so we find out right away if it's
+    /// gone instead of the next time we need something from it. Returns whether it's still there.
+    fn check_heartbeat(&mut self) -> bool {
+        if self.last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+            self.last_heartbeat = Instant::now();
+            if let Err(e) = self.live_split.ping() {
+                log::debug!("LiveSplit heartbeat failed: {e}");
+            }
+        }
+
+        self.live_split.is_connected()
+    }
+
     pub fn update(&mut self) -> Result<()> {
         loop {
+            if self.connection_state != ConnectionState::LiveSplitPending && !self.check_heartbeat() {
+                self.conn_fail(ConnectionState::LiveSplitPending)?;
+            }
+
             match self.connection_state {
                 ConnectionState::LiveSplitPending => self.wait_for_live_split(),
                 ConnectionState::GamePending => self.reconnect_game()?,
@@ -398,10 +1611,45 @@ impl AutoSplitter {
         }
     }
 
+    /// Check that everything is set up properly for a run
+    fn checklist_problems(&self) -> Vec<String> {
+        let mut problems = self.game.setup_problems();
+
+        let segment_count = self.run_info.info().segment_count;
+        if let (Some(segment_count), Some(expected)) = (segment_count, self.expected_segment_count())
+            && segment_count != expected as i64
+        {
+            problems.push(format!("LiveSplit has {segment_count} segments, but the route has {expected}"));
+        }
+
+        problems
+    }
+
+    /// Put a run that was in progress when LiveSplit went away back into LiveSplit. The server
+    /// has no way to set the time of a split, so the timer restarts from zero, but the splits pick
+    /// up where they left off.
+    fn restore_live_split_run(&mut self) -> Result<()> {
+        if !self.restore_run || self.split_only || !self.run_state.is_started() || self.run_state == RunState::Finished {
+            return Ok(());
+        }
+
+        if self.live_split.get_timer_phase()? != TimerPhase::NotRunning {
+            return Ok(());
+        }
+
+        log::info!("Restoring run in progress at split {}", self.last_split_index);
+        self.live_split.split()?;
+        for _ in 0..self.last_split_index {
+            self.live_split.skip_split()?;
+        }
+
+        Ok(())
+    }
+
     fn wait_for_live_split(&mut self) {
         if self.live_split.try_reconnect().is_ok() && self.live_split.is_connected() {
             // now that we're reconnected, sync up
-            if let Err(e) = self.sync_with_live_split() {
+            if let Err(e) = self.restore_live_split_run().and_then(|_| self.sync_with_live_split()) {
                 if !self.live_split.is_connected() {
                     // the connection failed again. do not advance.
                     return;
@@ -412,31 +1660,165 @@ impl AutoSplitter {
                 log::warn!("Failed to sync with LiveSplit: {e}. Attempting to continue anyway.");
             }
 
-            self.live_split_keep_alive.reset();
-            self.connection_state.advance();
+            self.live_split_sync.reset();
+            self.last_heartbeat = Instant::now();
+            self.run_info.refresh();
+            // LiveSplit may have been restarted and forgotten everything
+            self.published.variables.clear();
+            self.published.metadata = None;
+            self.set_connection_state(self.connection_state.next());
+        }
+    }
+
+    /// If the splits in LiveSplit are for the other kind of run than the one we're watching,
+    /// switch to watching that kind of run instead
+    fn swap_game_if_needed(&mut self) -> Result<()> {
+        // if the user told us the split type or the backend, it can't change
+        if self.requested_split_type.is_some() || self.is_backend_requested {
+            return Ok(());
+        }
+
+        let Some(is_console) = live_split_is_console(self.live_split.as_mut())? else {
+            return Ok(());
+        };
+        if is_console == self.game_backend.is_console() {
+            return Ok(());
         }
+
+        let game_backend = GameBackendType::for_run(is_console);
+        let game = match game_backend.connect(&self.backend_options, &self.platform, false) {
+            Ok(game) => game,
+            Err(e) if !is_console => {
+                // no emulator yet; we'll try again next time
+                log::debug!("Can't switch to emulator yet: {e}");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let run_type = if is_console { "console" } else { "emulator" };
+        log::info!("LiveSplit has {run_type} splits loaded; switching to the {} backend", game_backend.as_str());
+
+        self.game = game;
+        self.game_backend = game_backend;
+        // make sure we pick up the new split type right away
+        self.live_split_sync = KeepAliveCounter::new(LIVE_SPLIT_SYNC_PERIOD).with_trigger_on_start();
+        Ok(())
     }
 
     fn reconnect_game(&mut self) -> Result<()> {
-        self.game.reconnect(&self.platform)?;
-        self.connection_state.advance();
+        if self.hot_swap
+            && let Err(e) = self.swap_game_if_needed()
+        {
+            log::warn!("Failed to switch between emulator and console: {e}");
+        }
+
+        if let Err(e) = self.game.reconnect(&self.platform) {
+            // not back yet; we'll try again after a delay
+            log::debug!("Game not available: {e}");
+            return Ok(());
+        }
+
+        self.set_connection_state(self.connection_state.next());
         Ok(())
     }
 
-    fn check_split_event(&mut self, split_index: i64) -> Result<bool> {
-        let Some(event) = self.splits.and_then(|s| s.get(split_index as usize)) else {
-            return Ok(false);
-        };
+    /// Check whether the boss's HP has hit zero. Boss HP may read as zero before the fight starts,
+    /// so it only counts once we've seen the boss with HP left.
+    fn is_boss_defeated(&mut self, boss: Boss) -> bool {
+        match self.game.boss_health(boss) {
+            Some(0) => self.boss_seen_alive == Some(boss),
+            Some(_) => {
+                self.boss_seen_alive = Some(boss);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Check whether the flag has gone from set to cleared
+    fn is_flag_cleared(&mut self, stage: Stage, flag: u32) -> bool {
+        if self.game.flag(stage, flag) {
+            self.flag_seen_set = Some((stage, flag));
+            false
+        } else {
+            self.flag_seen_set == Some((stage, flag))
+        }
+    }
+
+    /// Check whether the item has gone from being in the inventory to not being there
+    fn is_item_used(&mut self, item: Item) -> bool {
+        if self.game.has_item(item) {
+            self.item_seen_held = Some(item);
+            false
+        } else {
+            self.item_seen_held == Some(item)
+        }
+    }
 
-        Ok(match event {
-            Event::Room(map, room) => (*map as u16, *room) == self.current_room(),
-            Event::Room2((map1, room1), (map2, room2)) => {
-                let current_room = self.current_room();
-                (*map1 as u16, *room1) == current_room || (*map2 as u16, *room2) == current_room
+    /// If the player went off the route and came back to it further along, find how many route
+    /// events they missed. We only look a few doors ahead, and only past events that we can tell
+    /// the player has already done, so that a room that comes up again later in the route isn't
+    /// mistaken for the player jumping ahead.
+    fn missed_route_events(&self, route_index: i64, room: (u16, u16)) -> Option<usize> {
+        let splits = self.splits?;
+        let upcoming = splits.get(usize::try_from(route_index).ok()?..)?;
+
+        for (missed, event) in upcoming.iter().enumerate().skip(1).take(MAX_MISSED_ROUTE_EVENTS) {
+            match event {
+                Event::Room(..) | Event::Rooms(_) if event.matches_room(room) => return Some(missed),
+                Event::Room(..) | Event::Rooms(_) => (),
+                _ if self.is_event_done(event) == Some(true) => (),
+                _ => return None,
             }
-            Event::Flag(stage, flag) => self.game.flag(*stage, *flag),
+        }
+
+        None
+    }
+
+    fn is_event_seen(&mut self, event: &Event) -> bool {
+        match event {
+            Event::Room(..) | Event::Rooms(_) => event.matches_room(self.current_room()),
+            Event::Flag(stage, flag, _) => self.game.flag(*stage, *flag),
+            Event::FlagCleared(stage, flag) => self.is_flag_cleared(*stage, *flag),
             Event::Item(item) => self.game.has_item(*item),
-        })
+            Event::ItemUsed(item) => self.is_item_used(*item),
+            Event::Knock(state) => self.game.knock_state().is_some_and(|knock_state| knock_state >= *state),
+            Event::BossDefeated(boss) => self.is_boss_defeated(*boss),
+            Event::Cutscene(id) => self.game.cutscene_id() == Some(*id),
+            Event::DoorCount(doors) => self.door_count >= *doors,
+            Event::ItemsCollected(items) => self.items_collected.len() >= *items as usize,
+            // check every event, even once the answer is known, so that boss HP keeps being tracked
+            Event::All(events) => {
+                let seen: Vec<_> = events.iter().map(|event| self.is_event_seen(event)).collect();
+                seen.into_iter().all(|seen| seen)
+            }
+            Event::Any(events) => {
+                let seen: Vec<_> = events.iter().map(|event| self.is_event_seen(event)).collect();
+                seen.into_iter().any(|seen| seen)
+            }
+        }
+    }
+
+    fn check_split_event(&mut self, route_index: i64) -> Result<bool> {
+        let Some(event) = self.splits.and_then(|s| s.get(route_index as usize)) else {
+            return Ok(false);
+        };
+
+        let is_seen = self.is_event_seen(event);
+
+        // some events flicker before they settle, so they have to be seen several updates in a row
+        let (streak_index, streak) = self.event_streak;
+        let streak = match (is_seen, streak_index == route_index) {
+            (false, _) => 0,
+            (true, true) => streak.saturating_add(1),
+            (true, false) => 1,
+        };
+        if streak == 1 {
+            self.event_first_seen = self.update_started;
+        }
+        self.event_streak = (route_index, streak);
+
+        Ok(streak >= event.required_updates())
     }
 
     fn update_splits(&mut self) -> Result<()> {
@@ -449,8 +1831,8 @@ impl AutoSplitter {
     }
 
     fn update_splits_inner(&mut self) -> Result<()> {
-        if self.live_split_keep_alive.should_check() {
-            // make sure the LiveSplit connection is still good and our run state is in sync with theirs
+        if self.live_split_sync.should_check() {
+            // make sure our run state is in sync with theirs
             if self.sync_with_live_split().is_err() && !self.live_split.is_connected() {
                 // we lost the LiveSplit connection
                 return self.conn_fail(ConnectionState::LiveSplitPending);
@@ -458,8 +1840,15 @@ impl AutoSplitter {
         }
 
         let split_index = self.live_split.get_split_index()?;
-        let route_hint = if split_index >= 0 {
-            self.splits.and_then(|s| s.get(split_index as usize))
+        if split_index != self.last_split_index {
+            // the runner split, skipped, or undid a split themselves
+            self.checkpoints_passed = 0;
+        }
+        self.last_split_index = split_index;
+        let route_index = self.route_index(split_index);
+        self.update_started = Instant::now();
+        let route_hint = if route_index >= 0 {
+            self.splits.and_then(|s| s.get(route_index as usize))
         } else {
             None
         };
@@ -471,6 +1860,7 @@ impl AutoSplitter {
                 // if we just changed to a different game, any run we had in progress is no longer
                 // meaningful, so reset
                 log::info!("Game version changed; resetting");
+                self.output.emit(OutputEvent::GameChanged);
                 return self.reset();
             }
             GameState::Disconnected => {
@@ -479,17 +1869,100 @@ impl AutoSplitter {
             }
         }
 
-        if self.run_state.is_active() && self.game.is_at_main_menu() {
+        if self.match_score_report.should_check() {
+            self.report_match_scores();
+        }
+        self.publish_progress(route_index)?;
+        self.compensate_capture_latency()?;
+
+        if self.run_state == RunState::Resuming && !self.game.is_at_main_menu() && !self.game.is_new_game_start() {
+            return self.resume_run();
+        }
+
+        if self.run_state == RunState::StageRestart {
+            // wait until the player is out of the menu and back in the game
+            if self.game.is_at_main_menu() || self.game.is_new_game_start() {
+                return Ok(());
+            }
+
+            let current_room = self.current_room();
+            return match Map::from_id(current_room.0).map(|map| map.stage()) {
+                Some(stage) if self.current_stage == Some(stage) => {
+                    log::info!("Resuming run from the start of stage {stage:?}");
+                    self.last_room = current_room;
+                    self.set_run_state(RunState::Active);
+                    Ok(())
+                }
+                _ if self.no_auto_reset => {
+                    log::info!("Player did not return to the stage they left; resuming from where they are");
+                    self.resume_run()
+                }
+                _ => {
+                    log::info!("Player did not return to the stage they left; resetting");
+                    self.reset()
+                }
+            };
+        } else if self.run_state.is_active() && self.game.is_at_main_menu() {
+            if let Some(stage) = self.stage_reset_target() {
+                log::info!("Returned to main menu; rewinding to the start of stage {stage:?}");
+                return self.stage_reset();
+            }
+
+            if self.waits_at_main_menu() {
+                log::info!("Returned to main menu; waiting for a save to be loaded");
+                self.set_run_state(RunState::Resuming);
+                return Ok(());
+            }
+
             // we died or reset; the run is over
             log::info!("Reset");
             return self.reset();
-        } else if !self.run_state.is_active() && self.game.is_new_game_start() {
+        }
+
+        if !self.game.is_new_game_start() {
+            self.refused_start = false;
+        }
+
+        let il_stage = self.il_stage.filter(|stage| *stage != Stage::A);
+        if il_stage.is_none() && !self.run_state.is_active() && self.game.is_new_game_start() {
+            if self.refused_start {
+                return Ok(());
+            }
+
+            if self.checklist {
+                let problems = self.checklist_problems();
+                if !problems.is_empty() {
+                    for problem in &problems {
+                        log::warn!("{problem}");
+                    }
+                    log::warn!("Not starting the run. Fix these problems and start the game again.");
+                    self.refused_start = true;
+                    return Ok(());
+                }
+            }
+
             // a new run has been started
-            if self.run_state == RunState::Finished {
+            if matches!(self.run_state, RunState::Finished | RunState::Resuming) {
                 self.reset()?;
             }
-            log::info!("Run starting");
-            return self.split();
+            let run_info = self.run_info.info();
+            match (run_info.attempt_count, run_info.completed_count) {
+                (Some(attempts), Some(completed)) => log::info!("Run starting ({attempts} previous attempts, {completed} completed)"),
+                _ => log::info!("Run starting"),
+            }
+            return self.split(SplitReason::RunStart);
+        } else if let Some(stage) = il_stage && matches!(self.run_state, RunState::NotStarted | RunState::Finished) {
+            return self.check_il_start(stage);
+        } else if self.run_state == RunState::Intro && let Some(start_room) = self.game.start_room() {
+            // the player is starting from a save, so the intro room logic doesn't apply
+            return if self.current_room() == start_room {
+                log::debug!("Player is in starting room");
+                self.set_run_state(RunState::Active);
+                self.last_room = start_room;
+                self.skip_to_room(start_room)
+            } else {
+                Ok(())
+            };
         } else if self.run_state == RunState::Intro {
             // I don't want to rely on the map and room IDs being set to sensible values before the
             // first room is actually loaded. so, immediately after new game start, we won't track
@@ -497,11 +1970,12 @@ impl AutoSplitter {
             // should indicate that they're now progressing normally.
             return if self.current_room() == SECOND_ROOM {
                 log::debug!("Player reached second room");
-                self.run_state = RunState::Active;
+                self.set_run_state(RunState::Active);
                 self.last_room = SECOND_ROOM;
+                self.door_count = 1;
                 // if we're splitting on all doors, split now
                 if self.splits.is_none() {
-                    self.split()
+                    self.split(SplitReason::RoomChange)
                 } else {
                     Ok(())
                 }
@@ -515,27 +1989,90 @@ impl AutoSplitter {
 
         // the run is active, so check for player progression
         let current_room = self.current_room();
+        // rapid room ID changes and duplicate matches during long door animations shouldn't cause
+        // extra splits, so nothing splits for a little while after a room change
+        let is_cooling_down = self.last_room_change.is_some_and(|time| time.elapsed() < self.split_cooldown);
+        if self.last_room != current_room {
+            self.last_room_change = Some(Instant::now());
+            // the cooldown is there to ignore room changes that aren't real doors
+            if !is_cooling_down {
+                self.door_count += 1;
+            }
+        }
+        self.track_knock();
+        self.track_items();
+        if self.igt.should_sync() {
+            self.sync_game_time()?;
+        }
+        if let Some(next_stage) = self.il_stage.and_then(|stage| stage.next())
+            && Map::from_id(current_room.0).is_some_and(|map| map.stage() == next_stage)
+        {
+            self.split(SplitReason::StageEnd)?;
+            self.set_run_state(RunState::Finished);
+            log::info!("Stage run completed!");
+            self.report_run_summary();
+            return Ok(());
+        }
         if self.last_room == FINAL_BOSS_ROOM {
             // if we're in the final boss room, start watching flags to see when the player beats
             // the game. we'll also stop watching for room changes, since there's no way out of
             // here but to win.
             if self.game.has_defeated_final_boss() {
-                self.split()?;
-                self.run_state = RunState::Finished;
+                let missing = self.missing_items();
+                if self.effective_run_category == Some(RunCategory::HundredPercent) && !self.game_backend.is_console() && !missing.is_empty() {
+                    log::warn!("Game completed without collecting everything, so this isn't a 100% run. Missing: {missing:?}");
+                    self.set_run_state(RunState::Finished);
+                    return Ok(());
+                }
+                // make sure the final game time is exact before the timer stops
+                if self.igt.enabled {
+                    self.sync_game_time()?;
+                }
+                self.split(SplitReason::FinalBoss)?;
+                self.set_run_state(RunState::Finished);
                 log::info!("Run completed!");
+                self.report_run_summary();
+            }
+        } else if self.last_room != current_room && self.is_backtrack(current_room) {
+            // this takes priority over the cooldown, since stepping straight back through a door
+            // could easily happen within it
+            log::debug!("Returned to map = {}, room = {}; undoing the last split", current_room.0, current_room.1);
+            self.unsplit()?;
+        } else if is_cooling_down {
+            if self.last_room != current_room {
+                log::debug!("Ignoring room change to map = {}, room = {} during split cooldown", current_room.0, current_room.1);
             }
+        } else if self.start_stop_only {
+            // the runner takes care of everything between the start and the end
         } else if self.splits.is_some() {
-            if self.check_split_event(split_index)? {
-                self.split()?;
+            self.take_branch(route_index);
+            if self.check_split_event(route_index)? {
+                self.take_route_action(route_index)?;
+            } else if self.last_room != current_room && let Some(missed) = self.missed_route_events(route_index, current_room) {
+                let skipped: Vec<_> = (route_index..route_index + missed as i64).filter_map(|index| self.event_name(index)).collect();
+                log::info!("Player rejoined the route {missed} event(s) ahead; skipping the missed splits ({})", skipped.join(", "));
+                let rejoined_index = route_index + missed as i64;
+                self.move_to_route_index(rejoined_index as usize)?;
+                self.event_first_seen = self.update_started;
+                self.take_route_action(rejoined_index)?;
+            }
+        } else if self.effective_split_type == Some(SplitType::Randomizer) {
+            // with items and bosses in a random order, there's no route to follow; anything new is
+            // progress
+            if let Some(reason) = self.randomizer_split_reason(current_room) {
+                self.split(reason)?;
             }
         } else if self.last_room != current_room {
             // player changed rooms; split
             log::debug!("Room change: map = {}, room = {}", self.last_room.0, self.last_room.1);
-            self.split()?;
+            self.split(SplitReason::RoomChange)?;
         }
 
+        if self.last_room != current_room {
+            self.output.emit(OutputEvent::Room { map: current_room.0, room: current_room.1 });
+        }
         self.last_room = current_room;
 
-        Ok(())
+        self.track_stage(current_room.0)
     }
 }
\ No newline at end of file
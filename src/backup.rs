@@ -0,0 +1,154 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+
+const BACKUP_DIR: &str = "backups";
+const MAX_BACKUPS: usize = 20;
+
+fn backup_dir(path: &Path) -> PathBuf {
+    path.parent().unwrap_or(Path::new("")).join(BACKUP_DIR)
+}
+
+/// Split a backup's file name into the original file's name and the backup timestamp
+fn parse_backup_name(backup: &Path) -> Option<(String, u128)> {
+    let stem = backup.file_stem()?.to_str()?;
+    let (original_stem, timestamp) = stem.rsplit_once('-')?;
+    let timestamp = timestamp.parse().ok()?;
+    let original_name = match backup.extension().and_then(OsStr::to_str) {
+        Some(extension) => format!("{original_stem}.{extension}"),
+        None => String::from(original_stem),
+    };
+    Some((original_name, timestamp))
+}
+
+/// Get the backups in the given directory, oldest first
+fn backups_in(dir: &Path) -> Result<Vec<(PathBuf, String, u128)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some((original_name, timestamp)) = parse_backup_name(&path) {
+            backups.push((path, original_name, timestamp));
+        }
+    }
+    backups.sort_by_key(|(_, _, timestamp)| *timestamp);
+
+    Ok(backups)
+}
+
+/// Copy a file into the backup directory next to it before it gets overwritten. Only the most
+/// recent backups of each file are kept.
+pub fn backup_file(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let Some(file_name) = path.file_name().and_then(OsStr::to_str) else {
+        bail!("Can't back up {}", path.display());
+    };
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let backup_name = match file_name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{stem}-{timestamp}.{extension}"),
+        None => format!("{file_name}-{timestamp}"),
+    };
+
+    let dir = backup_dir(path);
+    fs::create_dir_all(&dir)?;
+    fs::copy(path, dir.join(&backup_name))?;
+    log::debug!("Backed up {} as {backup_name}", path.display());
+
+    let backups: Vec<_> = backups_in(&dir)?.into_iter().filter(|(_, original_name, _)| original_name == file_name).collect();
+    if backups.len() > MAX_BACKUPS {
+        for (old_backup, _, _) in &backups[..backups.len() - MAX_BACKUPS] {
+            fs::remove_file(old_backup)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the backups in the backup directory next to the given file
+pub fn list_backups(path: impl AsRef<Path>) -> Result<()> {
+    let dir = backup_dir(path.as_ref());
+    let backups = backups_in(&dir)?;
+    if backups.is_empty() {
+        println!("No backups in {}", dir.display());
+        return Ok(());
+    }
+
+    println!("Backups in {} (oldest first):", dir.display());
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    for (backup, original_name, timestamp) in backups {
+        let minutes_ago = now.saturating_sub(timestamp) / 60_000;
+        println!(
+            "  {} (backup of {original_name}, {minutes_ago} minutes ago)",
+            backup.file_name().map(|name| name.to_string_lossy()).unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Put a backup back in place of the file it was made from. The file being replaced is backed up
+/// first so the restore can be undone.
+pub fn restore_backup(backup: impl AsRef<Path>) -> Result<PathBuf> {
+    let backup = backup.as_ref();
+    // allow just the name of a backup in the default backup directory
+    let backup = if backup.exists() { backup.to_path_buf() } else { Path::new(BACKUP_DIR).join(backup) };
+    let backup = backup.as_path();
+    if !backup.exists() {
+        bail!("Backup {} does not exist", backup.display());
+    }
+    let (original_name, _) = parse_backup_name(backup).ok_or_else(|| anyhow!("{} is not a backup", backup.display()))?;
+    let backup_dir = backup.parent().ok_or_else(|| anyhow!("{} is not in a backup directory", backup.display()))?;
+    let original = backup_dir.parent().unwrap_or(Path::new("")).join(original_name);
+
+    // backing up the original can prune the backup we're restoring if it's the oldest one, so read
+    // it first
+    let contents = fs::read(backup)?;
+    backup_file(&original)?;
+    fs::write(&original, contents)?;
+
+    Ok(original)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_backup_name() {
+        assert_eq!(parse_backup_name(Path::new("backups/route-1700000000000.json")), Some((String::from("route.json"), 1700000000000)));
+        assert_eq!(parse_backup_name(Path::new("my-route-5.json")), Some((String::from("my-route.json"), 5)));
+        assert_eq!(parse_backup_name(Path::new("calibration-42")), Some((String::from("calibration"), 42)));
+        assert_eq!(parse_backup_name(Path::new("route.json")), None);
+        assert_eq!(parse_backup_name(Path::new("my-route.json")), None);
+    }
+
+    #[test]
+    fn test_restore_oldest_backup() {
+        let dir = std::env::temp_dir().join(format!("galerians-backup-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let backups = dir.join(BACKUP_DIR);
+        fs::create_dir_all(&backups).unwrap();
+        let original = dir.join("splits.lss");
+        fs::write(&original, "current").unwrap();
+        for i in 0..MAX_BACKUPS {
+            fs::write(backups.join(format!("splits-{i}.lss")), format!("backup {i}")).unwrap();
+        }
+
+        let restored = restore_backup(backups.join("splits-0.lss")).unwrap();
+
+        assert_eq!(restored, original);
+        assert_eq!(fs::read_to_string(&original).unwrap(), "backup 0");
+        assert_eq!(backups_in(&backups).unwrap().len(), MAX_BACKUPS);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
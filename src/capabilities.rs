@@ -0,0 +1,128 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::SplitType;
+use crate::game::{GameBackendType, supported_game_versions};
+use crate::output::OutputFormat;
+use crate::platform::supported_emulators;
+use crate::timer::TimerBackendType;
+
+/// Bumped whenever a field is removed or changes meaning, so tools can tell whether they understand
+/// the report
+const CAPABILITIES_VERSION: u32 = 1;
+
+/// Event names that can appear in the JSON event stream
+const EVENTS: [&str; 12] = [
+    "connection_state",
+    "run_state",
+    "split_type",
+    "run_category",
+    "game_changed",
+    "split",
+    "unsplit",
+    "room",
+    "knock",
+    "match_scores",
+    "run_summary",
+    "reset",
+];
+
+/// Features other programs might want to check for before relying on them
+const FEATURES: [&str; 10] = [
+    "json_events",
+    "race_host",
+    "race_server",
+    "route_editor",
+    "backups",
+    "hot_swap",
+    "split_screenshots",
+    "igt",
+    "layout_variables",
+    "run_reports",
+];
+
+/// What this build of the autosplitter supports, for companion tools and race organizers to check
+/// compatibility against
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    capabilities_version: u32,
+    version: &'static str,
+    emulators: Vec<&'static str>,
+    game_versions: Vec<&'static str>,
+    split_types: Vec<String>,
+    game_backends: Vec<String>,
+    timer_backends: Vec<String>,
+    output_formats: Vec<String>,
+    events: Vec<&'static str>,
+    features: Vec<&'static str>,
+}
+
+fn value_names<T: ValueEnum>() -> Vec<String> {
+    T::value_variants()
+        .iter()
+        .filter_map(ValueEnum::to_possible_value)
+        .map(|value| String::from(value.get_name()))
+        .collect()
+}
+
+impl Capabilities {
+    pub fn get() -> Self {
+        Self {
+            capabilities_version: CAPABILITIES_VERSION,
+            version: env!("CARGO_PKG_VERSION"),
+            emulators: supported_emulators().to_vec(),
+            game_versions: supported_game_versions().to_vec(),
+            split_types: value_names::<SplitType>(),
+            game_backends: value_names::<GameBackendType>(),
+            timer_backends: value_names::<TimerBackendType>(),
+            output_formats: value_names::<OutputFormat>(),
+            events: EVENTS.to_vec(),
+            features: FEATURES.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{OutputEvent, SplitReason};
+    use crate::summary::RunStats;
+
+    #[test]
+    fn test_events_match_output_events() {
+        let events = [
+            OutputEvent::ConnectionState { state: "connected" },
+            OutputEvent::RunState { state: "active" },
+            OutputEvent::SplitType { split_type: "all-doors" },
+            OutputEvent::RunCategory { run_category: "any%" },
+            OutputEvent::GameChanged,
+            OutputEvent::Split { reason: SplitReason::RoomChange, map: 0, room: 0, name: None },
+            OutputEvent::Unsplit { map: 0, room: 0 },
+            OutputEvent::Room { map: 0, room: 0 },
+            OutputEvent::Knock { state: "learned" },
+            OutputEvent::MatchScores { scores: Vec::new() },
+            OutputEvent::RunSummary { summary: RunStats::default().finish(None, None, None) },
+            OutputEvent::Reset,
+        ];
+        // this stops compiling when an event is added, so it can't be left out of the list above
+        for event in &events {
+            match event {
+                OutputEvent::ConnectionState { .. }
+                | OutputEvent::RunState { .. }
+                | OutputEvent::SplitType { .. }
+                | OutputEvent::RunCategory { .. }
+                | OutputEvent::GameChanged
+                | OutputEvent::Split { .. }
+                | OutputEvent::Unsplit { .. }
+                | OutputEvent::Room { .. }
+                | OutputEvent::Knock { .. }
+                | OutputEvent::MatchScores { .. }
+                | OutputEvent::RunSummary { .. }
+                | OutputEvent::Reset => (),
+            }
+        }
+
+        let names: Vec<_> = events.iter().map(|event| serde_json::to_value(event).unwrap()["event"].as_str().unwrap().to_owned()).collect();
+        assert_eq!(names, EVENTS);
+    }
+}
@@ -1,22 +1,27 @@
 use std::fmt::Debug;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::RunCategory;
+use crate::image::Deinterlace;
 use crate::platform::PlatformRef;
 use crate::splits::Event;
 
 mod console;
-pub use console::ConsoleGame;
+pub use console::{CaptureApi, CaptureSource, ConsoleGame, DeviceMode, FrameSize, ScreenRegion, measure_latency, missing_assets as missing_console_assets, room_links};
 
 mod emulator;
-pub use emulator::EmulatorGame;
+pub use emulator::{EmulatorGame, NUM_FLAGS, can_read_boss_health, can_read_cutscene_id, supported_versions as supported_game_versions};
 
 // silencing "unused" warnings on these enums. even if all the possible values aren't used today,
 // I still want them to be defined here both as a reference and for potential future use.
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize_repr, Deserialize_repr, ValueEnum)]
 #[repr(u32)]
 pub enum Stage {
     A = 0,
@@ -25,6 +30,21 @@ pub enum Stage {
     D = 3,
 }
 
+impl Stage {
+    pub const fn all() -> [Self; 4] {
+        [Self::A, Self::B, Self::C, Self::D]
+    }
+
+    pub const fn next(&self) -> Option<Self> {
+        match self {
+            Self::A => Some(Self::B),
+            Self::B => Some(Self::C),
+            Self::C => Some(Self::D),
+            Self::D => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize_repr, Deserialize_repr)]
 #[repr(u16)]
 pub enum Map {
@@ -39,8 +59,37 @@ pub enum Map {
     MushroomTower = 8,
 }
 
+impl Map {
+    pub const fn from_id(map_id: u16) -> Option<Self> {
+        Some(match map_id {
+            0 => Self::Hospital15F,
+            1 => Self::Hospital14F,
+            2 => Self::Hospital13F,
+            3 => Self::YourHouse1F,
+            4 => Self::YourHouse2F,
+            5 => Self::Hotel1F,
+            6 => Self::Hotel2F,
+            7 => Self::Hotel3F,
+            8 => Self::MushroomTower,
+            _ => return None,
+        })
+    }
+
+    pub const fn stage(&self) -> Stage {
+        match self {
+            Self::Hospital15F | Self::Hospital14F | Self::Hospital13F => Stage::A,
+            Self::YourHouse1F | Self::YourHouse2F => Stage::B,
+            Self::Hotel1F | Self::Hotel2F | Self::Hotel3F => Stage::C,
+            Self::MushroomTower => Stage::D,
+        }
+    }
+}
+
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Number of different key items and files in the game
+pub const NUM_ITEMS: u32 = 41;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
 #[repr(i16)]
 pub enum Item {
     MemoryChip15F = 0,
@@ -86,6 +135,172 @@ pub enum Item {
     DFelon = 40,
 }
 
+impl Item {
+    pub const fn from_id(item_id: i16) -> Option<Self> {
+        Some(match item_id {
+            0 => Self::MemoryChip15F,
+            1 => Self::SecurityCard,
+            2 => Self::Beeject,
+            3 => Self::FreezerRoomKey,
+            4 => Self::PpecStorageKey,
+            5 => Self::Fuse,
+            6 => Self::LiquidExplosive,
+            7 => Self::MemoryChip14F,
+            8 => Self::SecurityCardReformatted,
+            9 => Self::SpecialPpecOfficeKey,
+            10 => Self::MemoryChip13F,
+            11 => Self::TestLabKey,
+            12 => Self::ControlRoomKey,
+            13 => Self::ResearchLabKey,
+            14 => Self::TwoHeadedSnake,
+            15 => Self::TwoHeadedMonkey,
+            16 => Self::TwoHeadedWolf,
+            17 => Self::TwoHeadedEagle,
+            18 => Self::YourHouseMemoryChip,
+            19 => Self::BackdoorKey,
+            20 => Self::DoorKnob,
+            21 => Self::NineBall,
+            22 => Self::MothersRing,
+            23 => Self::FathersRing,
+            24 => Self::LiliasDoll,
+            25 => Self::Metamorphosis,
+            26 => Self::BedroomKey,
+            27 => Self::SecondFloorKey,
+            28 => Self::MedicalStaffNotes,
+            29 => Self::GProjectReport,
+            30 => Self::PhotoOfParents,
+            31 => Self::RionsTestData,
+            32 => Self::DrLemsNotes,
+            33 => Self::NewReplicativeComputerTheory,
+            34 => Self::DrPascallesDiary,
+            35 => Self::LetterFromElsa,
+            36 => Self::Newspaper,
+            37 => Self::ThreeBall,
+            38 => Self::ShedKey,
+            39 => Self::LetterFromLilia,
+            40 => Self::DFelon,
+            _ => return None,
+        })
+    }
+
+    /// The item's name as the game shows it
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::MemoryChip15F => "Memory Chip (15F)",
+            Self::SecurityCard => "Security Card",
+            Self::Beeject => "Beeject",
+            Self::FreezerRoomKey => "Freezer Room Key",
+            Self::PpecStorageKey => "PPEC Storage Key",
+            Self::Fuse => "Fuse",
+            Self::LiquidExplosive => "Liquid Explosive",
+            Self::MemoryChip14F => "Memory Chip (14F)",
+            Self::SecurityCardReformatted => "Security Card (reformatted)",
+            Self::SpecialPpecOfficeKey => "Special PPEC Office Key",
+            Self::MemoryChip13F => "Memory Chip (13F)",
+            Self::TestLabKey => "Test Lab Key",
+            Self::ControlRoomKey => "Control Room Key",
+            Self::ResearchLabKey => "Research Lab Key",
+            Self::TwoHeadedSnake => "Two-Headed Snake",
+            Self::TwoHeadedMonkey => "Two-Headed Monkey",
+            Self::TwoHeadedWolf => "Two-Headed Wolf",
+            Self::TwoHeadedEagle => "Two-Headed Eagle",
+            Self::YourHouseMemoryChip => "Memory Chip (Your House)",
+            Self::BackdoorKey => "Backdoor Key",
+            Self::DoorKnob => "Door Knob",
+            Self::NineBall => "9 Ball",
+            Self::MothersRing => "Mother's Ring",
+            Self::FathersRing => "Father's Ring",
+            Self::LiliasDoll => "Lilia's Doll",
+            Self::Metamorphosis => "Metamorphosis",
+            Self::BedroomKey => "Bedroom Key",
+            Self::SecondFloorKey => "2nd Floor Key",
+            Self::MedicalStaffNotes => "Medical Staff Notes",
+            Self::GProjectReport => "G Project Report",
+            Self::PhotoOfParents => "Photo of Parents",
+            Self::RionsTestData => "Rion's Test Data",
+            Self::DrLemsNotes => "Dr. Lem's Notes",
+            Self::NewReplicativeComputerTheory => "New Replicative Computer Theory",
+            Self::DrPascallesDiary => "Dr. Pascalle's Diary",
+            Self::LetterFromElsa => "Letter from Elsa",
+            Self::Newspaper => "Newspaper",
+            Self::ThreeBall => "3 Ball",
+            Self::ShedKey => "Shed Key",
+            Self::LetterFromLilia => "Letter from Lilia",
+            Self::DFelon => "D-Felon",
+        }
+    }
+
+    /// Whether this is one of the files Rion can read, as opposed to a key item
+    pub const fn is_file(&self) -> bool {
+        matches!(
+            self,
+            Self::MedicalStaffNotes
+                | Self::GProjectReport
+                | Self::RionsTestData
+                | Self::DrLemsNotes
+                | Self::NewReplicativeComputerTheory
+                | Self::DrPascallesDiary
+                | Self::LetterFromElsa
+                | Self::Newspaper
+                | Self::LetterFromLilia
+        )
+    }
+
+    /// Every item in the game, in ID order
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..NUM_ITEMS as i16).filter_map(Self::from_id)
+    }
+}
+
+/// Progress through the secret knock minigame in the hotel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KnockState {
+    NotLearned,
+    Learned,
+    Succeeded,
+}
+
+impl KnockState {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotLearned => "not_learned",
+            Self::Learned => "learned",
+            Self::Succeeded => "succeeded",
+        }
+    }
+}
+
+/// Bosses whose HP we can watch to tell exactly when they're defeated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Boss {
+    Birdman,
+    Rainheart,
+    Rita,
+    Cain,
+    Dorothy,
+}
+
+/// Something a capture frame was compared against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "target", rename_all = "snake_case")]
+pub enum MatchTarget {
+    Room { map: u16, room: u16 },
+    MainMenu,
+    LoadingSave,
+}
+
+/// How closely a capture frame matched something we were looking for
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MatchScore {
+    #[serde(flatten)]
+    pub target: MatchTarget,
+    pub score: f64,
+    /// The score needed to count as a match
+    pub threshold: f64,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GameState {
     Connected,
@@ -97,6 +312,8 @@ pub trait Game: Debug {
     /// Update our information on the game state from the connected game instance
     fn update(&mut self, route_hint: Option<&Event>) -> GameState;
     
+    /// Make one attempt to get the game back after it was lost. Returns an error if it isn't back
+    /// yet.
     fn reconnect(&mut self, platform: &PlatformRef) -> Result<()>;
     
     fn set_run_category(&mut self, new_category: RunCategory);
@@ -104,6 +321,10 @@ pub trait Game: Debug {
     fn is_at_main_menu(&self) -> bool;
 
     fn is_new_game_start(&self) -> bool;
+
+    /// The room the run starts in, if the player is starting somewhere other than the beginning of
+    /// the game
+    fn start_room(&self) -> Option<(u16, u16)>;
     
     // this returns u16 instead of Map because we can't guarantee that there will always be a valid
     // map value in emulator memory
@@ -116,4 +337,131 @@ pub trait Game: Debug {
     fn has_defeated_final_boss(&self) -> bool;
     
     fn has_item(&self, item_id: Item) -> bool;
-}
\ No newline at end of file
+
+    /// The player's progress through the secret knock minigame, if it can be determined
+    fn knock_state(&self) -> Option<KnockState>;
+
+    /// ID of the FMV or scripted cutscene that's currently playing, if any, and if it can be read
+    fn cutscene_id(&self) -> Option<u16> {
+        None
+    }
+
+    /// The in-game time shown on the save screen, if it can be read
+    fn igt(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The given boss's current HP, if it can be read
+    fn boss_health(&self, _boss: Boss) -> Option<u16> {
+        None
+    }
+
+    /// Anything about the game setup that should be fixed before starting a run
+    fn setup_problems(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Time spent loading since the run started, if the game can tell when it's loading
+    fn load_time(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Save a picture of what the game looked like when a split happened, if that's supported and
+    /// enabled
+    fn save_split_screenshot(&self, _split_index: i64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Match scores from the most recent capture frames, oldest first, if the game is tracked by
+    /// matching images
+    fn match_score_history(&self) -> Vec<Vec<MatchScore>> {
+        Vec::new()
+    }
+}
+
+/// Settings for connecting to the game. Each backend only uses the ones that apply to it.
+#[derive(Debug, Clone, Default)]
+pub struct GameBackendOptions {
+    pub capture_device: i32,
+    pub device_mode: DeviceMode,
+    pub deinterlace: Deinterlace,
+    /// Watch recorded footage instead of the capture device
+    pub video_file: Option<PathBuf>,
+    /// Capture this part of the screen instead of the capture device
+    pub screen_region: Option<ScreenRegion>,
+    /// Capture the window with this title instead of the capture device
+    pub capture_window: Option<String>,
+    pub calibration_profile: Option<String>,
+    pub force_calibrate: bool,
+    pub preview_calibration: bool,
+    pub start_room: Option<String>,
+    pub split_screenshots: Option<PathBuf>,
+}
+
+impl GameBackendOptions {
+    /// Where the console backend should get its video from
+    pub fn capture_source(&self) -> CaptureSource {
+        match (&self.video_file, self.screen_region, &self.capture_window) {
+            (Some(path), _, _) => CaptureSource::File(path.clone()),
+            (None, Some(region), _) => CaptureSource::Screen(region),
+            (None, None, Some(title)) => CaptureSource::Window(title.clone()),
+            (None, None, None) => CaptureSource::Device(self.capture_device),
+        }
+    }
+}
+
+/// The ways we know of to watch the game. Supporting a new way of running the game (e.g. a PC port)
+/// means adding it here.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum GameBackendType {
+    /// Read the game's memory from an emulator
+    Emulator,
+    /// Match video capture from a console against reference images
+    Capture,
+}
+
+impl GameBackendType {
+    /// The backend to use for a run if the user didn't ask for a specific one
+    pub const fn for_run(is_console: bool) -> Self {
+        if is_console {
+            Self::Capture
+        } else {
+            Self::Emulator
+        }
+    }
+
+    pub const fn is_console(&self) -> bool {
+        matches!(self, Self::Capture)
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Emulator => "emulator",
+            Self::Capture => "capture",
+        }
+    }
+
+    /// Files this backend needs that are missing or can't be read
+    pub fn missing_assets(&self) -> Vec<String> {
+        match self {
+            Self::Emulator => Vec::new(),
+            Self::Capture => missing_console_assets(),
+        }
+    }
+
+    /// Connect to the game. If wait is true, this blocks until the game is found; otherwise, it
+    /// returns an error if the game isn't available right now.
+    pub fn connect(&self, options: &GameBackendOptions, platform: &PlatformRef, wait: bool) -> Result<Box<dyn Game>> {
+        Ok(match self {
+            Self::Emulator if wait => Box::new(EmulatorGame::connect(platform)),
+            Self::Emulator => match EmulatorGame::try_connect(platform) {
+                Some(game) => Box::new(game),
+                None => bail!("No emulator with the game loaded was found"),
+            },
+            Self::Capture => Box::new(
+                ConsoleGame::connect(options.capture_source(), options.device_mode.clone(), options.deinterlace, options.calibration_profile.as_deref(), options.force_calibrate, options.preview_calibration, options.start_room.as_deref())?
+                    .with_screenshot_dir(options.split_screenshots.clone())
+            ),
+        })
+    }
+}
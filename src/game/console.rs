@@ -1,18 +1,31 @@
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{bail, Result};
-use opencv::core::min as cv_min;
+use clap::ValueEnum;
+use opencv::core::{absdiff, add_weighted_def, mean_def, min as cv_min};
+use opencv::highgui::{destroy_window, imshow, wait_key};
 use opencv::prelude::*;
 use opencv::imgcodecs::{IMREAD_GRAYSCALE, imread};
-use opencv::videoio::VideoCapture;
+use opencv::videoio::{
+    CAP_ANY, CAP_DSHOW, CAP_FFMPEG, CAP_GSTREAMER, CAP_MSMF, CAP_V4L2, CAP_PROP_CONVERT_RGB, CAP_PROP_FOURCC, CAP_PROP_FPS, CAP_PROP_FRAME_HEIGHT,
+    CAP_PROP_FRAME_WIDTH, CAP_PROP_POS_FRAMES, CAP_PROP_POS_MSEC, VideoCapture,
+};
+use serde::{Deserialize, Serialize};
 
-use super::{Game, GameState, Item, Map, Stage};
+use super::{Game, GameState, Item, KnockState, Map, MatchScore, MatchTarget, Stage};
 use crate::RunCategory;
+use crate::backup::backup_file;
 use crate::image::{
     MATCH_THRESHOLD,
-    CaptureImage, CaptureTransform, CaptureTransformJson, MaskImage, MaskedImage, ReferenceImage,
+    AspectRatio, CaptureImage, CaptureTransform, CaptureTransformJson, Deinterlace, MaskImage, MaskedImage, RawFormat, RawLayout, ReferenceImage,
     gray_float, is_fade_out,
 };
 use crate::platform::PlatformRef;
@@ -30,28 +43,436 @@ const MAIN_MENU_MATCH_THRESHOLD: f64 = 0.7;
 const LOADING_SAVE_MATCH_THRESHOLD: f64 = 0.85;
 const MAIN_MENU_FADE_MAX: f64 = 0.05;
 const GAME_END_FADE_MAX: f64 = 0.005;
+const BLACK_SCREEN_MAX: f64 = 0.005;
+const DETECT_START_ROOM: &str = "detect";
+const START_ROOM_CANDIDATES: usize = 5;
+const CALIBRATION_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(250);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(5);
+// how many times to try reopening the capture device before giving up on the run
+const RECONNECT_ATTEMPTS: usize = 5;
+const MATCH_SCORE_HISTORY_LENGTH: usize = 120; // ~2 seconds of 60 FPS capture
+const PREVIEW_WINDOW: &str = "Calibration preview";
+// how long to wait for a key press between frames of the preview, in milliseconds
+const PREVIEW_KEY_DELAY: i32 = 30;
+const KEY_ENTER: [i32; 2] = [10, 13];
+const KEY_ESCAPE: i32 = 27;
+const LATENCY_TAPS: usize = 10;
+// average difference between two frames, out of 255, for the picture to count as having changed
+const LATENCY_CHANGE_MIN: f64 = 20.0;
+// how far either side of a tap to look for the change it was for. taps can be a little early or
+// late, and the capture is usually behind by less than a quarter of a second.
+const LATENCY_TAP_EARLY: Duration = Duration::from_millis(100);
+const LATENCY_TAP_LATE: Duration = Duration::from_millis(500);
+const DRIFT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// below this, something other than drift is probably wrong, like a camera angle we don't have a
+// background for or a cutscene, and shifting the picture around won't help
+const DRIFT_MIN_SCORE: f64 = 0.35;
+const DRIFT_MIN_IMPROVEMENT: f64 = 0.05;
+/// Furthest the picture may be moved from where it was calibrated, in capture pixels, before we
+/// stop following it
+const MAX_DRIFT: i32 = 8;
+/// The name that calibrations for video files are saved under in place of a device index
+const VIDEO_FILE_SETTINGS_KEY: &str = "video";
+/// The name that calibrations for screen and window capture are saved under
+const SCREEN_SETTINGS_KEY: &str = "screen";
+/// The end of every screen capture pipeline, which hands the picture to OpenCV in the format it
+/// expects. Only the latest frame is kept so we don't fall behind the screen.
+const SCREEN_CAPTURE_SINK: &str = "videoconvert ! video/x-raw,format=BGR ! appsink drop=true max-buffers=1";
+
+type BackgroundMap = HashMap<(Map, u16), Vec<(Map, u16, PathBuf, f64)>>;
+type RoomNames = HashMap<String, Vec<(Map, u16)>>;
+type RoomGroups = Vec<Vec<(Map, u16)>>;
+type BackgroundLinks = Vec<BackgroundLink>;
+type RoomLinks = Vec<((Map, u16), (Map, u16))>;
+type NamesByRoom = HashMap<(Map, u16), String>;
+
+/// A transition from one room to another, along with the background of the room it leads to
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BackgroundLink {
+    Default((Map, u16, Map, u16), String),
+    /// A background that needs a different match threshold than usual, because it scores poorly
+    /// even when it's on screen or because it looks too much like something else
+    Threshold((Map, u16, Map, u16), String, f64),
+}
+
+impl BackgroundLink {
+    fn into_parts(self) -> ((Map, u16, Map, u16), String, f64) {
+        match self {
+            Self::Default(rooms, filename) => (rooms, filename, MATCH_THRESHOLD),
+            Self::Threshold(rooms, filename, threshold) => (rooms, filename, threshold),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BackgroundMapJson {
+    Links(BackgroundLinks),
+    Full {
+        links: BackgroundLinks,
+        /// Groups of rooms whose backgrounds are too similar to reliably tell apart
+        #[serde(default)]
+        equivalent_rooms: RoomGroups,
+    },
+}
+
+/// A saved calibration, along with the size of the capture it was made with if we know it
+#[derive(Debug, Clone)]
+struct DeviceSettings {
+    transform: CaptureTransform,
+    capture_size: Option<(i32, i32)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DeviceSettingsJson {
+    #[serde(flatten)]
+    transform: CaptureTransformJson,
+    // calibrations saved before profiles were added don't have this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    capture_size: Option<(i32, i32)>,
+}
+
+/// The OpenCV backend used to talk to a capture device
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum CaptureApi {
+    /// Let OpenCV choose
+    #[default]
+    Auto,
+    /// Video4Linux (Linux)
+    V4l2,
+    /// DirectShow (Windows)
+    Dshow,
+    /// Media Foundation (Windows)
+    Msmf,
+    Gstreamer,
+    Ffmpeg,
+}
+
+impl CaptureApi {
+    const fn api_preference(&self) -> i32 {
+        match self {
+            Self::Auto => CAP_ANY,
+            Self::V4l2 => CAP_V4L2,
+            Self::Dshow => CAP_DSHOW,
+            Self::Msmf => CAP_MSMF,
+            Self::Gstreamer => CAP_GSTREAMER,
+            Self::Ffmpeg => CAP_FFMPEG,
+        }
+    }
+}
+
+/// A capture resolution, given on the command line as WIDTHxHEIGHT
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl FromStr for FrameSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((width, height)) = s.split_once(['x', 'X']) else {
+            bail!("Resolution should be WIDTHxHEIGHT, not {s}");
+        };
+        let (width, height) = (width.trim().parse()?, height.trim().parse()?);
+        if width <= 0 || height <= 0 {
+            bail!("Resolution {s} is empty");
+        }
+        Ok(Self { width, height })
+    }
+}
+
+/// How to open a capture device, for cards whose default mode is a bad one. Anything not given is
+/// left to the driver.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceMode {
+    pub api: CaptureApi,
+    pub frame_size: Option<FrameSize>,
+    pub fps: Option<f64>,
+    /// Pixel format as a FOURCC code, e.g. MJPG or YUYV
+    pub fourcc: Option<String>,
+    /// Take YUV frames as they come from the device and use their luma directly instead of having
+    /// OpenCV convert them to color
+    pub raw_yuv: bool,
+}
+
+impl DeviceMode {
+    /// Ask the device for the requested mode and warn about anything it didn't go along with.
+    /// Returns the layout of the device's frames if they're coming through unconverted.
+    fn apply(&self, capture_device: &mut VideoCapture, source: &CaptureSource) -> Result<Option<RawLayout>> {
+        // some drivers only offer higher resolutions in compressed formats, so the format goes first
+        if let Some(fourcc) = &self.fourcc {
+            let [a, b, c, d] = fourcc_chars(fourcc)?;
+            let code = VideoCapture::fourcc(a, b, c, d)?;
+            if !capture_device.set(CAP_PROP_FOURCC, code as f64)? || capture_device.get(CAP_PROP_FOURCC)? as i32 != code {
+                log::warn!("{source} didn't accept the pixel format {fourcc}");
+            }
+        }
+
+        if let Some(FrameSize { width, height }) = self.frame_size {
+            capture_device.set(CAP_PROP_FRAME_WIDTH, width as f64)?;
+            capture_device.set(CAP_PROP_FRAME_HEIGHT, height as f64)?;
+            let actual_width = capture_device.get(CAP_PROP_FRAME_WIDTH)? as i32;
+            let actual_height = capture_device.get(CAP_PROP_FRAME_HEIGHT)? as i32;
+            if (actual_width, actual_height) != (width, height) {
+                log::warn!("Asked {source} for {width}x{height}, but it's capturing at {actual_width}x{actual_height}");
+            }
+        }
+
+        if let Some(fps) = self.fps {
+            capture_device.set(CAP_PROP_FPS, fps)?;
+            let actual_fps = capture_device.get(CAP_PROP_FPS)?;
+            // some backends can't report the frame rate at all
+            if actual_fps > 0.0 && (actual_fps - fps).abs() > 0.5 {
+                log::warn!("Asked {source} for {fps} FPS, but it's capturing at {actual_fps} FPS");
+            }
+        }
+
+        if self.raw_yuv {
+            Self::use_raw_frames(capture_device, source)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Switch the device to handing over unconverted frames if its pixel format is one we can read.
+    /// If anything about the frames isn't what we expect, the device is switched back.
+    fn use_raw_frames(capture_device: &mut VideoCapture, source: &CaptureSource) -> Result<Option<RawLayout>> {
+        let code = capture_device.get(CAP_PROP_FOURCC)? as u32;
+        let fourcc: String = code.to_le_bytes().iter().map(|byte| char::from(*byte)).collect();
+        let Some(format) = RawFormat::from_fourcc(&fourcc) else {
+            log::warn!("{source} is capturing in {}, which isn't a YUV format we can read; converting frames as usual", fourcc.trim_end_matches('\0'));
+            return Ok(None);
+        };
+
+        if !capture_device.set(CAP_PROP_CONVERT_RGB, 0.0)? {
+            log::warn!("{source} can't hand over unconverted frames; converting frames as usual");
+            return Ok(None);
+        }
+
+        let layout = RawLayout {
+            format,
+            width: capture_device.get(CAP_PROP_FRAME_WIDTH)? as i32,
+            height: capture_device.get(CAP_PROP_FRAME_HEIGHT)? as i32,
+        };
+        let mut frame = Mat::default();
+        capture_device.read(&mut frame)?;
+        if let Err(e) = CaptureImage::from_raw(frame, layout) {
+            log::warn!("Can't read unconverted frames from {source} ({e}); converting frames as usual");
+            capture_device.set(CAP_PROP_CONVERT_RGB, 1.0)?;
+            return Ok(None);
+        }
+
+        log::debug!("Reading {fourcc} frames from {source} without converting them");
+        Ok(Some(layout))
+    }
+}
+
+/// How to turn the frames we read from the capture into capture images
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct FrameFormat {
+    /// The layout of the frames if they're coming from the device unconverted
+    raw_layout: Option<RawLayout>,
+    deinterlace: Deinterlace,
+}
 
-type BackgroundMap = HashMap<(Map, u16), Vec<(Map, u16, PathBuf)>>;
+impl FrameFormat {
+    fn decode(&self, frame: Mat) -> Result<CaptureImage> {
+        let capture_image = match self.raw_layout {
+            Some(layout) => CaptureImage::from_raw(frame, layout)?,
+            None => CaptureImage::new(frame)?,
+        };
+        capture_image.deinterlace(self.deinterlace)
+    }
+}
+
+fn fourcc_chars(fourcc: &str) -> Result<[char; 4]> {
+    let chars: Vec<char> = fourcc.chars().collect();
+    match chars[..] {
+        [a, b, c, d] if chars.iter().all(char::is_ascii) => Ok([a, b, c, d]),
+        _ => bail!("Pixel format should be a four-character code like MJPG, not {fourcc}"),
+    }
+}
+
+/// A rectangle on the screen, given on the command line as X,Y,WIDTH,HEIGHT
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScreenRegion {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
 
-fn load_device_settings() -> Result<HashMap<i32, CaptureTransform>> {
+impl FromStr for ScreenRegion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s.split(',').map(|part| part.trim().parse::<i32>()).collect::<Result<Vec<_>, _>>()?;
+        let [x, y, width, height] = parts[..] else {
+            bail!("Screen region should be X,Y,WIDTH,HEIGHT, not {s}");
+        };
+        if x < 0 || y < 0 || width <= 0 || height <= 0 {
+            bail!("Screen region {s} is outside the screen");
+        }
+        Ok(Self { x, y, width, height })
+    }
+}
+
+/// Where the video we're watching comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureSource {
+    /// A live video capture device with the given index
+    Device(i32),
+    /// Footage recorded earlier, played back at its own frame rate
+    File(PathBuf),
+    /// Part of the screen, e.g. where an emulator's window is
+    Screen(ScreenRegion),
+    /// The window with the given title, e.g. an emulator's
+    Window(String),
+}
+
+impl CaptureSource {
+    pub const fn is_file(&self) -> bool {
+        matches!(self, Self::File(_))
+    }
+}
+
+impl Display for CaptureSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Device(device_index) => write!(f, "video capture device {device_index}"),
+            Self::File(path) => write!(f, "video file {}", path.display()),
+            Self::Screen(ScreenRegion { x, y, width, height }) => write!(f, "screen region {width}x{height} at {x},{y}"),
+            Self::Window(title) => write!(f, "window \"{title}\""),
+        }
+    }
+}
+
+/// The key a calibration is saved under in device.json. Each device has a default calibration
+/// under its index, and named profiles under the index and the profile name, e.g. "0:1080p".
+/// Video files all share the calibrations under "video".
+fn settings_key(source: &CaptureSource, profile: Option<&str>) -> String {
+    let source_key = match source {
+        CaptureSource::Device(device_index) => device_index.to_string(),
+        CaptureSource::File(_) => String::from(VIDEO_FILE_SETTINGS_KEY),
+        CaptureSource::Screen(_) | CaptureSource::Window(_) => String::from(SCREEN_SETTINGS_KEY),
+    };
+    match profile {
+        Some(profile) => format!("{source_key}:{profile}"),
+        None => source_key,
+    }
+}
+
+fn load_device_settings() -> Result<HashMap<String, DeviceSettings>> {
     let path = Path::new(DEVICE_SETTINGS_PATH);
     if !path.exists() {
         return Ok(HashMap::new());
     }
 
     let file = File::open(path)?;
-    let settings: HashMap<i32, CaptureTransformJson> = serde_json::from_reader(file)?;
+    let settings: HashMap<String, DeviceSettingsJson> = serde_json::from_reader(file)?;
 
-    Ok(settings.into_iter().map(|(index, json)| (index, CaptureTransform::from_json(&json))).collect())
+    Ok(settings.into_iter().map(|(key, json)| (key, DeviceSettings {
+        transform: CaptureTransform::from_json(&json.transform),
+        capture_size: json.capture_size,
+    })).collect())
 }
 
-fn save_device_settings(settings: &HashMap<i32, CaptureTransform>) -> Result<()> {
-    let json: HashMap<_, _> = settings.iter().map(|(index, transform)| (*index, transform.for_json())).collect();
+fn save_device_settings(settings: &HashMap<String, DeviceSettings>) -> Result<()> {
+    let json: HashMap<_, _> = settings.iter().map(|(key, settings)| (key, DeviceSettingsJson {
+        transform: settings.transform.for_json(),
+        capture_size: settings.capture_size,
+    })).collect();
+    // keep the old calibration around in case the new one is bad
+    if let Err(e) = backup_file(DEVICE_SETTINGS_PATH) {
+        log::warn!("Failed to back up {DEVICE_SETTINGS_PATH}: {e}");
+    }
     let file = File::create(DEVICE_SETTINGS_PATH)?;
     serde_json::to_writer(file, &json)?;
     Ok(())
 }
 
+/// Width and height of the frames the capture device is producing
+fn capture_size(capture_device: &mut VideoCapture, frame_format: FrameFormat) -> Result<(i32, i32)> {
+    // unconverted frames don't come in the shape of the picture
+    if let Some(RawLayout { width, height, .. }) = frame_format.raw_layout {
+        return Ok((width, height));
+    }
+
+    let mut frame = Mat::default();
+    capture_device.read(&mut frame)?;
+    Ok((frame.cols(), frame.rows()))
+}
+
+/// Open the capture, returning the layout of its frames if they're coming from the device
+/// unconverted
+fn open_capture_device(source: &CaptureSource, device_mode: &DeviceMode) -> Result<(VideoCapture, Option<RawLayout>)> {
+    let mut capture_device = match source {
+        CaptureSource::Device(device_index) => VideoCapture::new(*device_index, device_mode.api.api_preference())?,
+        CaptureSource::File(path) => VideoCapture::from_file(&path.to_string_lossy(), CAP_ANY)?,
+        CaptureSource::Screen(_) | CaptureSource::Window(_) => {
+            let pipeline = screen_capture_pipeline(source)?;
+            log::debug!("Screen capture pipeline: {pipeline}");
+            VideoCapture::from_file(&pipeline, CAP_GSTREAMER)?
+        }
+    };
+    if !capture_device.is_opened()? {
+        bail!("Failed to open {source}");
+    }
+    // the mode only means something for a device we're capturing from directly
+    let raw_layout = match source {
+        CaptureSource::Device(_) => device_mode.apply(&mut capture_device, source)?,
+        _ => None,
+    };
+    Ok((capture_device, raw_layout))
+}
+
+/// Build the GStreamer pipeline that captures the given part of the screen
+#[cfg(windows)]
+fn screen_capture_pipeline(source: &CaptureSource) -> Result<String> {
+    let capture = match source {
+        CaptureSource::Screen(ScreenRegion { x, y, width, height }) => {
+            format!("d3d11screencapturesrc show-cursor=false crop-x={x} crop-y={y} crop-width={width} crop-height={height}")
+        }
+        CaptureSource::Window(title) => {
+            let window_handle = crate::platform::find_window(title)?;
+            format!("d3d11screencapturesrc show-cursor=false capture-api=wgc window-handle={window_handle}")
+        }
+        _ => bail!("{source} is not on the screen"),
+    };
+    Ok(format!("{capture} ! d3d11download ! {SCREEN_CAPTURE_SINK}"))
+}
+
+/// Build the GStreamer pipeline that captures the given part of the screen
+#[cfg(not(windows))]
+fn screen_capture_pipeline(source: &CaptureSource) -> Result<String> {
+    let capture = match source {
+        CaptureSource::Screen(ScreenRegion { x, y, width, height }) => {
+            format!("ximagesrc show-pointer=false use-damage=false startx={x} starty={y} endx={} endy={}", x + width - 1, y + height - 1)
+        }
+        // the title is quoted in case it has spaces, so it can't have quotes of its own
+        CaptureSource::Window(title) if !title.contains('"') => {
+            format!("ximagesrc show-pointer=false use-damage=false xname=\"{title}\"")
+        }
+        CaptureSource::Window(title) => bail!("Can't capture a window with a quote in its title: {title}"),
+        _ => bail!("{source} is not on the screen"),
+    };
+    Ok(format!("{capture} ! {SCREEN_CAPTURE_SINK}"))
+}
+
+/// Parse a time in a recording given as seconds or minutes:seconds, e.g. "95.5" or "1:35.5"
+fn parse_video_time(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let seconds = match text.split_once(':') {
+        Some((minutes, seconds)) => minutes.parse::<u64>()? as f64 * 60.0 + seconds.parse::<f64>()?,
+        None => text.parse::<f64>()?,
+    };
+    Ok(Duration::try_from_secs_f64(seconds)?)
+}
+
 fn load_gray(path: impl AsRef<str>) -> Result<Mat> {
     let path = path.as_ref();
     let mat = imread(path, IMREAD_GRAYSCALE)?;
@@ -61,51 +482,350 @@ fn load_gray(path: impl AsRef<str>) -> Result<Mat> {
     gray_float(mat)
 }
 
-fn calibrate(capture_device: &mut VideoCapture, hud_mask: &Mat) -> Result<CaptureTransform> {
-    println!(concat!(
-        "Before starting a run, we must first calibrate the video capture. ",
-        "Please start a new game, wait until you gain control of Rion in the first room, and then press enter.",
-    ));
-    std::io::stdin().read_line(&mut String::new())?;
+fn calibrate(capture_device: &mut VideoCapture, source: &CaptureSource, frame_format: FrameFormat, hud_mask: &Mat) -> Result<CaptureTransform> {
+    if source.is_file() {
+        eprintln!(concat!(
+            "Before watching the video, we must first calibrate it. Enter a time in the video (in seconds or minutes:seconds) ",
+            "after Rion gains control in the first room of a new game.",
+        ));
+        let mut response = String::new();
+        std::io::stdin().read_line(&mut response)?;
+        let time = parse_video_time(&response)?;
+        capture_device.set(CAP_PROP_POS_MSEC, time.as_secs_f64() * 1000.0)?;
+    } else {
+        eprintln!(concat!(
+            "Before starting a run, we must first calibrate the video capture. ",
+            "Please start a new game, wait until you gain control of Rion in the first room, and then press enter.",
+        ));
+        std::io::stdin().read_line(&mut String::new())?;
+    }
 
     let mut frame = Mat::default();
     capture_device.read(&mut frame)?;
 
-    let capture_image = CaptureImage::new(frame)?;
+    let capture_image = frame_format.decode(frame)?;
     let calibration_image = load_gray(CALIBRATION_IMAGE_PATH)?;
     capture_image.find_transform(&calibration_image, hud_mask)
 }
 
-fn load_bg_map() -> Result<BackgroundMap> {
+/// Show the transformed capture over the calibration background, either blended together or as
+/// the difference between them, so the runner can check that they line up. Returns whether the
+/// runner accepted the calibration.
+fn show_calibration_preview(capture_device: &mut VideoCapture, frame_format: FrameFormat, transform: &CaptureTransform) -> Result<bool> {
+    eprintln!(concat!(
+        "Go to the first room and check the preview window: the capture should line up with the background behind it. ",
+        "Press D to switch between the blended and difference views, enter to accept the calibration, or escape to redo it.",
+    ));
+
+    let background = transform.transform_bg(&load_gray(CALIBRATION_IMAGE_PATH)?)?;
+    let mut frame = Mat::default();
+    let mut preview = Mat::default();
+    let mut show_difference = false;
+    let is_accepted = loop {
+        capture_device.read(&mut frame)?;
+        let capture_image = frame_format.decode(std::mem::take(&mut frame))?;
+        let aspect_ratio = capture_image.aspect_ratio(transform)?;
+        let capture = capture_image.transform(transform, aspect_ratio)?;
+        if show_difference {
+            absdiff(&capture, &background, &mut preview)?;
+        } else {
+            add_weighted_def(&capture, 0.5, &background, 0.5, 0.0, &mut preview)?;
+        }
+        imshow(PREVIEW_WINDOW, &preview)?;
+
+        match wait_key(PREVIEW_KEY_DELAY)? {
+            key if KEY_ENTER.contains(&key) => break true,
+            KEY_ESCAPE => break false,
+            key if key == i32::from(b'd') || key == i32::from(b'D') => show_difference = !show_difference,
+            _ => (),
+        }
+    };
+
+    destroy_window(PREVIEW_WINDOW)?;
+    Ok(is_accepted)
+}
+
+/// Calibrate the capture, showing a preview of the result until the runner is happy with it. If
+/// the preview can't be shown (e.g. there's no display), the first calibration is used.
+fn calibrate_with_preview(capture_device: &mut VideoCapture, source: &CaptureSource, frame_format: FrameFormat, hud_mask: &Mat) -> Result<CaptureTransform> {
+    loop {
+        let transform = calibrate(capture_device, source, frame_format, hud_mask)?;
+        match show_calibration_preview(capture_device, frame_format, &transform) {
+            Ok(true) => return Ok(transform),
+            Ok(false) => eprintln!("Let's try that again."),
+            Err(e) => {
+                log::warn!("Couldn't show the calibration preview: {e}");
+                return Ok(transform);
+            }
+        }
+    }
+}
+
+fn load_bg_map() -> Result<(BackgroundMap, RoomNames, RoomGroups)> {
     let file = File::open(BG_MAP_PATH)?;
-    let bg_list: Vec<((Map, u16, Map, u16), String)> = serde_json::from_reader(file)?;
+    let (bg_list, equivalent_rooms) = match serde_json::from_reader(file)? {
+        BackgroundMapJson::Links(links) => (links, Vec::new()),
+        BackgroundMapJson::Full { links, equivalent_rooms } => (links, equivalent_rooms),
+    };
 
     let bg_path = Path::new(BACKGROUND_PATH);
     let mut bg_map = HashMap::new();
-    for ((source_map, source_room, dest_map, dest_room), filename) in bg_list {
+    let mut room_names: RoomNames = HashMap::new();
+    for ((source_map, source_room, dest_map, dest_room), filename, threshold) in bg_list.into_iter().map(BackgroundLink::into_parts) {
+        // background filenames start with the name of the room they belong to
+        if let Some((room_name, _)) = filename.split_once('_') {
+            let rooms = room_names.entry(room_name.to_uppercase()).or_default();
+            if !rooms.contains(&(dest_map, dest_room)) {
+                rooms.push((dest_map, dest_room));
+            }
+        }
+
         let links = bg_map.entry((source_map, source_room)).or_insert_with(Vec::new);
-        links.push((dest_map, dest_room, bg_path.join(filename)));
+        links.push((dest_map, dest_room, bg_path.join(filename), threshold));
     }
 
-    Ok(bg_map)
+    Ok((bg_map, room_names, equivalent_rooms))
+}
+
+fn room_name(room_names: &RoomNames, map: Map, room: u16) -> &str {
+    room_names
+        .iter()
+        .find(|(_, rooms)| rooms.contains(&(map, room)))
+        .map_or("unknown room", |(name, _)| name.as_str())
+}
+
+fn lookup_room(room_names: &RoomNames, bg_map: &BackgroundMap, name: &str) -> Result<(Map, u16)> {
+    let Some(rooms) = room_names.get(&name.to_uppercase()) else {
+        bail!("Unknown room {name}");
+    };
+
+    // some rooms are mapped more than once. we can only track the player from a mapping that has
+    // links to other rooms.
+    match rooms.iter().find(|room| bg_map.contains_key(room)) {
+        Some(room) => Ok(*room),
+        None => bail!("Room {name} can't be used as a starting room"),
+    }
+}
+
+fn detect_start_room(
+    capture_device: &mut VideoCapture,
+    frame_format: FrameFormat,
+    transform: &CaptureTransform,
+    hud_mask: &MaskImage,
+    bg_map: &BackgroundMap,
+    room_names: &RoomNames,
+) -> Result<(Map, u16)> {
+    eprintln!("Please load your save, wait until you have control of Rion, and then press enter.");
+    io::stdin().read_line(&mut String::new())?;
+
+    let mut frame = Mat::default();
+    capture_device.read(&mut frame)?;
+    let capture_image = frame_format.decode(frame)?;
+    let aspect_ratio = capture_image.aspect_ratio(transform)?;
+    let capture = hud_mask.mask(&capture_image.transform(transform, aspect_ratio)?)?;
+
+    // score the capture against every background of every room we can start from
+    let mut scores: HashMap<(Map, u16), f64> = HashMap::new();
+    for (dest_map, dest_room, bg_path, _) in bg_map.values().flatten() {
+        if !bg_map.contains_key(&(*dest_map, *dest_room)) {
+            continue;
+        }
+
+        let bg_image = load_gray(bg_path.to_string_lossy())?;
+        let bg_image = transform.transform_bg(&bg_image)?;
+        let reference_image = ReferenceImage::new(hud_mask.mask(&bg_image)?)?;
+        let score = reference_image.match_score(&capture)?;
+
+        let best_score = scores.entry((*dest_map, *dest_room)).or_insert(score);
+        *best_score = best_score.max(score);
+    }
+
+    let mut candidates: Vec<_> = scores.into_iter().collect();
+    candidates.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    candidates.truncate(START_ROOM_CANDIDATES);
+
+    let Some(&((best_map, best_room), _)) = candidates.first() else {
+        bail!("No rooms available to start from");
+    };
+
+    eprintln!("Closest matching rooms:");
+    for ((map, room), score) in &candidates {
+        eprintln!("  {} ({score:.3})", room_name(room_names, *map, *room));
+    }
+    eprint!("Press enter to start from {}, or type the name of a different room: ", room_name(room_names, best_map, best_room));
+    io::stderr().flush()?;
+
+    let mut response = String::new();
+    io::stdin().read_line(&mut response)?;
+    let response = response.trim();
+    if response.is_empty() {
+        Ok((best_map, best_room))
+    } else {
+        lookup_room(room_names, bg_map, response)
+    }
+}
+
+/// Measure how far the video capture lags behind what's on the TV. The player taps enter in time
+/// with something changing on the TV at a steady pace, and each tap is compared with when the same
+/// change shows up in the capture. Keeping to a rhythm lets the player tap right as the change
+/// happens instead of reacting to it, so their reaction time doesn't get counted.
+pub fn measure_latency(device_index: i32, device_mode: &DeviceMode) -> Result<Duration> {
+    let (mut capture_device, raw_layout) = open_capture_device(&CaptureSource::Device(device_index), device_mode)?;
+    let frame_format = FrameFormat { raw_layout, deinterlace: Deinterlace::Off };
+    eprintln!(concat!(
+        "Find something in the game you can make change on screen at a steady pace, like opening and closing ",
+        "a menu about once a second. Watch your TV, not the capture, and press enter at the moment the TV ",
+        "changes, {} times. Press enter now to begin.",
+    ), LATENCY_TAPS);
+    io::stdin().read_line(&mut String::new())?;
+
+    let (tap_sender, tap_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            if line.is_err() || tap_sender.send(Instant::now()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut frame = Mat::default();
+    let mut last_gray = Mat::default();
+    let mut diff = Mat::default();
+    let mut was_changing = false;
+    let mut changes = Vec::new();
+    let mut taps = Vec::new();
+    // keep watching for a little while after the last tap so its change can show up
+    while taps.last().is_none_or(|tap: &Instant| taps.len() < LATENCY_TAPS || tap.elapsed() < LATENCY_TAP_LATE) {
+        taps.extend(tap_receiver.try_iter());
+
+        capture_device.read(&mut frame)?;
+        let seen_at = Instant::now();
+        let mut gray = frame_format.decode(std::mem::take(&mut frame))?.luma()?;
+        if !last_gray.empty() {
+            absdiff(&gray, &last_gray, &mut diff)?;
+            let is_changing = mean_def(&diff)?.0[0] >= LATENCY_CHANGE_MIN;
+            if is_changing && !was_changing {
+                changes.push(seen_at);
+            }
+            was_changing = is_changing;
+        }
+        std::mem::swap(&mut gray, &mut last_gray);
+    }
+
+    let mut latencies: Vec<Duration> = taps
+        .iter()
+        .filter_map(|tap| {
+            let earliest = tap.checked_sub(LATENCY_TAP_EARLY).unwrap_or(*tap);
+            changes.iter().find(|change| **change >= earliest && change.duration_since(*tap) <= LATENCY_TAP_LATE)
+                .map(|change| change.saturating_duration_since(*tap))
+        })
+        .collect();
+    if latencies.len() * 2 < taps.len() {
+        bail!("Only {} of {} taps lined up with a change in the capture; try again with a bigger change or a steadier pace", latencies.len(), taps.len());
+    }
+
+    latencies.sort();
+    Ok(latencies[latencies.len() / 2])
+}
+
+/// List the assets needed for console runs that are missing or can't be read
+pub fn missing_assets() -> Vec<String> {
+    let mut missing: Vec<String> = [CALIBRATION_IMAGE_PATH, HUD_MASK_PATH, MAIN_MENU_PATH, LOADING_SAVE_PATH]
+        .into_iter()
+        .filter(|path| load_gray(path).is_err())
+        .map(String::from)
+        .collect();
+
+    match load_bg_map() {
+        Ok((bg_map, _, _)) => missing.extend(
+            bg_map
+                .values()
+                .flatten()
+                .filter(|(_, _, bg_path, _)| !bg_path.is_file())
+                .map(|(_, _, bg_path, _)| bg_path.display().to_string()),
+        ),
+        Err(e) => missing.push(format!("{BG_MAP_PATH} ({e})")),
+    }
+
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
+/// Every door in the game, as the room it leads from and the room it leads to, along with the name
+/// of each room
+pub fn room_links() -> Result<(RoomLinks, NamesByRoom)> {
+    let (bg_map, room_names, _) = load_bg_map()?;
+    let links = bg_map
+        .iter()
+        .flat_map(|(source, dests)| dests.iter().map(move |(dest_map, dest_room, _, _)| (*source, (*dest_map, *dest_room))))
+        .collect();
+    let names = room_names
+        .into_iter()
+        .flat_map(|(name, rooms)| rooms.into_iter().map(move |room| (room, name.clone())))
+        .collect();
+    Ok((links, names))
+}
+
+/// What the capture is showing, apart from which room the player is in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ScreenState {
+    Picture,
+    /// The screen has been black since the given time, as it is while loading and around FMVs
+    Black(Instant),
 }
 
 #[derive(Debug)]
 pub struct ConsoleGame {
     capture_device: VideoCapture,
+    source: CaptureSource,
+    device_mode: DeviceMode,
+    frame_format: FrameFormat,
+    /// How long each frame of a video file lasts, so it can be played back in real time
+    frame_interval: Option<Duration>,
+    /// When the next frame of a video file should be shown
+    next_frame_at: Option<Instant>,
+    is_video_finished: bool,
     transform: CaptureTransform,
+    /// How the picture was displayed in the most recent frame
+    aspect_ratio: AspectRatio,
+    screen_state: ScreenState,
+    /// Time the screen has spent black since the run started, not counting the current black screen
+    load_time: Duration,
     hud_mask: MaskImage,
     main_menu: ReferenceImage,
     loading_save: ReferenceImage,
     bg_map: BackgroundMap,
+    equivalent_rooms: RoomGroups,
     current_map: Map,
     current_room: u16,
-    current_links: Vec<(Map, u16, ReferenceImage)>,
+    current_links: Vec<(Map, u16, ReferenceImage, f64)>,
+    /// Backgrounds of the current room itself, for checking that the calibration still lines up
+    room_references: Vec<ReferenceImage>,
+    /// How far the capture region has been moved from where it was calibrated to follow the
+    /// picture
+    drift: (i32, i32),
+    last_drift_check: Option<Instant>,
+    /// Rooms we might have just entered when we couldn't tell which of a group of equivalent rooms
+    /// it was
+    candidate_rooms: Vec<(Map, u16)>,
+    /// Room to move to on the next frame after resolving which candidate room we were in
+    pending_room: Option<(Map, u16)>,
+    calibrated_at: Option<SystemTime>,
+    /// Directory to save a screenshot to whenever a split happens
+    screenshot_dir: Option<PathBuf>,
+    /// The most recent frame from the capture device, for screenshots
+    last_capture: Option<CaptureImage>,
+    /// Scores from the frame currently being checked
+    frame_scores: Vec<MatchScore>,
+    /// Scores from recent frames, oldest first
+    score_history: VecDeque<Vec<MatchScore>>,
     has_defeated_final_boss: bool,
     is_at_main_menu: bool,
     is_loading_save: bool,
     is_new_game_start: bool,
     run_category: RunCategory,
+    start_room: Option<(Map, u16)>,
 }
 
 impl ConsoleGame {
@@ -116,45 +836,114 @@ impl ConsoleGame {
         main_menu: ReferenceImage,
         loading_save: ReferenceImage,
         bg_map: BackgroundMap,
+        start_room: Option<(Map, u16)>,
     ) -> Self {
         Self {
             capture_device,
+            source: CaptureSource::Device(0),
+            device_mode: DeviceMode { api: CaptureApi::Auto, frame_size: None, fps: None, fourcc: None, raw_yuv: false },
+            frame_format: FrameFormat { raw_layout: None, deinterlace: Deinterlace::Off },
+            frame_interval: None,
+            next_frame_at: None,
+            is_video_finished: false,
             transform,
+            aspect_ratio: AspectRatio::Standard,
+            screen_state: ScreenState::Picture,
+            load_time: Duration::ZERO,
             hud_mask,
             main_menu,
             loading_save,
             bg_map,
+            equivalent_rooms: Vec::new(),
             current_map: Map::Hospital15F,
             current_room: 0,
             current_links: Vec::new(),
+            room_references: Vec::new(),
+            drift: (0, 0),
+            last_drift_check: None,
+            candidate_rooms: Vec::new(),
+            pending_room: None,
+            calibrated_at: None,
+            screenshot_dir: None,
+            last_capture: None,
+            frame_scores: Vec::new(),
+            score_history: VecDeque::new(),
             has_defeated_final_boss: false,
             is_at_main_menu: false,
             is_loading_save: false,
             is_new_game_start: false,
             run_category: RunCategory::AnyPercent,
+            start_room,
         }
     }
 
-    pub fn connect(device_index: i32, force_calibrate: bool) -> Result<Self> {
-        let mut capture_device = VideoCapture::new_def(device_index)?;
+    /// Connect to the capture device. If `start_room` is provided, the run is expected to start
+    /// by loading a save in the room with that name instead of from a new game. A start room of
+    /// "detect" will detect the room from the capture. Calibration is saved separately for each
+    /// named `profile`, so one device can have a calibration for each mode it's used in. If
+    /// `preview_calibration` is set, the calibration preview is shown even when a saved calibration
+    /// is used. Capture devices are opened in the given `device_mode`, and the capture is
+    /// deinterlaced as given by `deinterlace`.
+    pub fn connect(
+        source: CaptureSource,
+        device_mode: DeviceMode,
+        deinterlace: Deinterlace,
+        profile: Option<&str>,
+        force_calibrate: bool,
+        preview_calibration: bool,
+        start_room: Option<&str>,
+    ) -> Result<Self> {
+        let (mut capture_device, raw_layout) = open_capture_device(&source, &device_mode)?;
+        let frame_format = FrameFormat { raw_layout, deinterlace };
         let hud_mask = load_gray(HUD_MASK_PATH)?;
         let main_menu = load_gray(MAIN_MENU_PATH)?;
         let loading_save = load_gray(LOADING_SAVE_PATH)?;
-        let bg_map = load_bg_map()?;
+        let (bg_map, room_names, equivalent_rooms) = load_bg_map()?;
 
         let mut settings = load_device_settings()?;
-        let transform = if force_calibrate || !settings.contains_key(&device_index) {
-            let transform = calibrate(&mut capture_device, &hud_mask)?;
-            println!("Calibration complete. Transform: {transform:?}");
-            settings.insert(device_index, transform.clone());
+        let key = settings_key(&source, profile);
+        let current_size = capture_size(&mut capture_device, frame_format)?;
+        let (transform, calibrated_at) = if force_calibrate || !settings.contains_key(&key) {
+            if let Some(profile) = profile {
+                eprintln!("Calibrating profile {profile} for {source}.");
+            }
+            let transform = calibrate_with_preview(&mut capture_device, &source, frame_format, &hud_mask)?;
+            eprintln!("Calibration complete. Transform: {transform:?}");
+            settings.insert(key, DeviceSettings { transform: transform.clone(), capture_size: Some(current_size) });
             save_device_settings(&settings)?;
-            transform
+            (transform, Some(SystemTime::now()))
         } else {
-            settings.get(&device_index).unwrap().clone()
+            // we don't keep track of when each device was calibrated, but the settings file
+            // tells us when the last calibration happened, which is good enough
+            let calibrated_at = fs::metadata(DEVICE_SETTINGS_PATH).and_then(|metadata| metadata.modified()).ok();
+            let DeviceSettings { transform, capture_size } = settings.remove(&key).unwrap();
+            if let Some((width, height)) = capture_size && capture_size != Some(current_size) {
+                let (current_width, current_height) = current_size;
+                log::warn!(
+                    "The capture is {current_width}x{current_height}, but this calibration was made at {width}x{height}. If you've switched modes, use --calibration-profile to keep a calibration for each mode, or --force-calibrate to recalibrate.",
+                );
+            }
+            if preview_calibration {
+                match show_calibration_preview(&mut capture_device, frame_format, &transform) {
+                    Ok(true) => (),
+                    Ok(false) => log::warn!("Calibration rejected; run again with --force-calibrate to recalibrate"),
+                    Err(e) => log::warn!("Couldn't show the calibration preview: {e}"),
+                }
+            }
+            (transform, calibrated_at)
         };
 
         let hud_mask = MaskImage::new(transform.transform_bg(&hud_mask)?)?;
 
+        let start_room = match start_room {
+            Some(DETECT_START_ROOM) => Some(detect_start_room(&mut capture_device, frame_format, &transform, &hud_mask, &bg_map, &room_names)?),
+            Some(name) => Some(lookup_room(&room_names, &bg_map, name)?),
+            None => None,
+        };
+        if let Some((map, room)) = start_room {
+            log::info!("Runs will start from {}", room_name(&room_names, map, room));
+        }
+
         let main_menu = transform.transform_bg(&main_menu)?;
         let main_menu = MaskedImage::unmasked(main_menu);
         let main_menu = ReferenceImage::new(main_menu)?;
@@ -163,15 +952,284 @@ impl ConsoleGame {
         let loading_save = MaskedImage::unmasked(loading_save);
         let loading_save = ReferenceImage::new(loading_save)?;
 
-        Ok(Self::new(capture_device, transform, hud_mask, main_menu, loading_save, bg_map))
+        Ok(
+            Self::new(capture_device, transform, hud_mask, main_menu, loading_save, bg_map, start_room)
+                .with_source(source)
+                .with_device_mode(device_mode)
+                .with_deinterlace(deinterlace)
+                .with_raw_layout(raw_layout)
+                .with_equivalent_rooms(equivalent_rooms)
+                .with_calibration_time(calibrated_at)
+        )
+    }
+
+    /// Record where the capture came from so we can reopen it if it goes away. Video files are
+    /// played back from the start at their own frame rate.
+    pub fn with_source(mut self, source: CaptureSource) -> Self {
+        self.frame_interval = None;
+        if source.is_file() {
+            // calibration and start room detection may have read some of the video already
+            match self.capture_device.set(CAP_PROP_POS_FRAMES, 0.0) {
+                Ok(true) => (),
+                _ => log::warn!("Couldn't rewind {source}; it will be watched from where calibration left off"),
+            }
+            match self.capture_device.get(CAP_PROP_FPS) {
+                Ok(fps) if fps > 0.0 && fps.is_finite() => self.frame_interval = Some(Duration::from_secs_f64(1.0 / fps)),
+                _ => log::warn!("Couldn't get the frame rate of {source}; it will be played back as fast as possible"),
+            }
+        }
+        self.source = source;
+        self.next_frame_at = None;
+        self.is_video_finished = false;
+        self
+    }
+
+    /// Declare groups of rooms whose backgrounds are too similar to tell apart on their own
+    pub fn with_equivalent_rooms(mut self, equivalent_rooms: RoomGroups) -> Self {
+        self.equivalent_rooms = equivalent_rooms;
+        self
+    }
+
+    /// Record when the capture was calibrated so we can warn if it's been a while
+    pub const fn with_calibration_time(mut self, calibrated_at: Option<SystemTime>) -> Self {
+        self.calibrated_at = calibrated_at;
+        self
+    }
+
+    /// Save a screenshot to the given directory every time a split happens
+    pub fn with_screenshot_dir(mut self, screenshot_dir: Option<PathBuf>) -> Self {
+        self.screenshot_dir = screenshot_dir;
+        self
+    }
+
+    /// Try to reopen the capture device, waiting longer after each failure. Gives up after the
+    /// given number of attempts. Everything we know about the capture and the game is kept.
+    /// Record the mode the capture device was opened in so it's reopened the same way
+    pub fn with_device_mode(mut self, device_mode: DeviceMode) -> Self {
+        self.device_mode = device_mode;
+        self
+    }
+
+    /// Deinterlace every frame of the capture this way
+    pub const fn with_deinterlace(mut self, deinterlace: Deinterlace) -> Self {
+        self.frame_format.deinterlace = deinterlace;
+        self
+    }
+
+    /// Read the capture device's frames as unconverted YUV with this layout
+    pub const fn with_raw_layout(mut self, raw_layout: Option<RawLayout>) -> Self {
+        self.frame_format.raw_layout = raw_layout;
+        self
+    }
+
+    fn reopen_capture_device(&mut self, max_attempts: usize) -> Result<()> {
+        // some drivers won't let us open the device again while we still have it open
+        if let Err(e) = self.capture_device.release() {
+            log::debug!("Failed to release {}: {e}", self.source);
+        }
+
+        let mut delay = RECONNECT_INITIAL_DELAY;
+        let mut attempt = 1;
+        loop {
+            match open_capture_device(&self.source, &self.device_mode) {
+                Ok((capture_device, raw_layout)) => {
+                    log::info!("Reopened {}", self.source);
+                    self.capture_device = capture_device;
+                    self.frame_format.raw_layout = raw_layout;
+                    return Ok(());
+                }
+                Err(e) if attempt >= max_attempts => return Err(e),
+                Err(e) => log::debug!("Failed to reopen {}: {e}", self.source),
+            }
+
+            thread::sleep(delay);
+            delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+            attempt += 1;
+        }
+    }
+
+    /// Wait until it's time for the next frame of a video file, skipping frames if we've fallen
+    /// behind, so that the video plays back in real time and the timer sees the run as it happened
+    fn wait_for_next_frame(&mut self) -> Result<()> {
+        let Some(frame_interval) = self.frame_interval else {
+            return Ok(());
+        };
+
+        let due = *self.next_frame_at.get_or_insert_with(Instant::now);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+
+        let mut next_frame_at = due + frame_interval;
+        while next_frame_at + frame_interval < Instant::now() {
+            if !self.capture_device.grab()? {
+                break;
+            }
+            next_frame_at += frame_interval;
+        }
+        self.next_frame_at = Some(next_frame_at);
+
+        Ok(())
+    }
+
+    fn record_score(&mut self, target: MatchTarget, score: f64, threshold: f64) {
+        self.frame_scores.push(MatchScore { target, score, threshold });
+    }
+
+    /// Move the current frame's scores into the history
+    fn finish_frame_scores(&mut self) {
+        if self.frame_scores.is_empty() {
+            return;
+        }
+
+        if self.score_history.len() >= MATCH_SCORE_HISTORY_LENGTH {
+            self.score_history.pop_front();
+        }
+        self.score_history.push_back(std::mem::take(&mut self.frame_scores));
+    }
+
+    fn update_screen_state(&mut self, is_black: bool) {
+        match (self.screen_state, is_black) {
+            (ScreenState::Picture, true) => self.screen_state = ScreenState::Black(Instant::now()),
+            (ScreenState::Black(since), false) => {
+                let duration = since.elapsed();
+                log::debug!("Black screen for {} ms", duration.as_millis());
+                self.load_time += duration;
+                self.screen_state = ScreenState::Picture;
+            }
+            _ => (),
+        }
     }
 
     fn is_in_final_boss_room(&self) -> bool {
         (self.current_map, self.current_room) == FINAL_BOSS_ROOM
     }
 
+    fn load_links(&mut self, map: Map, room: u16) -> Result<()> {
+        let Some(links) = self.bg_map.get(&(map, room)) else {
+            if (map, room) == FINAL_BOSS_ROOM {
+                // don't expect any rooms after the final boss
+                return Ok(());
+            }
+            bail!("No room links for room {} {}", map as u16, room);
+        };
+
+        for (dest_map, dest_room, bg_path, threshold) in links {
+            // when loading links for several candidate rooms, they may share destinations
+            if self.current_links.iter().any(|(m, r, _, _)| (*m, *r) == (*dest_map, *dest_room)) {
+                continue;
+            }
+
+            let bg_image = load_gray(bg_path.to_string_lossy())?;
+            let bg_image = self.transform.transform_bg(&bg_image)?;
+            let bg_image = self.hud_mask.mask(&bg_image)?;
+            let reference_image = ReferenceImage::new(bg_image)?;
+            self.current_links.push((*dest_map, *dest_room, reference_image, *threshold));
+        }
+
+        Ok(())
+    }
+
+    /// Load every background that's shown on entering the given room
+    fn load_room_references(&mut self, map: Map, room: u16) -> Result<()> {
+        self.room_references.clear();
+        let mut bg_paths: Vec<&PathBuf> = Vec::new();
+        for (dest_map, dest_room, bg_path, _) in self.bg_map.values().flatten() {
+            if (*dest_map, *dest_room) == (map, room) && !bg_paths.contains(&bg_path) {
+                bg_paths.push(bg_path);
+            }
+        }
+
+        for bg_path in bg_paths {
+            let bg_image = load_gray(bg_path.to_string_lossy())?;
+            let bg_image = self.transform.transform_bg(&bg_image)?;
+            let bg_image = self.hud_mask.mask(&bg_image)?;
+            self.room_references.push(ReferenceImage::new(bg_image)?);
+        }
+
+        Ok(())
+    }
+
+    /// Best score for the capture against the current room's backgrounds with the given transform
+    fn room_score(&self, capture_image: &CaptureImage, transform: &CaptureTransform) -> Result<f64> {
+        let capture = self.hud_mask.mask(&capture_image.transform(transform, AspectRatio::Standard)?)?;
+        let mut best_score = f64::MIN;
+        for reference_image in &self.room_references {
+            best_score = best_score.max(reference_image.match_score(&capture)?);
+        }
+        Ok(best_score)
+    }
+
+    /// Check whether the picture has moved a little since calibration, which can happen when the
+    /// capture device changes modes or a cable is reseated. If the current room matches poorly, try
+    /// nudging the capture region a pixel at a time in whichever direction improves the match.
+    /// Returns the new drift if moving the capture region makes the room match again.
+    fn find_drift(&self, capture_image: &CaptureImage) -> Result<Option<(i32, i32)>> {
+        let current_score = self.room_score(capture_image, &self.transform)?;
+        if !(DRIFT_MIN_SCORE..MATCH_THRESHOLD).contains(&current_score) {
+            return Ok(None);
+        }
+
+        let (mut best_x, mut best_y, mut best_score) = (0, 0, current_score);
+        loop {
+            let (start_x, start_y) = (best_x, best_y);
+            for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let (x, y) = (start_x + dx, start_y + dy);
+                let (drift_x, drift_y) = (self.drift.0 + x, self.drift.1 + y);
+                if drift_x.abs() > MAX_DRIFT || drift_y.abs() > MAX_DRIFT {
+                    continue;
+                }
+
+                let transform = self.transform.shifted(x, y);
+                if !capture_image.fits(&transform) {
+                    continue;
+                }
+
+                let score = self.room_score(capture_image, &transform)?;
+                if score > best_score {
+                    (best_x, best_y, best_score) = (x, y, score);
+                }
+            }
+
+            if (best_x, best_y) == (start_x, start_y) {
+                break;
+            }
+        }
+
+        log::debug!("Room score {current_score} at drift {:?}; best nearby is {best_score} at {best_x}, {best_y}", self.drift);
+        if best_score < MATCH_THRESHOLD || best_score - current_score < DRIFT_MIN_IMPROVEMENT {
+            return Ok(None);
+        }
+
+        Ok(Some((best_x, best_y)))
+    }
+
+    /// Periodically check the calibration against the current room and follow the picture if it
+    /// has moved
+    fn correct_drift(&mut self, capture_image: &CaptureImage) -> Result<()> {
+        if self.last_drift_check.is_some_and(|checked| checked.elapsed() < DRIFT_CHECK_INTERVAL) {
+            return Ok(());
+        }
+        self.last_drift_check = Some(Instant::now());
+
+        let Some((dx, dy)) = self.find_drift(capture_image)? else {
+            return Ok(());
+        };
+
+        self.transform = self.transform.shifted(dx, dy);
+        self.drift = (self.drift.0 + dx, self.drift.1 + dy);
+        log::info!(
+            "The capture picture has moved; shifted the capture region by {dx}, {dy} ({}, {} from calibration). Recalibrate with --force-calibrate if matching stays unreliable.",
+            self.drift.0,
+            self.drift.1,
+        );
+
+        Ok(())
+    }
+
     fn set_room(&mut self, map: Map, room: u16) -> Result<()> {
-        if map == self.current_map && room == self.current_room && !self.current_links.is_empty() {
+        if map == self.current_map && room == self.current_room && !self.current_links.is_empty() && self.candidate_rooms.is_empty() {
             return Ok(());
         }
 
@@ -179,41 +1237,107 @@ impl ConsoleGame {
 
         self.current_map = map;
         self.current_room = room;
+        self.candidate_rooms.clear();
+        self.pending_room = None;
         self.has_defeated_final_boss = false;
         self.is_at_main_menu = false;
         self.is_loading_save = false;
         self.is_new_game_start = false;
 
         self.current_links.clear();
-        let Some(links) = self.bg_map.get(&(self.current_map, self.current_room)) else {
-            if self.is_in_final_boss_room() {
-                // don't expect any rooms after the final boss
-                return Ok(());
-            }
-            bail!("No room links for room {} {}", self.current_map as u16, self.current_room);
+        self.load_links(map, room)?;
+        self.load_room_references(map, room)
+    }
+
+    /// Get the rooms linked from the current room that are equivalent to the given room
+    fn equivalent_links(&self, map: Map, room: u16) -> Vec<(Map, u16)> {
+        let Some(group) = self.equivalent_rooms.iter().find(|group| group.contains(&(map, room))) else {
+            return Vec::new();
         };
 
-        for (dest_map, dest_room, bg_path) in links {
-            let bg_image = load_gray(bg_path.to_string_lossy())?;
-            let bg_image = self.transform.transform_bg(&bg_image)?;
-            let bg_image = self.hud_mask.mask(&bg_image)?;
-            let reference_image = ReferenceImage::new(bg_image)?;
-            self.current_links.push((*dest_map, *dest_room, reference_image));
+        let mut rooms = Vec::new();
+        for (dest_map, dest_room, _, _) in &self.current_links {
+            if group.contains(&(*dest_map, *dest_room)) && !rooms.contains(&(*dest_map, *dest_room)) {
+                rooms.push((*dest_map, *dest_room));
+            }
+        }
+        rooms
+    }
+
+    /// Hold off on deciding which room we're in until we see where the player goes next
+    fn defer_room(&mut self, candidates: Vec<(Map, u16)>) -> Result<()> {
+        log::debug!("Room is one of {candidates:?}");
+
+        self.current_links.clear();
+        // we don't know which room's backgrounds to check the calibration against
+        self.room_references.clear();
+        for (map, room) in &candidates {
+            self.load_links(*map, *room)?;
         }
+        self.candidate_rooms = candidates;
+
+        Ok(())
+    }
+
+    /// Work out which candidate room the player was in based on the room they went to next
+    fn resolve_room(&mut self, dest_map: Map, dest_room: u16) -> Result<()> {
+        let links_to_dest = |room: &&(Map, u16)| {
+            self.bg_map
+                .get(room)
+                .is_some_and(|links| links.iter().any(|(m, r, _, _)| (*m, *r) == (dest_map, dest_room)))
+        };
+        let Some(&(map, room)) = self.candidate_rooms.iter().find(links_to_dest).or(self.candidate_rooms.first()) else {
+            return self.set_room(dest_map, dest_room);
+        };
+
+        // report the room the player was actually in before moving on so that room changes still
+        // happen in the right order
+        self.set_room(map, room)?;
+        self.pending_room = Some((dest_map, dest_room));
 
         Ok(())
     }
 
     fn check_frame(&mut self, route_hint: Option<&Event>) -> Result<()> {
+        if let Some((map, room)) = self.pending_room.take() {
+            return self.set_room(map, room);
+        }
+
+        self.wait_for_next_frame()?;
         let mut frame = Mat::default();
-        self.capture_device.read(&mut frame)?;
+        if !self.capture_device.read(&mut frame)? {
+            if self.source.is_file() {
+                self.is_video_finished = true;
+                return Ok(());
+            }
+            bail!("{} did not return a frame", self.source);
+        }
 
-        let capture_image = CaptureImage::new(frame)?;
-        let trans_capture = capture_image.transform(&self.transform)?;
+        let capture_image = self.frame_format.decode(frame)?;
+        // some setups stretch the picture during FMVs, so check how it's being displayed on every
+        // frame rather than failing to match until it goes back
+        let aspect_ratio = capture_image.aspect_ratio(&self.transform)?;
+        if aspect_ratio != self.aspect_ratio {
+            log::debug!("Capture aspect ratio changed to {aspect_ratio:?}");
+            self.aspect_ratio = aspect_ratio;
+        }
+        let trans_capture = capture_image.transform(&self.transform, aspect_ratio)?;
         let capture = self.hud_mask.mask(&trans_capture)?;
+        self.update_screen_state(is_fade_out(&trans_capture, BLACK_SCREEN_MAX)?);
+        // stretched pictures are too different from the backgrounds to fine-tune against, and the
+        // final boss room is too dark
+        let can_check_drift = self.screen_state == ScreenState::Picture
+            && aspect_ratio == AspectRatio::Standard
+            && !self.is_at_main_menu
+            && !self.is_in_final_boss_room()
+            && !self.room_references.is_empty();
+        self.last_capture = Some(capture_image);
 
+        // a black screen can't be any room, and the dark frames of a fade can look enough like the
+        // wrong room to match it, so only look for rooms when there's a picture
+        let links = if self.screen_state == ScreenState::Picture { self.current_links.as_slice() } else { &[] };
         let mut best_match = None;
-        for (dest_map, dest_room, reference_image) in &self.current_links {
+        for (dest_map, dest_room, reference_image, threshold) in links {
             let score = if (*dest_map, *dest_room) == FINAL_BOSS_ROOM {
                 // the background displayed in this room is a darkened version of the actual
                 // background image, and our matching algorithm has trouble with very dark images
@@ -227,16 +1351,15 @@ impl ConsoleGame {
             } else {
                 reference_image.match_score(&capture)?
             };
+            self.frame_scores.push(MatchScore {
+                target: MatchTarget::Room { map: *dest_map as u16, room: *dest_room },
+                score,
+                threshold: *threshold,
+            });
 
-            if score > MATCH_THRESHOLD {
+            if score > *threshold {
                 // if one of the matches is the expected next room, always take that one
-                let route_match = match route_hint {
-                    Some(Event::Room(route_map, route_room)) => (*route_map, *route_room) == (*dest_map, *dest_room),
-                    Some(Event::Room2((route_map1, route_room1), (route_map2, route_room2))) => {
-                        (*route_map1, *route_room1) == (*dest_map, *dest_room) || (*route_map2, *route_room2) == (*dest_map, *dest_room)
-                    }
-                    _ => false,
-                };
+                let route_match = route_hint.is_some_and(|event| event.matches_room((*dest_map as u16, *dest_room)));
                 if route_match {
                     best_match = Some((1.0, *dest_map, *dest_room));
                     break;
@@ -253,10 +1376,30 @@ impl ConsoleGame {
         }
 
         if let Some((_, dest_map, dest_room)) = best_match {
+            if !self.candidate_rooms.is_empty() {
+                return self.resolve_room(dest_map, dest_room);
+            }
+
+            // if the room looks like others we could have gone to, wait until the player leaves to
+            // decide which one it was. the route hint takes precedence, though.
+            let is_route_match = route_hint.is_some_and(|event| event.matches_room((dest_map as u16, dest_room)));
+            let candidates = self.equivalent_links(dest_map, dest_room);
+            if candidates.len() > 1 && !is_route_match {
+                return self.defer_room(candidates);
+            }
+
             self.set_room(dest_map, dest_room)?;
             return Ok(());
         }
 
+        // we're still in the same room, so if it's matching poorly, check whether the picture has
+        // moved
+        if can_check_drift && let Some(capture_image) = self.last_capture.take() {
+            let result = self.correct_drift(&capture_image);
+            self.last_capture = Some(capture_image);
+            result?;
+        }
+
         // if the player is in the final boss room, check for game completion by detecting the fade
         // to black
         if self.is_in_final_boss_room() && !self.has_defeated_final_boss {
@@ -268,12 +1411,17 @@ impl ConsoleGame {
         }
 
         // if we're at the main menu, check for the start of a new game
-        if self.is_at_main_menu && !self.is_new_game_start && self.is_loading_save == (self.run_category == RunCategory::ReplayMode) {
+        let expect_loading_save = self.run_category == RunCategory::ReplayMode || self.start_room.is_some();
+        if self.is_at_main_menu && !self.is_new_game_start && self.is_loading_save == expect_loading_save {
             // FIXME: this also triggers if the trailer starts playing
             if is_fade_out(&trans_capture, MAIN_MENU_FADE_MAX)? {
+                if let Some((start_map, start_room)) = self.start_room {
+                    self.set_room(start_map, start_room)?;
+                }
                 self.is_at_main_menu = false;
                 self.is_loading_save = false;
                 self.is_new_game_start = true;
+                self.load_time = Duration::ZERO;
                 log::debug!("New game start");
                 return Ok(());
             }
@@ -285,6 +1433,7 @@ impl ConsoleGame {
         // between NG and NG+
         if self.is_at_main_menu && !self.is_loading_save {
             let score = self.loading_save.match_score(&unmasked_capture)?;
+            self.record_score(MatchTarget::LoadingSave, score, LOADING_SAVE_MATCH_THRESHOLD);
             if score > LOADING_SAVE_MATCH_THRESHOLD {
                 self.is_loading_save = true;
                 log::debug!("Loading save: {score}");
@@ -296,6 +1445,7 @@ impl ConsoleGame {
             // the room 204 door triggers a false positive for the main menu with the normal match
             // threshold, so we use a slightly higher threshold here
             let score = self.main_menu.match_score(&unmasked_capture)?;
+            self.record_score(MatchTarget::MainMenu, score, MAIN_MENU_MATCH_THRESHOLD);
             if score > MAIN_MENU_MATCH_THRESHOLD {
                 self.set_room(Map::Hospital15F, 0)?;
                 log::debug!("At main menu: {score}");
@@ -310,17 +1460,45 @@ impl ConsoleGame {
 
 impl Game for ConsoleGame {
     fn update(&mut self, route_hint: Option<&Event>) -> GameState {
-        match self.check_frame(route_hint) {
+        if self.is_video_finished {
+            return GameState::Disconnected;
+        }
+
+        let result = self.check_frame(route_hint);
+        self.finish_frame_scores();
+        if self.is_video_finished {
+            log::info!("Reached the end of {}", self.source);
+            return GameState::Disconnected;
+        }
+        let Err(e) = result else {
+            return GameState::Connected;
+        };
+
+        // reopening a video file would start it over from the beginning
+        if self.source.is_file() {
+            log::error!("Failed to check next frame of {}: {e}", self.source);
+            self.is_video_finished = true;
+            return GameState::Disconnected;
+        }
+
+        // capture devices sometimes drop out for a moment, so give it a chance to come back before
+        // giving up on the run
+        log::warn!("Failed to check next capture frame: {e}; reopening video capture device");
+        match self.reopen_capture_device(RECONNECT_ATTEMPTS) {
             Ok(_) => GameState::Connected,
             Err(e) => {
-                log::error!("Failed to check next capture frame: {e}");
+                log::error!("Failed to reopen {}: {e}", self.source);
                 GameState::Disconnected
             }
         }
     }
 
     fn reconnect(&mut self, _platform: &PlatformRef) -> Result<()> {
-        bail!("Video capture reconnect is not implemented");
+        if self.is_video_finished {
+            bail!("{} has ended", self.source);
+        }
+        // the autosplitter will keep calling us until the device comes back
+        self.reopen_capture_device(1)
     }
 
     fn set_run_category(&mut self, new_category: RunCategory) {
@@ -340,6 +1518,44 @@ impl Game for ConsoleGame {
         self.is_new_game_start
     }
 
+    fn setup_problems(&self) -> Vec<String> {
+        let age = self.calibrated_at.and_then(|time| time.elapsed().ok());
+        match age {
+            Some(age) if age > CALIBRATION_MAX_AGE => vec![format!(
+                "Capture calibration is {} days old; recalibrate with --force-calibrate",
+                age.as_secs() / (24 * 60 * 60),
+            )],
+            _ => Vec::new(),
+        }
+    }
+
+    fn load_time(&self) -> Option<Duration> {
+        let current_black_screen = match self.screen_state {
+            ScreenState::Black(since) => since.elapsed(),
+            ScreenState::Picture => Duration::ZERO,
+        };
+        Some(self.load_time + current_black_screen)
+    }
+
+    fn save_split_screenshot(&self, split_index: i64) -> Result<()> {
+        let (Some(screenshot_dir), Some(capture)) = (&self.screenshot_dir, &self.last_capture) else {
+            return Ok(());
+        };
+
+        fs::create_dir_all(screenshot_dir)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = screenshot_dir.join(format!("split_{split_index:03}_{timestamp}.jpg"));
+        capture.save_screenshot(&path.to_string_lossy())
+    }
+
+    fn match_score_history(&self) -> Vec<Vec<MatchScore>> {
+        self.score_history.iter().cloned().collect()
+    }
+
+    fn start_room(&self) -> Option<(u16, u16)> {
+        self.start_room.map(|(map, room)| (map as u16, room))
+    }
+
     fn map_id(&self) -> u16 {
         self.current_map as u16
     }
@@ -359,4 +1575,9 @@ impl Game for ConsoleGame {
     fn has_item(&self, _item_id: Item) -> bool {
         panic!("Item check is not implemented for console autosplitter");
     }
+
+    fn knock_state(&self) -> Option<KnockState> {
+        // the knock is only reflected in flags, which we can't see on console
+        None
+    }
 }
\ No newline at end of file
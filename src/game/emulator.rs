@@ -1,20 +1,29 @@
 use std::thread;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
-use super::{Game, GameState, Item, Stage};
+use super::{Boss, Game, GameState, Item, KnockState, Map, Stage};
 use crate::RunCategory;
-use crate::platform::{Emulator, PlatformInterface, PlatformRef};
+use crate::platform::{Emulator, PlatformInterface, PlatformRef, RamSignature};
 use crate::splits::Event;
 
 const SEARCH_STRING: &[u8] = b"GALERIANS";
 const NEW_GAME_MENU_STATE: i32 = 99;
 const TRAILER_MENU_STATE: i32 = 200;
 const GAME_END_FLAGS: [u32; 4] = [37, 38, 39, 80];
+const KNOCK_LEARNED_FLAG: u32 = 5;
+const KNOCK_SUCCEEDED_FLAG: u32 = 17;
 const FLAG_BANK_SIZE: u32 = 4 * 8;
+const NUM_FLAG_BANKS: u32 = 3;
+/// Number of story flags each stage has
+pub const NUM_FLAGS: u32 = NUM_FLAG_BANKS * 64;
+/// Number of 64-bit flag words across all banks and stages
+const NUM_FLAG_WORDS: usize = (NUM_FLAG_BANKS * FLAG_BANK_SIZE / 8) as usize;
 const MAX_ITEMS: usize = 41;
 const EMULATOR_RETRY_DURATION: Duration = Duration::from_millis(5000);
+/// Rate at which the in-game time counter ticks
+const IGT_TICKS_PER_SECOND: u32 = 60;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GameCheck {
@@ -41,6 +50,12 @@ pub struct GameVersion {
     flag_banks_address: u32,
     inventory_address: u32,
     inventory_count_address: u32,
+    /// Address of the ID of the FMV or cutscene script that's currently playing
+    cutscene_id_address: Option<u32>,
+    /// Address of the frame counter the save screen's play time is calculated from
+    igt_address: Option<u32>,
+    /// Address of each boss's HP, in the order of the Boss enum
+    boss_health_addresses: [Option<u32>; 5],
 }
 
 impl GameVersion {
@@ -69,6 +84,11 @@ impl GameVersion {
         None
     }
 
+    /// Signature for finding emulated RAM by the location of the search string in this version
+    pub const fn ram_signature(&self) -> RamSignature {
+        RamSignature::new(SEARCH_STRING, self.search_string_address)
+    }
+
     pub fn validate(&self, emulator: &Emulator) -> bool {
         let mut compare_value = [0u8; SEARCH_STRING.len()];
         emulator.read_into(self.search_string_address, &mut compare_value);
@@ -88,6 +108,14 @@ const GAME_VERSIONS: [GameVersion; 2] = [
         flag_banks_address: 0x801AF9A0,
         inventory_address: 0x801AFAAC,
         inventory_count_address: 0x801AFAFE,
+        // TODO: the ID of the current FMV/cutscene script hasn't been located in either version yet.
+        //  we also need to know what value it has when nothing is playing.
+        cutscene_id_address: None,
+        // TODO: the in-game time counter hasn't been located in either version yet, and the tick
+        //  rate needs to be confirmed against the save screen once it is
+        igt_address: None,
+        // TODO: boss HP addresses haven't been located in either version yet
+        boss_health_addresses: [None; 5],
     },
     GameVersion {
         name: "NTSC-J",
@@ -100,13 +128,32 @@ const GAME_VERSIONS: [GameVersion; 2] = [
         flag_banks_address: 0x801AFFA0,
         inventory_address: 0x801B00AC,
         inventory_count_address: 0x801B00FE,
+        cutscene_id_address: None,
+        igt_address: None,
+        boss_health_addresses: [None; 5],
     },
 ];
 
+/// Names of the game versions we know the addresses for
+pub fn supported_versions() -> [&'static str; 2] {
+    GAME_VERSIONS.each_ref().map(|version| version.name)
+}
+
+/// Whether any game version we know the addresses for can read the given boss's HP
+pub fn can_read_boss_health(boss: Boss) -> bool {
+    GAME_VERSIONS.iter().any(|version| version.boss_health_addresses[boss as usize].is_some())
+}
+
+/// Whether any game version we know the addresses for can tell which cutscene is playing
+pub fn can_read_cutscene_id() -> bool {
+    GAME_VERSIONS.iter().any(|version| version.cutscene_id_address.is_some())
+}
+
 fn wait_for_emulator(platform: &PlatformRef) -> Emulator {
     log::info!("Waiting for emulator...");
+    let signatures = GAME_VERSIONS.each_ref().map(GameVersion::ram_signature);
     loop {
-        if let Some(emulator) = platform.search_for_emulator() {
+        if let Some(emulator) = platform.search_for_emulator(&signatures) {
             return emulator;
         }
 
@@ -132,15 +179,61 @@ fn wait_for_version(emulator: &mut Emulator, platform: &PlatformRef) -> &'static
     }
 }
 
+/// The values the autosplitter checks on every update, copied out of the emulator's memory together
+/// once per update so that they're consistent with each other
+#[derive(Debug, Clone)]
+struct MemorySnapshot {
+    menu_module_id: i16,
+    main_menu_state: i32,
+    map_id: u16,
+    room_id: u16,
+    /// Every stage's flag banks, in the order they're laid out in memory
+    flag_words: [u64; NUM_FLAG_WORDS],
+    inventory_count: u16,
+    inventory: [i16; MAX_ITEMS],
+}
+
+impl MemorySnapshot {
+    fn take(version: &GameVersion, emulator: &Emulator) -> Self {
+        Self {
+            menu_module_id: emulator.read_num(version.menu_module_id_address),
+            main_menu_state: emulator.read_num(version.main_menu_state_address),
+            map_id: emulator.read_num(version.map_id_address),
+            room_id: emulator.read_num(version.room_id_address),
+            flag_words: emulator.read_nums_stable(version.flag_banks_address),
+            inventory_count: emulator.read_num(version.inventory_count_address),
+            inventory: emulator.read_nums_stable(version.inventory_address),
+        }
+    }
+
+    fn flag_word(&self, version: &GameVersion, bank_address: u32) -> u64 {
+        self.flag_words[((bank_address - version.flag_banks_address) / 8) as usize]
+    }
+}
+
 #[derive(Debug)]
 pub struct EmulatorGame {
     version: &'static GameVersion,
     emulator: Emulator,
+    snapshot: MemorySnapshot,
+    run_category: RunCategory,
+    /// Whether the player left the main menu by loading a save and hasn't reached a room yet
+    is_loading_save: bool,
+    /// Whether the player went straight from the main menu into the game by loading a save
+    is_save_load_start: bool,
 }
 
 impl EmulatorGame {
-    pub const fn new(version: &'static GameVersion, emulator: Emulator) -> Self {
-        Self { version, emulator }
+    pub fn new(version: &'static GameVersion, emulator: Emulator) -> Self {
+        let snapshot = MemorySnapshot::take(version, &emulator);
+        Self {
+            version,
+            emulator,
+            snapshot,
+            run_category: RunCategory::AnyPercent,
+            is_loading_save: false,
+            is_save_load_start: false,
+        }
     }
 
     pub fn connect(platform: &PlatformRef) -> Self {
@@ -149,13 +242,38 @@ impl EmulatorGame {
         Self::new(version, emulator)
     }
 
-    pub fn main_menu_state(&self) -> i32 {
-        let menu_module_id: i16 = self.emulator.read_num(self.version.menu_module_id_address);
-        if menu_module_id != self.version.main_menu_module_id {
+    /// Connect to an emulator with the game loaded if there is one, without waiting
+    pub fn try_connect(platform: &PlatformRef) -> Option<Self> {
+        let signatures = GAME_VERSIONS.each_ref().map(GameVersion::ram_signature);
+        let emulator = platform.search_for_emulator(&signatures)?;
+        let version = GameVersion::detect(&emulator)?;
+        Some(Self::new(version, emulator))
+    }
+
+    pub const fn main_menu_state(&self) -> i32 {
+        if self.snapshot.menu_module_id != self.version.main_menu_module_id {
             return -1;
         }
 
-        self.emulator.read_num(self.version.main_menu_state_address)
+        self.snapshot.main_menu_state
+    }
+
+    /// Get the indexes of all the flags that are set for the given stage
+    pub fn set_flags(&self, stage: Stage) -> Vec<u32> {
+        let mut flags = Vec::new();
+        for bank_index in 0..NUM_FLAG_BANKS {
+            let first_flag = bank_index * 64;
+            let (bank_address, _) = self.version.flag_bank_address(stage, first_flag);
+            let bank = self.snapshot.flag_word(self.version, bank_address);
+            flags.extend((0..64).filter(|bit| bank & (1u64 << bit) != 0).map(|bit| first_flag + bit));
+        }
+        flags
+    }
+
+    /// Get the items currently in the player's inventory
+    pub fn inventory(&self) -> Vec<Item> {
+        let num_items = self.snapshot.inventory_count as usize;
+        self.snapshot.inventory[..num_items.min(MAX_ITEMS)].iter().copied().filter_map(Item::from_id).collect()
     }
 
     /// Check that the emulator providing the game memory is still running
@@ -191,27 +309,48 @@ impl Game for EmulatorGame {
             return GameState::Disconnected;
         }
         
-        match self.check_version() {
+        let state = match self.check_version() {
             GameCheck::Same => GameState::Connected,
             GameCheck::Changed => GameState::GameChanged,
-            GameCheck::Unknown => GameState::Disconnected,
+            GameCheck::Unknown => return GameState::Disconnected,
+        };
+
+        let was_at_main_menu = self.is_at_main_menu();
+        self.snapshot = MemorySnapshot::take(self.version, &self.emulator);
+        // starting a new game goes through the new game menu states on the way out of the main
+        // menu, so leaving the main menu without them means a save is being loaded. it only counts
+        // once the player is in a room, so that other screens reached from the main menu don't.
+        if was_at_main_menu && self.main_menu_state() < 0 {
+            self.is_loading_save = true;
+        } else if self.is_at_main_menu() {
+            self.is_loading_save = false;
+        }
+        self.is_save_load_start = self.is_loading_save && self.main_menu_state() < 0 && Map::from_id(self.snapshot.map_id).is_some();
+        if self.is_save_load_start {
+            self.is_loading_save = false;
         }
+        state
     }
     
     fn reconnect(&mut self, platform: &PlatformRef) -> Result<()> {
         if !self.check_emulator() {
-            self.emulator = wait_for_emulator(platform);
+            let signatures = GAME_VERSIONS.each_ref().map(GameVersion::ram_signature);
+            let Some(emulator) = platform.search_for_emulator(&signatures) else {
+                bail!("No emulator found");
+            };
+            self.emulator = emulator;
         }
-        
+
         if !self.search_for_game().is_valid() {
-            self.version = wait_for_version(&mut self.emulator, platform);
+            bail!("Game is not loaded in the emulator");
         }
-        
+
+        self.snapshot = MemorySnapshot::take(self.version, &self.emulator);
         Ok(())
     }
 
-    fn set_run_category(&mut self, _new_category: RunCategory) {
-        // TODO: figure out how to distinguish between starting a new game and loading a save
+    fn set_run_category(&mut self, new_category: RunCategory) {
+        self.run_category = new_category;
     }
 
     fn is_at_main_menu(&self) -> bool {
@@ -219,20 +358,35 @@ impl Game for EmulatorGame {
     }
 
     fn is_new_game_start(&self) -> bool {
-        (NEW_GAME_MENU_STATE..TRAILER_MENU_STATE).contains(&self.main_menu_state())
+        // replay mode is started by loading a clear save. we can't tell a clear save from any
+        // other save, so this is the same as on console: any save loaded from the main menu counts.
+        (self.run_category == RunCategory::ReplayMode && self.is_save_load_start)
+            || (NEW_GAME_MENU_STATE..TRAILER_MENU_STATE).contains(&self.main_menu_state())
+    }
+
+    fn setup_problems(&self) -> Vec<String> {
+        if self.version.validate(&self.emulator) {
+            Vec::new()
+        } else {
+            vec![String::from("The game loaded in the emulator is no longer recognized")]
+        }
+    }
+
+    fn start_room(&self) -> Option<(u16, u16)> {
+        None
     }
 
     fn map_id(&self) -> u16 {
-        self.emulator.read_num(self.version.map_id_address)
+        self.snapshot.map_id
     }
 
     fn room_id(&self) -> u16 {
-        self.emulator.read_num(self.version.room_id_address)
+        self.snapshot.room_id
     }
 
     fn flag(&self, stage: Stage, flag_index: u32) -> bool {
         let (bank_address, bit_value) = self.version.flag_bank_address(stage, flag_index);
-        let bank: u64 = self.emulator.read_num(bank_address);
+        let bank = self.snapshot.flag_word(self.version, bank_address);
         bank & bit_value != 0
     }
 
@@ -247,9 +401,30 @@ impl Game for EmulatorGame {
     }
 
     fn has_item(&self, item_id: Item) -> bool {
-        let num_items: u16 = self.emulator.read_num(self.version.inventory_count_address);
-        let items: [i16; MAX_ITEMS] = self.emulator.read_nums(self.version.inventory_address);
-        items[..num_items as usize].contains(&(item_id as i16))
+        self.inventory().contains(&item_id)
+    }
+
+    fn cutscene_id(&self) -> Option<u16> {
+        self.version.cutscene_id_address.map(|address| self.emulator.read_num(address))
+    }
+
+    fn igt(&self) -> Option<Duration> {
+        let ticks: u32 = self.emulator.read_num(self.version.igt_address?);
+        Some(Duration::from_secs_f64(f64::from(ticks) / f64::from(IGT_TICKS_PER_SECOND)))
+    }
+
+    fn boss_health(&self, boss: Boss) -> Option<u16> {
+        self.version.boss_health_addresses[boss as usize].map(|address| self.emulator.read_num(address))
+    }
+
+    fn knock_state(&self) -> Option<KnockState> {
+        Some(if self.flag(Stage::C, KNOCK_SUCCEEDED_FLAG) {
+            KnockState::Succeeded
+        } else if self.flag(Stage::C, KNOCK_LEARNED_FLAG) {
+            KnockState::Learned
+        } else {
+            KnockState::NotLearned
+        })
     }
 }
 
@@ -1,12 +1,22 @@
 use anyhow::{Result, bail};
+use clap::ValueEnum;
 use opencv::prelude::*;
-use opencv::core::{CV_32F, CV_8UC1, CV_8UC3, CV_32FC1, Point3_, Rect, Size, ElemMul, sum_elems};
+use opencv::core::{CV_32F, CV_8UC1, CV_8UC3, CV_32FC1, Point3_, Rect, Size, Vector, ElemMul, extract_channel, mean_def, sum_elems};
 //use opencv::highgui::{destroy_all_windows, imshow, wait_key_def};
-use opencv::imgproc::{COLOR_BGR2GRAY, cvt_color_def, resize_def};
+use opencv::imgcodecs::{IMWRITE_JPEG_QUALITY, imwrite};
+use opencv::imgproc::{COLOR_BGR2GRAY, COLOR_GRAY2BGR, INTER_AREA, INTER_LINEAR, INTER_NEAREST, cvt_color_def, resize};
 use serde::{Deserialize, Serialize};
 
 const GRAYSCALE_NORM: f64 = 1.0 / 255.0;
-const BLACK_MAX: u8 = 10;
+// capture cards and scalers that use limited-range video show black as 16, and scalers can add
+// a little noise on top of that
+const BLACK_MAX: u8 = 24;
+/// Fraction of a row or column's pixels that must be lit for it to count as part of the picture,
+/// so that stray noise and ringing from a scaler in the borders doesn't
+const PICTURE_MIN_LIT: f64 = 0.02;
+/// Calibration shrinks high-resolution captures (e.g. 1080p from an upscaler) to this multiple of
+/// the background size before searching, so the search takes the same time at any resolution
+const SEARCH_SCALE: i32 = 2;
 pub const MATCH_THRESHOLD: f64 = 0.65;
 pub const BACKGROUND_WIDTH: i32 = 320;
 pub const BACKGROUND_HEIGHT: i32 = 240;
@@ -14,6 +24,11 @@ const SEARCH_X: i32 = 12;
 const SEARCH_Y: i32 = 9;
 const MIN_SEARCH_WIDTH: i32 = BACKGROUND_WIDTH - SEARCH_X;
 const MIN_SEARCH_HEIGHT: i32 = BACKGROUND_HEIGHT - SEARCH_Y;
+const SCREENSHOT_WIDTH: i32 = 480;
+const SCREENSHOT_QUALITY: i32 = 80;
+/// Narrowest black bar at the side of the picture that we'll use to tell whether the picture has
+/// been stretched
+const MIN_BAR_WIDTH: i32 = 8;
 
 pub fn gray_float(mat: Mat) -> Result<Mat> {
     let mat = if mat.typ() == CV_8UC1 {
@@ -55,8 +70,11 @@ fn crop(mat: &Mat, x: i32, y: i32, width: i32, height: i32) -> Result<Mat> {
 }
 
 fn scale_to(mat: &Mat, width: i32, height: i32) -> Result<Mat> {
+    // averaging areas avoids aliasing when shrinking a high-resolution capture down to the size of
+    // the background, but it's no good for enlarging
+    let interpolation = if width < mat.cols() || height < mat.rows() { INTER_AREA } else { INTER_LINEAR };
     let mut scaled = Mat::default();
-    resize_def(mat, &mut scaled, Size::new(width, height))?;
+    resize(mat, &mut scaled, Size::new(width, height), 0.0, 0.0, interpolation)?;
 
     Ok(scaled)
 }
@@ -169,6 +187,30 @@ pub struct CaptureTransformJson {
     brh: i32,
 }
 
+/// How the capture device or scaler is currently displaying the game
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AspectRatio {
+    /// The picture is displayed as it was during calibration
+    Standard,
+    /// The picture has been stretched horizontally to fill the frame. Some consoles and scalers
+    /// switch to this while FMVs play.
+    Stretched,
+}
+
+/// How to handle an interlaced capture. A real console's 480i output arrives as two fields woven
+/// together, and anything that moves between one field and the next leaves combing that drags
+/// match scores down.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum Deinterlace {
+    /// Use the capture as it is
+    #[default]
+    Off,
+    /// Keep only one field and stretch it back to the full height
+    Bob,
+    /// Keep both fields, blending each pair of lines together so they can't comb
+    Weave,
+}
+
 #[derive(Debug, Clone)]
 pub struct CaptureTransform {
     capture_roi: Rect,
@@ -200,19 +242,127 @@ impl CaptureTransform {
         }
     }
 
+    /// The same transform with the capture region moved by the given number of pixels
+    pub const fn shifted(&self, dx: i32, dy: i32) -> Self {
+        let roi = self.capture_roi;
+        Self {
+            capture_roi: Rect::new(roi.x + dx, roi.y + dy, roi.width, roi.height),
+            bg_roi: self.bg_roi,
+        }
+    }
+
+    /// Whether the capture region lies entirely within a frame of the given size
+    const fn fits(&self, frame_width: i32, frame_height: i32) -> bool {
+        let roi = self.capture_roi;
+        roi.x >= 0 && roi.y >= 0 && roi.x + roi.width <= frame_width && roi.y + roi.height <= frame_height
+    }
+
     pub fn transform_bg(&self, mat: &Mat) -> Result<Mat> {
         crop(mat, self.bg_roi.x, self.bg_roi.y, self.bg_roi.width, self.bg_roi.height)
     }
 
-    pub fn transform_capture(&self, mat: &Mat) -> Result<Mat> {
+    /// The black bars at the left and right of the picture when it was calibrated, if they're wide
+    /// enough to check
+    fn side_bars(&self, frame_width: i32) -> Option<(Rect, Rect)> {
+        let right_x = self.capture_roi.x + self.capture_roi.width;
+        let right_width = frame_width - right_x;
+        if self.capture_roi.x < MIN_BAR_WIDTH || right_width < MIN_BAR_WIDTH {
+            return None;
+        }
+
+        Some((
+            Rect::new(0, self.capture_roi.y, self.capture_roi.x, self.capture_roi.height),
+            Rect::new(right_x, self.capture_roi.y, right_width, self.capture_roi.height),
+        ))
+    }
+
+    /// Work out whether the picture has been stretched since calibration by checking whether
+    /// there's anything in the bars that were black at the time
+    pub fn detect_aspect_ratio(&self, mat: &Mat) -> Result<AspectRatio> {
+        let Some((left_bar, right_bar)) = self.side_bars(mat.cols()) else {
+            // with no bars, there's no room to stretch into
+            return Ok(AspectRatio::Standard);
+        };
+
+        let is_bar_lit = |bar: Rect| -> Result<bool> {
+            let brightness = mean_def(&mat.roi(bar)?)?;
+            Ok(brightness.0[..3].iter().any(|channel| *channel >= BLACK_MAX as f64))
+        };
+
+        if is_bar_lit(left_bar)? && is_bar_lit(right_bar)? {
+            Ok(AspectRatio::Stretched)
+        } else {
+            Ok(AspectRatio::Standard)
+        }
+    }
+
+    /// The part of the capture frame that the picture occupies in the given aspect ratio
+    const fn capture_roi_for(&self, aspect_ratio: AspectRatio, frame_width: i32) -> Rect {
+        match aspect_ratio {
+            AspectRatio::Standard => self.capture_roi,
+            // a stretched picture fills the frame horizontally, but the vertical position is the same
+            AspectRatio::Stretched => Rect::new(0, self.capture_roi.y, frame_width, self.capture_roi.height),
+        }
+    }
+
+    pub fn transform_capture(&self, mat: &Mat, aspect_ratio: AspectRatio) -> Result<Mat> {
+        // scale down before converting so that a high-resolution capture costs no more than a
+        // native one
+        let roi = self.capture_roi_for(aspect_ratio, mat.cols());
+        let cropped = crop(mat, roi.x, roi.y, roi.width, roi.height)?;
+        let scaled = scale_to(&cropped, self.bg_roi.width, self.bg_roi.height)?;
+        // captures taken from YUV are already grayscale
+        if scaled.typ() == CV_8UC1 {
+            return gray_float(scaled);
+        }
         let mut grayscale = Mat::default();
-        cvt_color_def(mat, &mut grayscale, COLOR_BGR2GRAY)?;
-        let grayscale = gray_float(grayscale)?;
-        let cropped = crop(&grayscale, self.capture_roi.x, self.capture_roi.y, self.capture_roi.width, self.capture_roi.height)?;
-        scale_to(&cropped, self.bg_roi.width, self.bg_roi.height)
+        cvt_color_def(&scaled, &mut grayscale, COLOR_BGR2GRAY)?;
+        gray_float(grayscale)
     }
 }
 
+/// YUV layouts we can take the luma from without converting the frame to color first
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RawFormat {
+    /// Packed 4:2:2 with luma first (YUYV, also called YUY2)
+    Yuyv,
+    /// Packed 4:2:2 with chroma first
+    Uyvy,
+    /// Planar 4:2:0 with a full-size luma plane followed by interleaved chroma
+    Nv12,
+}
+
+impl RawFormat {
+    pub fn from_fourcc(fourcc: &str) -> Option<Self> {
+        match fourcc {
+            "YUYV" | "YUY2" => Some(Self::Yuyv),
+            "UYVY" => Some(Self::Uyvy),
+            "NV12" => Some(Self::Nv12),
+            _ => None,
+        }
+    }
+
+    /// Size in bytes of a frame with the given dimensions
+    const fn frame_size(&self, width: i32, height: i32) -> usize {
+        let pixels = width as usize * height as usize;
+        match self {
+            Self::Yuyv | Self::Uyvy => pixels * 2,
+            Self::Nv12 => pixels * 3 / 2,
+        }
+    }
+}
+
+/// The format and dimensions of the unconverted frames a capture device is giving us. Backends
+/// don't agree on what shape to hand these over in, so we only rely on the bytes being in order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RawLayout {
+    pub format: RawFormat,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// A frame from the capture, either in color or, if it came from the device as YUV, as just the
+/// luma
 #[derive(Debug, Clone)]
 pub struct CaptureImage(Mat);
 
@@ -225,27 +375,89 @@ impl CaptureImage {
         Ok(Self(mat))
     }
 
+    /// Take the luma straight out of an unconverted YUV frame. Since we only match in grayscale,
+    /// this skips the conversion to color and back that would otherwise happen on every frame.
+    pub fn from_raw(mat: Mat, layout: RawLayout) -> Result<Self> {
+        let RawLayout { format, width, height } = layout;
+        let expected_size = format.frame_size(width, height);
+        if !mat.is_continuous() || mat.total() * mat.elem_size()? != expected_size {
+            bail!("Raw capture frame is not a {width}x{height} {format:?} frame");
+        }
+
+        let luma = match format {
+            RawFormat::Yuyv | RawFormat::Uyvy => {
+                let packed = mat.reshape(2, height)?;
+                let mut luma = Mat::default();
+                extract_channel(&*packed, &mut luma, if format == RawFormat::Yuyv { 0 } else { 1 })?;
+                luma
+            }
+            RawFormat::Nv12 => {
+                let planes = mat.reshape(1, height * 3 / 2)?;
+                crop(&planes, 0, 0, width, height)?
+            }
+        };
+
+        Ok(Self(luma))
+    }
+
+    /// The capture in 8-bit grayscale
+    pub fn luma(&self) -> Result<Mat> {
+        if self.0.typ() == CV_8UC1 {
+            return Ok(self.0.try_clone()?);
+        }
+
+        let mut luma = Mat::default();
+        cvt_color_def(&self.0, &mut luma, COLOR_BGR2GRAY)?;
+        Ok(luma)
+    }
+
     pub fn find_transform(&self, background: &Mat, mask: &Mat) -> Result<CaptureTransform> {
-        // crop any black bars to find the actual game display within the capture
-        let mut y_min = self.0.rows() - 1;
-        let mut y_max = 0;
-        let mut x_min = self.0.cols() - 1;
-        let mut x_max = 0;
-
-        for x in 0..self.0.cols() {
-            for y in 0..self.0.rows() {
+        // looking for the borders works in color, and calibration doesn't happen often enough for
+        // the conversion to matter
+        if self.0.typ() == CV_8UC1 {
+            let mut color = Mat::default();
+            cvt_color_def(&self.0, &mut color, COLOR_GRAY2BGR)?;
+            return Self(color).find_transform(background, mask);
+        }
+
+        // crop any black bars to find the actual game display within the capture. count how much
+        // of each row and column is lit rather than taking the first lit pixel, because scalers
+        // can leave noise in the borders.
+        let (rows, cols) = (self.0.rows(), self.0.cols());
+        let mut row_lit = vec![0; rows as usize];
+        let mut col_lit = vec![0; cols as usize];
+        for y in 0..rows {
+            for x in 0..cols {
                 let pixel = self.0.at_2d(y, x)?;
                 if !is_black(pixel) {
-                    y_min = y_min.min(y);
-                    y_max = y_max.max(y);
-                    x_min = x_min.min(x);
-                    x_max = x_max.max(x);
+                    row_lit[y as usize] += 1;
+                    col_lit[x as usize] += 1;
                 }
             }
         }
 
-        let capture_roi = Rect::new(x_min, y_min, x_max - x_min, y_max - y_min);
-        let cropped = self.0.roi(capture_roi)?;
+        let is_row_lit = |count: &i32| *count as f64 >= cols as f64 * PICTURE_MIN_LIT;
+        let is_col_lit = |count: &i32| *count as f64 >= rows as f64 * PICTURE_MIN_LIT;
+        let (Some(y_min), Some(y_max), Some(x_min), Some(x_max)) = (
+            row_lit.iter().position(is_row_lit),
+            row_lit.iter().rposition(is_row_lit),
+            col_lit.iter().position(is_col_lit),
+            col_lit.iter().rposition(is_col_lit),
+        ) else {
+            bail!("Capture is blank");
+        };
+
+        let (x_min, y_min) = (x_min as i32, y_min as i32);
+        let capture_roi = Rect::new(x_min, y_min, x_max as i32 - x_min + 1, y_max as i32 - y_min + 1);
+        let cropped = crop(&self.0, capture_roi.x, capture_roi.y, capture_roi.width, capture_roi.height)?;
+        // shrink high-resolution captures once up front instead of for every size we try
+        let max_width = BACKGROUND_WIDTH * SEARCH_SCALE;
+        let max_height = BACKGROUND_HEIGHT * SEARCH_SCALE;
+        let cropped = if cropped.cols() > max_width || cropped.rows() > max_height {
+            scale_to(&cropped, max_width.min(cropped.cols()), max_height.min(cropped.rows()))?
+        } else {
+            cropped
+        };
         let mut grayscale = Mat::default();
         cvt_color_def(&cropped, &mut grayscale, COLOR_BGR2GRAY)?;
         let grayscale = gray_float(grayscale)?;
@@ -281,7 +493,51 @@ impl CaptureImage {
         Ok(CaptureTransform::new(capture_roi, best_match.1))
     }
 
-    pub fn transform(&self, transform: &CaptureTransform) -> Result<Mat> {
-        transform.transform_capture(&self.0)
+    /// Remove combing from an interlaced capture. The capture stays the same size so that the
+    /// calibration still applies.
+    pub fn deinterlace(self, deinterlace: Deinterlace) -> Result<Self> {
+        // the backgrounds we match against are only 240 lines tall, so halving the height of a
+        // 480-line capture costs us nothing
+        let interpolation = match deinterlace {
+            Deinterlace::Off => return Ok(self),
+            // nearest neighbor keeps every other line as it is
+            Deinterlace::Bob => INTER_NEAREST,
+            // area averaging mixes each line with the one from the other field next to it
+            Deinterlace::Weave => INTER_AREA,
+        };
+
+        let (width, height) = (self.0.cols(), self.0.rows());
+        let mut field = Mat::default();
+        resize(&self.0, &mut field, Size::new(width, height / 2), 0.0, 0.0, interpolation)?;
+        let mut full = Mat::default();
+        resize(&field, &mut full, Size::new(width, height), 0.0, 0.0, INTER_LINEAR)?;
+        Ok(Self(full))
+    }
+
+    pub fn aspect_ratio(&self, transform: &CaptureTransform) -> Result<AspectRatio> {
+        transform.detect_aspect_ratio(&self.0)
+    }
+
+    /// Whether the given transform can be applied to this capture without going off the edge
+    pub fn fits(&self, transform: &CaptureTransform) -> bool {
+        transform.fits(self.0.cols(), self.0.rows())
+    }
+
+    pub fn transform(&self, transform: &CaptureTransform, aspect_ratio: AspectRatio) -> Result<Mat> {
+        transform.transform_capture(&self.0, aspect_ratio)
+    }
+
+    /// Save a downscaled copy of the capture as a JPEG
+    pub fn save_screenshot(&self, path: &str) -> Result<()> {
+        let height = self.0.rows() * SCREENSHOT_WIDTH / self.0.cols().max(1);
+        let mut scaled = Mat::default();
+        resize(&self.0, &mut scaled, Size::new(SCREENSHOT_WIDTH, height), 0.0, 0.0, INTER_AREA)?;
+
+        let params = Vector::from_slice(&[IMWRITE_JPEG_QUALITY, SCREENSHOT_QUALITY]);
+        if !imwrite(path, &scaled, &params)? {
+            bail!("Failed to write screenshot {path}");
+        }
+
+        Ok(())
     }
 }
\ No newline at end of file
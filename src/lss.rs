@@ -1,74 +1,222 @@
-use std::fmt::Display;
-use std::io::{BufRead, BufReader, Error as IoError, ErrorKind, Write};
-use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::fmt::{Debug, Display};
+use std::io::{self, BufRead, BufReader, Error as IoError, ErrorKind, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
 use std::thread;
 use std::time::Duration;
 
 use anyhow::{anyhow, bail, Result};
 
-const MAX_RETRIES: u8 = 3;
-const RETRY_DELAY: Duration = Duration::from_millis(500);
+use crate::timer::{TimerBackend, TimerPhase, format_time};
+
+mod websocket;
+use websocket::WebSocketConnection;
+
 const SOCKET_TIMEOUT: Duration = Duration::from_secs(1);
+/// Most responses we'll skip over while waiting for the answer to a ping
+const MAX_STRAY_RESPONSES: usize = 16;
+#[cfg(windows)]
+const PIPE_PATH: &str = r"\\.\pipe\LiveSplit";
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum TimerPhase {
-    NotRunning,
-    Running,
-    Ended,
-    Paused,
+/// How hard to try when a LiveSplit command fails before deciding the connection is lost
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// How many more times to try after the first attempt fails
+    pub retries: u8,
+    /// How long to wait before the first retry
+    pub delay: Duration,
+    /// What to multiply the delay by after each retry
+    pub backoff: f64,
+}
+
+impl RetryPolicy {
+    fn delay(&self, retry: u8) -> Duration {
+        self.delay.mul_f64(self.backoff.powi(retry as i32))
+    }
 }
 
-impl TimerPhase {
-    fn try_from_raw(s: &[u8]) -> Option<TimerPhase> {
-        match s {
-            b"NotRunning" => Some(TimerPhase::NotRunning),
-            b"Running" => Some(TimerPhase::Running),
-            b"Ended" => Some(TimerPhase::Ended),
-            b"Paused" => Some(TimerPhase::Paused),
-            _ => None,
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            delay: Duration::from_millis(500),
+            backoff: 1.0,
         }
     }
 }
 
+/// Whether an error just means LiveSplit didn't answer before the socket timeout
+fn is_timeout(error: &IoError) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+fn strip_line_ending(buf: &mut Vec<u8>) {
+    // strip the trailing newline
+    buf.pop();
+    // strip any trailing carriage return
+    if buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+}
+
+/// A way of exchanging LiveSplit Server commands and responses
+trait Connection: Debug {
+    fn send_all(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Read a single response into the buffer, including the line ending. Returns 0 if the
+    /// connection was closed.
+    fn recv_line(&mut self, buf: &mut Vec<u8>) -> io::Result<usize>;
+
+    fn shutdown(&mut self);
+
+    /// Whether reads give up after a timeout instead of blocking until there's a response
+    fn can_time_out(&self) -> bool {
+        true
+    }
+}
+
+impl Connection for BufReader<TcpStream> {
+    fn send_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.get_mut().write_all(data)
+    }
+
+    fn recv_line(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        self.read_until(b'\n', buf)
+    }
+
+    fn shutdown(&mut self) {
+        // doesn't matter if the shutdown fails as the connection appears to be hosed anyway
+        let _ = self.get_mut().shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(windows)]
+impl Connection for BufReader<std::fs::File> {
+    fn send_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.get_mut().write_all(data)
+    }
+
+    fn recv_line(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        self.read_until(b'\n', buf)
+    }
+
+    fn shutdown(&mut self) {
+        // the pipe is closed when the handle is dropped
+    }
+
+    fn can_time_out(&self) -> bool {
+        false
+    }
+}
+
+impl Connection for WebSocketConnection {
+    fn send_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.send_message(data)
+    }
+
+    fn recv_line(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        self.recv_message(buf)
+    }
+
+    fn shutdown(&mut self) {
+        WebSocketConnection::shutdown(self);
+    }
+}
+
+/// Which revision of the LiveSplit Server protocol the server speaks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    /// Older servers answer queries only, and silently ignore commands they don't know
+    Legacy,
+    /// Newer servers answer `ping`, and may answer commands that older servers didn't. To stay in
+    /// step, every command is followed by a ping, and anything the server says before the pong is
+    /// skipped.
+    Modern,
+}
+
+/// Where to find LiveSplit Server
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LiveSplitEndpoint {
+    Tcp(SocketAddr),
+    Pipe,
+    WebSocket(SocketAddr),
+}
+
+impl LiveSplitEndpoint {
+    fn connect(&self) -> Result<Box<dyn Connection>> {
+        Ok(match self {
+            Self::Tcp(addr) => {
+                // a host that's gone quiet would otherwise leave us waiting on the OS's connect
+                // timeout, which can be minutes
+                let connection = TcpStream::connect_timeout(addr, SOCKET_TIMEOUT)?;
+                connection.set_read_timeout(Some(SOCKET_TIMEOUT))?;
+                connection.set_write_timeout(Some(SOCKET_TIMEOUT))?;
+                Box::new(BufReader::new(connection))
+            }
+            #[cfg(windows)]
+            Self::Pipe => {
+                let pipe = std::fs::OpenOptions::new().read(true).write(true).open(PIPE_PATH)?;
+                Box::new(BufReader::new(pipe))
+            }
+            #[cfg(not(windows))]
+            Self::Pipe => bail!("LiveSplit's named pipe is only available on Windows"),
+            Self::WebSocket(addr) => Box::new(WebSocketConnection::connect(*addr, SOCKET_TIMEOUT)?),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct LiveSplit {
-    addr: SocketAddr,
-    connection: BufReader<TcpStream>,
+    endpoint: LiveSplitEndpoint,
+    connection: Box<dyn Connection>,
     is_connected: bool,
+    retry_policy: RetryPolicy,
+    protocol: Protocol,
 }
 
 impl LiveSplit {
-    pub fn create(port: u16) -> Result<Self> {
-        let addr = ("127.0.0.1", port).to_socket_addrs()?.next().unwrap();
-        let connection = TcpStream::connect(addr)?;
-        connection.set_read_timeout(Some(SOCKET_TIMEOUT))?;
-        connection.set_write_timeout(Some(SOCKET_TIMEOUT))?;
-        let connection = BufReader::new(connection);
-        log::info!("Successfully connected to LiveSplit");
+    /// Open a connection to LiveSplit without announcing it
+    pub fn open(endpoint: LiveSplitEndpoint) -> Result<Self> {
+        let connection = endpoint.connect()?;
 
         Ok(Self {
-            addr,
+            endpoint,
             connection,
             is_connected: true,
+            retry_policy: RetryPolicy::default(),
+            protocol: Protocol::Legacy,
         })
     }
 
-    pub fn try_reconnect(&mut self) -> Result<()> {
-        self.connection = BufReader::new(TcpStream::connect(self.addr)?);
-        self.is_connected = true;
-        log::info!("LiveSplit connection re-established");
-        Ok(())
+    /// Find out which protocol revision the server speaks. Older servers don't answer pings, so
+    /// with them, this waits out the socket timeout.
+    pub fn detect_protocol(mut self) -> Result<Self> {
+        self.protocol = self.query_protocol()?;
+        log::debug!("LiveSplit server speaks the {:?} protocol", self.protocol);
+        Ok(self)
     }
 
-    pub const fn is_connected(&self) -> bool {
-        self.is_connected
+    fn query_protocol(&mut self) -> Result<Protocol> {
+        if !self.connection.can_time_out() {
+            // an older server would leave us waiting for an answer forever
+            return Ok(Protocol::Legacy);
+        }
+
+        self.send(b"ping\n")?;
+        Ok(match self.recv_optional()? {
+            Some(_) => Protocol::Modern,
+            None => Protocol::Legacy,
+        })
+    }
+
+    pub const fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
     }
 
     fn connection_lost<T: Display>(&mut self, error: &T) {
         log::error!("LiveSplit connection lost: {error}");
         self.is_connected = false;
-        // doesn't matter if the shutdown fails as the connection appears to be hosed anyway
-        let _ = self.connection.get_mut().shutdown(Shutdown::Both);
+        self.connection.shutdown();
     }
 
     fn handle_error(&mut self, error: &IoError) {
@@ -82,10 +230,34 @@ impl LiveSplit {
         }
     }
 
+    /// Send a command that doesn't have an answer
+    fn command(&mut self, data: &[u8]) -> Result<()> {
+        self.send(data)?;
+        if self.protocol == Protocol::Modern {
+            self.wait_for_pong()?;
+        }
+        Ok(())
+    }
+
+    /// Ping the server and skip everything it says until it answers
+    fn wait_for_pong(&mut self) -> Result<()> {
+        self.send(b"ping\n")?;
+        for _ in 0..MAX_STRAY_RESPONSES {
+            let response = self.recv()?;
+            if response == b"pong" {
+                return Ok(());
+            }
+            log::debug!("Skipping LiveSplit response {}", String::from_utf8_lossy(&response));
+        }
+
+        bail!("LiveSplit never answered ping");
+    }
+
     pub fn send(&mut self, data: &[u8]) -> Result<()> {
-        for _ in 0..MAX_RETRIES {
-            match self.connection.get_mut().write_all(data) {
+        for attempt in 0..=self.retry_policy.retries {
+            match self.connection.send_all(data) {
                 Ok(_) => return Ok(()),
+                Err(e) if is_timeout(&e) => log::warn!("Timed out sending to LiveSplit. Retrying..."),
                 Err(e) => {
                     self.handle_error(&e);
                     if !self.is_connected {
@@ -94,7 +266,7 @@ impl LiveSplit {
                     }
 
                     log::warn!("LiveSplit communication error: {e}. Retrying...");
-                    thread::sleep(RETRY_DELAY);
+                    thread::sleep(self.retry_policy.delay(attempt));
                 }
             }
         }
@@ -104,22 +276,20 @@ impl LiveSplit {
     }
 
     pub fn recv(&mut self) -> Result<Vec<u8>> {
-        for _ in 0..MAX_RETRIES {
-            let mut buf = Vec::new();
-            match self.connection.read_until(b'\n', &mut buf) {
+        // anything read before a timeout is the start of the response, so keep it for the next try
+        let mut buf = Vec::new();
+        for attempt in 0..=self.retry_policy.retries {
+            match self.connection.recv_line(&mut buf) {
                 Ok(0) => {
                     self.connection_lost(&"Connection closed");
                     bail!("Connection closed");
                 }
                 Ok(_) => {
-                    // strip the trailing newline
-                    buf.pop();
-                    // strip any trailing carriage return
-                    if buf.last() == Some(&b'\r') {
-                        buf.pop();
-                    }
+                    strip_line_ending(&mut buf);
                     return Ok(buf);
                 }
+                // the socket timeout already made us wait, so there's no need to wait any longer
+                Err(e) if is_timeout(&e) => log::warn!("Timed out waiting for LiveSplit to respond. Retrying..."),
                 Err(e) => {
                     self.handle_error(&e);
                     if !self.is_connected {
@@ -128,7 +298,7 @@ impl LiveSplit {
                     }
 
                     log::warn!("LiveSplit communication error: {e}. Retrying...");
-                    thread::sleep(RETRY_DELAY);
+                    thread::sleep(self.retry_policy.delay(attempt));
                 }
             }
         }
@@ -137,31 +307,183 @@ impl LiveSplit {
         bail!("Maximum retries exceeded");
     }
 
+    /// Receive the response to a command that not all servers support. Unsupported commands are
+    /// silently ignored by the server, so if we don't get an answer in time, we assume the command
+    /// isn't supported and return None rather than treating it as a connection problem.
+    pub fn recv_optional(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut buf = Vec::new();
+        match self.connection.recv_line(&mut buf) {
+            Ok(0) => {
+                self.connection_lost(&"Connection closed");
+                bail!("Connection closed");
+            }
+            Ok(_) => {
+                strip_line_ending(&mut buf);
+                Ok(Some(buf))
+            }
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => {
+                self.handle_error(&e);
+                Err(e.into())
+            }
+        }
+    }
+
     pub fn recv_int(&mut self) -> Result<i64> {
         let raw = self.recv()?;
         Ok(str::from_utf8(&raw)?.parse()?)
     }
+}
+
+impl TimerBackend for LiveSplit {
+    fn is_connected(&self) -> bool {
+        self.is_connected
+    }
+
+    fn try_reconnect(&mut self) -> Result<()> {
+        self.connection = self.endpoint.connect()?;
+        self.is_connected = true;
+        // LiveSplit may have been updated while we were disconnected
+        self.protocol = self.query_protocol()?;
+        log::info!("LiveSplit connection re-established");
+        Ok(())
+    }
+
+    fn ping(&mut self) -> Result<()> {
+        // any cheap query will do for older servers. unlike other commands, we don't retry,
+        // because the point is to notice quickly when LiveSplit has gone away.
+        self.send(if self.protocol == Protocol::Modern { b"ping\n" } else { b"getsplitindex\n" })?;
+        if self.recv_optional()?.is_none() {
+            self.connection_lost(&"No response to heartbeat");
+            bail!("No response to heartbeat");
+        }
+        Ok(())
+    }
+
+    fn split(&mut self) -> Result<()> {
+        self.command(b"startorsplit\n")
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.command(b"reset\n")
+    }
 
-    pub fn split(&mut self) -> Result<()> {
-        self.send(b"startorsplit\n")
+    fn unsplit(&mut self) -> Result<()> {
+        self.command(b"unsplit\n")
     }
 
-    pub fn reset(&mut self) -> Result<()> {
-        self.send(b"reset\n")
+    fn skip_split(&mut self) -> Result<()> {
+        self.command(b"skipsplit\n")
     }
 
-    pub fn get_split_index(&mut self) -> Result<i64> {
+    fn get_split_index(&mut self) -> Result<i64> {
         self.send(b"getsplitindex\n")?;
         self.recv_int()
     }
 
-    pub fn get_timer_phase(&mut self) -> Result<TimerPhase> {
+    fn get_segment_count(&mut self) -> Result<Option<i64>> {
+        if !self.connection.can_time_out() {
+            // we'd wait forever for a response if the server doesn't support the command
+            return Ok(None);
+        }
+
+        self.send(b"getsegmentcount\n")?;
+        let Some(response) = self.recv_optional()? else {
+            return Ok(None);
+        };
+
+        Ok(str::from_utf8(&response).ok().and_then(|s| s.parse().ok()))
+    }
+
+    fn get_timer_phase(&mut self) -> Result<TimerPhase> {
         self.send(b"gettimerphase\n")?;
         let response = self.recv()?;
         TimerPhase::try_from_raw(&response).ok_or_else(|| anyhow!("Invalid timer phase received from LiveSplit server"))
     }
 
-    pub fn get_custom_variable_value(&mut self, variable_name: &str) -> Result<Option<String>> {
+    fn set_game_time(&mut self, time: Duration) -> Result<()> {
+        let cmd = format!("setgametime {}\n", format_time(time));
+        self.command(cmd.as_bytes())
+    }
+
+    fn init_game_time(&mut self) -> Result<()> {
+        self.command(b"initgametime\n")
+    }
+
+    fn pause_game_time(&mut self) -> Result<()> {
+        self.command(b"pausegametime\n")
+    }
+
+    fn resume_game_time(&mut self) -> Result<()> {
+        self.command(b"unpausegametime\n")
+    }
+
+    fn get_current_time(&mut self) -> Result<String> {
+        self.send(b"getcurrenttime\n")?;
+        let response = self.recv()?;
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+
+    fn get_final_time(&mut self) -> Result<Option<String>> {
+        if !self.connection.can_time_out() {
+            return Ok(None);
+        }
+
+        self.send(b"getfinaltime\n")?;
+        let Some(response) = self.recv_optional()? else {
+            return Ok(None);
+        };
+        let value = String::from_utf8_lossy(&response);
+        Ok(if value == "-" || value.is_empty() { None } else { Some(value.into_owned()) })
+    }
+
+    fn get_attempt_count(&mut self) -> Result<i64> {
+        self.send(b"getattemptcount\n")?;
+        self.recv_int()
+    }
+
+    fn get_completed_count(&mut self) -> Result<i64> {
+        self.send(b"getcompletedcount\n")?;
+        self.recv_int()
+    }
+
+    fn get_current_split_name(&mut self) -> Result<String> {
+        self.send(b"getcurrentsplitname\n")?;
+        let response = self.recv()?;
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+
+    fn get_split_names(&mut self) -> Result<Option<Vec<String>>> {
+        let Some(segment_count) = self.get_segment_count()? else {
+            return Ok(None);
+        };
+
+        let mut names = Vec::new();
+        for index in 0..segment_count {
+            self.send(format!("getsplitname {index}\n").as_bytes())?;
+            // older servers don't know the command, in which case there's no point asking about
+            // the rest of the segments
+            let Some(response) = self.recv_optional()? else {
+                return Ok(None);
+            };
+            names.push(String::from_utf8_lossy(&response).into_owned());
+        }
+
+        Ok(Some(names))
+    }
+
+    fn get_comparison_split_time(&mut self) -> Result<Option<String>> {
+        self.send(b"getcomparisonsplittime\n")?;
+        let response = self.recv()?;
+        let value = str::from_utf8(&response)?;
+        if value == "-" || value.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(value.to_string()))
+        }
+    }
+
+    fn get_custom_variable_value(&mut self, variable_name: &str) -> Result<Option<String>> {
         let mut cmd = Vec::with_capacity(24 + variable_name.len());
         cmd.extend_from_slice(b"getcustomvariablevalue ");
         cmd.extend_from_slice(variable_name.as_bytes());
@@ -176,4 +498,20 @@ impl LiveSplit {
             Ok(Some(value.to_string()))
         }
     }
+
+    fn set_custom_variable(&mut self, variable_name: &str, value: &str) -> Result<()> {
+        // the server takes the name and value as a JSON array so that they can contain spaces
+        let cmd = format!("setcustomvariable {}\n", serde_json::to_string(&[variable_name, value])?);
+        self.command(cmd.as_bytes())
+    }
+
+    // these two only change the run's names, not its attempt history. servers that don't know them
+    // ignore them without answering, so there's nothing to wait for either way.
+    fn set_game_name(&mut self, game_name: &str) -> Result<()> {
+        self.command(format!("setgamename {game_name}\n").as_bytes())
+    }
+
+    fn set_category_name(&mut self, category_name: &str) -> Result<()> {
+        self.command(format!("setcategoryname {category_name}\n").as_bytes())
+    }
 }
\ No newline at end of file
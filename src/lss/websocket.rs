@@ -0,0 +1,318 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+
+use super::is_timeout;
+
+const WEBSOCKET_PATH: &str = "/livesplit";
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+const FIN_BIT: u8 = 0x80;
+const MASK_BIT: u8 = 0x80;
+/// Longest message we'll accept from the server. LiveSplit's responses are all short, so anything
+/// longer means something has gone wrong.
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024;
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Some bytes that are different every time. They don't need to be cryptographically secure; the
+/// protocol only uses them to keep proxies from getting confused.
+fn nonce<const N: usize>() -> [u8; N] {
+    let mut state = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+        | 1;
+    let mut bytes = [0u8; N];
+    for b in &mut bytes {
+        // xorshift
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        *b = state as u8;
+    }
+    bytes
+}
+
+fn base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, b)| bits | (*b as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Once part of a frame has been read, we can't tell where the next one starts, so a timeout can't
+/// be retried and has to be treated as losing the connection
+fn mid_frame_error(error: io::Error) -> io::Error {
+    if is_timeout(&error) {
+        io::Error::new(ErrorKind::ConnectionAborted, "Timed out in the middle of a WebSocket frame")
+    } else {
+        error
+    }
+}
+
+/// Minimal WebSocket client for talking to LiveSplit Server
+#[derive(Debug)]
+pub struct WebSocketConnection {
+    stream: BufReader<TcpStream>,
+}
+
+impl WebSocketConnection {
+    pub fn connect(addr: SocketAddr, timeout: Duration) -> Result<Self> {
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut stream = BufReader::new(stream);
+
+        let key = base64(&nonce::<16>());
+        write!(
+            stream.get_mut(),
+            "GET {WEBSOCKET_PATH} HTTP/1.1\r\nHost: {addr}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )?;
+
+        let mut status = String::new();
+        stream.read_line(&mut status)?;
+        if status.split_whitespace().nth(1) != Some("101") {
+            bail!("LiveSplit WebSocket server refused the connection: {}", status.trim());
+        }
+
+        // we don't need anything from the headers
+        loop {
+            let mut header = String::new();
+            if stream.read_line(&mut header)? == 0 {
+                bail!("LiveSplit WebSocket server closed the connection");
+            }
+            if header.trim().is_empty() {
+                break;
+            }
+        }
+
+        Ok(Self { stream })
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(FIN_BIT | opcode);
+        // clients always have to mask their frames
+        match payload.len() {
+            len @ 0..126 => frame.push(MASK_BIT | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(MASK_BIT | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(MASK_BIT | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        let mask = nonce::<4>();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+
+        self.stream.get_mut().write_all(&frame)
+    }
+
+    /// Send a message to the server. Any trailing line ending is dropped since each message is
+    /// its own frame.
+    pub fn send_message(&mut self, data: &[u8]) -> io::Result<()> {
+        let data = data.strip_suffix(b"\n").unwrap_or(data);
+        self.send_frame(OPCODE_TEXT, data)
+    }
+
+    /// Read one frame, returning whether it's the last one of its message, its opcode, and its
+    /// unmasked payload. `max_len` is the longest payload to accept.
+    fn recv_frame(&mut self, max_len: usize) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut header = [0u8; 2];
+        self.stream.read_exact(&mut header)?;
+        let is_final = header[0] & FIN_BIT != 0;
+        let opcode = header[0] & 0x0F;
+        let is_masked = header[1] & MASK_BIT != 0;
+        let len = match header[1] & !MASK_BIT {
+            126 => {
+                let mut len = [0u8; 2];
+                self.stream.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+        let len = match usize::try_from(len) {
+            Ok(len) if len <= max_len => len,
+            // there's no skipping a frame this long, so give up on the connection
+            _ => return Err(io::Error::new(ErrorKind::ConnectionAborted, format!("WebSocket frame of {len} bytes is too long"))),
+        };
+
+        let mut mask = [0u8; 4];
+        if is_masked {
+            self.stream.read_exact(&mut mask)?;
+        }
+
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload)?;
+        if is_masked {
+            for (b, m) in payload.iter_mut().zip(mask.iter().cycle()) {
+                *b ^= m;
+            }
+        }
+
+        Ok((is_final, opcode, payload))
+    }
+
+    /// Receive the next message from the server, terminated with a newline to look like the TCP
+    /// server's responses. Returns 0 if the server closed the connection.
+    pub fn recv_message(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        loop {
+            // a timeout while waiting for the next frame can be retried like any other
+            self.stream.fill_buf()?;
+            let max_len = MAX_MESSAGE_LENGTH.saturating_sub(buf.len() - start);
+            let (is_final, opcode, payload) = self.recv_frame(max_len).map_err(mid_frame_error)?;
+
+            match opcode {
+                OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                    buf.extend_from_slice(&payload);
+                    if is_final {
+                        break;
+                    }
+                }
+                OPCODE_CLOSE => return Ok(0),
+                OPCODE_PING => self.send_frame(OPCODE_PONG, &payload)?,
+                // pongs and anything else we don't know about can be ignored
+                _ => (),
+            }
+        }
+
+        if buf.last() != Some(&b'\n') {
+            buf.push(b'\n');
+        }
+        Ok(buf.len() - start)
+    }
+
+    pub fn shutdown(&mut self) {
+        let _ = self.send_frame(OPCODE_CLOSE, &[]);
+        let _ = self.stream.get_mut().shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// A connection to a fake server, skipping the handshake
+    fn connect() -> (WebSocketConnection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (WebSocketConnection { stream: BufReader::new(client) }, server)
+    }
+
+    /// Read a frame the client sent, returning its opcode and unmasked payload
+    fn read_client_frame(server: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 2];
+        server.read_exact(&mut header).unwrap();
+        assert_eq!(header[0] & FIN_BIT, FIN_BIT);
+        assert_eq!(header[1] & MASK_BIT, MASK_BIT);
+        let len = match header[1] & !MASK_BIT {
+            126 => {
+                let mut len = [0u8; 2];
+                server.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut mask = [0u8; 4];
+        server.read_exact(&mut mask).unwrap();
+        let mut payload = vec![0u8; len];
+        server.read_exact(&mut payload).unwrap();
+        let payload = payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m).collect();
+        (header[0] & 0x0F, payload)
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xFF; 16]), "/////////////////////w==");
+    }
+
+    #[test]
+    fn test_send_message() {
+        let (mut connection, mut server) = connect();
+        connection.send_message(b"starttimer\n").unwrap();
+        assert_eq!(read_client_frame(&mut server), (OPCODE_TEXT, b"starttimer".to_vec()));
+
+        let long = vec![b'a'; 300];
+        connection.send_message(&long).unwrap();
+        assert_eq!(read_client_frame(&mut server), (OPCODE_TEXT, long));
+    }
+
+    #[test]
+    fn test_recv_fragmented_message() {
+        let (mut connection, mut server) = connect();
+        // the first fragment is masked, which servers aren't supposed to do but we accept anyway
+        let mask = [1, 2, 3, 4];
+        let masked: Vec<u8> = b"00:0".iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m).collect();
+        server.write_all(&[OPCODE_TEXT, MASK_BIT | 4]).unwrap();
+        server.write_all(&mask).unwrap();
+        server.write_all(&masked).unwrap();
+        server.write_all(&[FIN_BIT | OPCODE_PING, 2, b'h', b'i']).unwrap();
+        server.write_all(&[FIN_BIT | OPCODE_CONTINUATION, 4]).unwrap();
+        server.write_all(b"1.50").unwrap();
+
+        let mut buf = Vec::new();
+        assert_eq!(connection.recv_message(&mut buf).unwrap(), 9);
+        assert_eq!(buf, b"00:01.50\n");
+        assert_eq!(read_client_frame(&mut server), (OPCODE_PONG, b"hi".to_vec()));
+
+        server.write_all(&[FIN_BIT | OPCODE_CLOSE, 0]).unwrap();
+        assert_eq!(connection.recv_message(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_recv_too_long() {
+        let (mut connection, mut server) = connect();
+        server.write_all(&[FIN_BIT | OPCODE_TEXT, 127]).unwrap();
+        server.write_all(&u64::MAX.to_be_bytes()).unwrap();
+
+        let error = connection.recv_message(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let (mut connection, mut server) = connect();
+        connection.stream.get_ref().set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+
+        // nothing has been read yet, so this is an ordinary timeout
+        let error = connection.recv_message(&mut Vec::new()).unwrap_err();
+        assert!(is_timeout(&error));
+
+        server.write_all(&[FIN_BIT | OPCODE_TEXT, 10]).unwrap();
+        server.write_all(b"00:0").unwrap();
+        let error = connection.recv_message(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+    }
+}
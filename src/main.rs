@@ -1,17 +1,42 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use anyhow::{anyhow, Result};
-use clap::{Parser, ValueEnum};
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use log::LevelFilter;
 
 mod autosplitter;
-use autosplitter::AutoSplitter;
+use autosplitter::{AutoSplitter, AutoSplitterConfig, GameTimeOptions, ReportOptions, SaveLoadBehavior};
+mod backup;
+mod capabilities;
+use capabilities::Capabilities;
 mod game;
+use game::{CaptureApi, DeviceMode, FrameSize, GameBackendOptions, GameBackendType, ScreenRegion, Stage};
 mod image;
+use image::Deinterlace;
 mod lss;
+use lss::RetryPolicy;
+mod output;
+use output::{EventOutput, OutputFormat};
 mod platform;
+use platform::EmulatorSearchOptions;
+mod race;
+use race::RaceReporter;
+mod route_editor;
+use route_editor::RouteEditor;
+mod run_info;
+mod split_file;
 mod splits;
-use splits::{Event, CONSOLE_DOOR_SPLITS, DOOR_SPLITS, KEY_EVENT_SPLITS};
+mod summary;
+use splits::{Event, RouteEntry, RouteFile, CONSOLE_DOOR_SPLITS, DOOR_SPLITS, HUNDRED_PERCENT_SPLITS, KEY_EVENT_SPLITS, REPLAY_KEY_EVENT_SPLITS};
+mod timer;
+use timer::TimerBackendType;
+
+/// Fraction of segment names that must match a route for the split type to be inferred from them
+const SEGMENT_NAME_MATCH_MIN: f64 = 0.5;
+/// Environment variable that gives the LiveSplit server's port when --live-split-port isn't used
+const LIVE_SPLIT_PORT_VARIABLE: &str = "LIVESPLIT_PORT";
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum LevelFilterArg {
@@ -57,6 +82,13 @@ enum SplitType {
     RouteDoors,
     /// Split on specific key events - key item pickups, bosses, and hotel progression events
     KeyEvents,
+    /// Key events for replay mode, without the item pickups in stages A and B
+    ReplayKeyEvents,
+    /// Split on each new key item or file picked up, for 100% runs
+    HundredPercent,
+    /// For the item randomizer: split on each new key item picked up and each boss defeated, in
+    /// any order
+    Randomizer,
     // this option is disabled for now because we can't reliably tell the difference between some of
     // the rooms in the hotel, which can lead to the autosplitter getting stuck
     /*/// For console: split on all doors
@@ -68,9 +100,11 @@ enum SplitType {
 impl SplitType {
     const fn splits(&self) -> Option<&'static [Event]> {
         match self {
-            Self::AllDoors /*| Self::AllDoorsConsole*/ => None,
+            Self::AllDoors | Self::Randomizer /*| Self::AllDoorsConsole*/ => None,
             Self::RouteDoors => Some(&DOOR_SPLITS),
             Self::KeyEvents => Some(&KEY_EVENT_SPLITS),
+            Self::ReplayKeyEvents => Some(&REPLAY_KEY_EVENT_SPLITS),
+            Self::HundredPercent => Some(&HUNDRED_PERCENT_SPLITS),
             Self::RouteDoorsConsole => Some(&CONSOLE_DOOR_SPLITS),
         }
     }
@@ -80,6 +114,9 @@ impl SplitType {
             Self::AllDoors => "all-doors",
             Self::RouteDoors => "route-doors",
             Self::KeyEvents => "key-events",
+            Self::ReplayKeyEvents => "replay-key-events",
+            Self::HundredPercent => "hundred-percent",
+            Self::Randomizer => "randomizer",
             //Self::AllDoorsConsole => "all-doors-console",
             Self::RouteDoorsConsole => "route-doors-console",
         }
@@ -88,6 +125,60 @@ impl SplitType {
     const fn is_console(&self) -> bool {
         matches!(self, /*Self::AllDoorsConsole |*/ Self::RouteDoorsConsole)
     }
+
+    /// The split type to use for the same kind of route on an emulator
+    const fn emulator_equivalent(&self) -> Self {
+        match self {
+            Self::RouteDoorsConsole => Self::RouteDoors,
+            _ => *self,
+        }
+    }
+
+    /// Number of segments that a split file for this split type should have, if it's fixed
+    const fn segment_count(&self) -> Option<usize> {
+        match self.splits() {
+            // the last split of the run is on defeating the final boss, which isn't in the route
+            Some(splits) => Some(splits.len() + 1),
+            None => None,
+        }
+    }
+
+    fn from_segment_count(segment_count: usize) -> Option<Self> {
+        Self::value_variants().iter().copied().find(|split_type| split_type.segment_count() == Some(segment_count))
+    }
+
+    /// Guess the split type from the names of the segments in the user's splits by comparing them
+    /// against the names `segment-names` would give each built-in route. Segments are compared in
+    /// order, ignoring subsplit markers and case, and the split type whose names match best wins as
+    /// long as at least half of them match.
+    fn from_segment_names(names: &[String]) -> Option<Self> {
+        let names: Vec<_> = names.iter().map(|name| plain_segment_name(name)).collect();
+        let mut best_match = None;
+        for split_type in Self::value_variants() {
+            let Some(route) = split_type.splits() else {
+                continue;
+            };
+
+            let route_names: Vec<_> = splits::segment_names(route, &[]).iter().map(|name| plain_segment_name(name)).collect();
+            let matches = names.iter().zip(&route_names).filter(|(name, route_name)| name == route_name).count();
+            let score = matches as f64 / names.len().max(route_names.len()) as f64;
+            if score >= SEGMENT_NAME_MATCH_MIN && best_match.is_none_or(|(_, best_score)| score > best_score) {
+                best_match = Some((*split_type, score));
+            }
+        }
+
+        best_match.map(|(split_type, _)| split_type)
+    }
+}
+
+/// A segment name without the subsplit markers that `segment_names` adds
+fn plain_segment_name(name: &str) -> String {
+    let name = name.strip_prefix('-').unwrap_or(name);
+    let name = match name.strip_prefix('{').and_then(|rest| rest.split_once('}')) {
+        Some((_, rest)) => rest,
+        None => name,
+    };
+    name.trim().to_lowercase()
 }
 
 impl TryFrom<&str> for SplitType {
@@ -98,6 +189,9 @@ impl TryFrom<&str> for SplitType {
             "AllDoors" | "all-doors" => Ok(Self::AllDoors),
             "RouteDoors" | "route-doors" => Ok(Self::RouteDoors),
             "KeyEvents" | "key-events" => Ok(Self::KeyEvents),
+            "ReplayKeyEvents" | "replay-key-events" => Ok(Self::ReplayKeyEvents),
+            "HundredPercent" | "hundred-percent" => Ok(Self::HundredPercent),
+            "Randomizer" | "randomizer" => Ok(Self::Randomizer),
             // "AllDoorsConsole" | "all-doors-console" => Ok(Self::AllDoorsConsole),
             "RouteDoorsConsole" | "route-doors-console" => Ok(Self::RouteDoorsConsole),
             _ => Err(anyhow!("Unknown split type: {value}")),
@@ -111,6 +205,8 @@ enum RunCategory {
     AnyPercent,
     /// Replay mode (new game+)
     ReplayMode,
+    /// 100% (new game, collecting every key item, memory chip, and document)
+    HundredPercent,
 }
 
 impl RunCategory {
@@ -118,6 +214,7 @@ impl RunCategory {
         match self {
             Self::AnyPercent => "Any%",
             Self::ReplayMode => "Replay Mode",
+            Self::HundredPercent => "100%",
         }
     }
 }
@@ -129,27 +226,183 @@ impl TryFrom<&str> for RunCategory {
         match value {
             "AnyPercent" | "Any%" | "any-percent" => Ok(Self::AnyPercent),
             "ReplayMode" | "Replay Mode" | "replay-mode" => Ok(Self::ReplayMode),
+            "HundredPercent" | "100%" | "hundred-percent" => Ok(Self::HundredPercent),
             _ => Err(anyhow!("Unknown run category: {value}")),
         }
     }
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Restore a backup of the capture calibration (device.json) or a route file. Backups are made
+    /// automatically whenever one of those files is rewritten.
+    Restore {
+        /// Backup to restore. If not given, the available backups of device.json are listed.
+        backup: Option<PathBuf>,
+    },
+    /// Check a route file for unknown rooms and flags, doors that don't exist, and the wrong
+    /// number of splits
+    ValidateRoute {
+        /// Route file to check
+        route: PathBuf,
+        /// Number of segments your LiveSplit splits have for this route
+        #[arg(long)]
+        segments: Option<usize>,
+    },
+    /// Print the LiveSplit segment names for a route, grouped into subsplits by stage
+    SegmentNames {
+        /// Route file to name the segments of. If not given, the built-in route for the split type
+        /// chosen with --split-type is used.
+        route: Option<PathBuf>,
+    },
+    /// Make a LiveSplit split file for a route, with the segments named and the split type and
+    /// category set so the autosplitter can detect them
+    GenerateLss {
+        /// File to save the splits to
+        output_file: PathBuf,
+        /// Route file to make splits for. If not given, the built-in route for the split type
+        /// chosen with --split-type is used.
+        #[arg(long)]
+        route: Option<PathBuf>,
+    },
+    /// Measure how far the video capture given by --capture-device lags behind the console, for use
+    /// with --capture-latency
+    MeasureLatency,
+    /// Host a race instead of autosplitting. Other autosplitters can report their progress here
+    /// with --race-server, and the race overview can be viewed in a web browser.
+    RaceHost {
+        /// Port to host the race on
+        port: u16,
+        /// Address to listen on. The default only accepts connections from this computer; use
+        /// 0.0.0.0 to let runners on other computers join.
+        #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+        bind: IpAddr,
+    },
+    /// Build a route by playing through it in an emulator. Rooms, flags, and items are offered as
+    /// route events as they happen.
+    EditRoute {
+        /// File to save the route to. If it already exists, new events are added to the end of it.
+        route: PathBuf,
+    },
+    /// Print a JSON report of the emulators, game versions, split types, backends, and events this
+    /// build supports
+    Capabilities,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about)]
 struct Cli {
-    /// Port that the LiveSplit server is running on
-    #[arg(short, long, default_value_t = 16834)]
-    live_split_port: u16,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Address of the computer LiveSplit is running on, for setups where LiveSplit runs on a
+    /// different computer (e.g. a streaming PC) than the autosplitter
+    #[arg(long, default_value = "127.0.0.1")]
+    live_split_host: String,
+    /// Port that the LiveSplit server is running on. If not given, the LIVESPLIT_PORT environment
+    /// variable is used if it's set, and otherwise the usual ports are tried, starting with
+    /// LiveSplit's default of 16834.
+    #[arg(short, long)]
+    live_split_port: Option<u16>,
+    /// How to connect to LiveSplit. The port is ignored when using the named pipe.
+    #[arg(short = 'b', long, visible_alias = "live-split-protocol", value_enum, default_value_t = TimerBackendType::Tcp)]
+    timer_backend: TimerBackendType,
+    /// How many times to retry a LiveSplit command that fails before giving up on the connection
+    #[arg(long, default_value_t = 2)]
+    live_split_retries: u8,
+    /// How long to wait in milliseconds before retrying a failed LiveSplit command
+    #[arg(long, default_value_t = 500)]
+    live_split_retry_delay: u64,
+    /// What to multiply the retry delay by after each retry, e.g. 2 to double it each time
+    #[arg(long, default_value_t = 1.0)]
+    live_split_retry_backoff: f64,
     /// How often to update the state of the game in milliseconds
     #[arg(short, long, default_value_t = 15)]
     update_frequency: u64,
+    /// How to watch the game. If not provided, the emulator is used unless the split type is for
+    /// console runs.
+    #[arg(long, value_enum)]
+    game_backend: Option<GameBackendType>,
     /// When doing console runs, the index of the video capture device to use
     #[arg(short, long, default_value_t = 0)]
     capture_device: i32,
+    /// When doing console runs, which OpenCV backend to open the capture device with
+    #[arg(long, value_enum, default_value_t = CaptureApi::Auto)]
+    capture_api: CaptureApi,
+    /// When doing console runs, the resolution to ask the capture device for (e.g. 1920x1080)
+    #[arg(long)]
+    capture_resolution: Option<FrameSize>,
+    /// When doing console runs, the frame rate to ask the capture device for
+    #[arg(long)]
+    capture_fps: Option<f64>,
+    /// When doing console runs, the pixel format to ask the capture device for, as a FOURCC code
+    /// (e.g. MJPG or YUYV)
+    #[arg(long)]
+    capture_format: Option<String>,
+    /// When doing console runs, read YUV frames (YUYV, UYVY, or NV12) from the capture device as
+    /// they are and match against their brightness directly, instead of converting every frame
+    /// to color first. Falls back to converting if the device or OpenCV backend can't do it.
+    #[arg(long, default_value_t = false)]
+    raw_yuv: bool,
+    /// When doing console runs, how to deinterlace the capture. Use this if the capture shows
+    /// combing (e.g. 480i from a real console). Bob keeps one field and weave blends both.
+    #[arg(long, value_enum, default_value_t = Deinterlace::Off)]
+    deinterlace: Deinterlace,
+    /// When doing console runs, watch this recorded video (e.g. run.mkv) instead of a capture
+    /// device. The video is played back in real time, so the timer sees the run as it happened.
+    #[arg(long, conflicts_with_all = ["screen_region", "capture_window"])]
+    video_file: Option<PathBuf>,
+    /// When doing console runs, capture this region of the screen (X,Y,WIDTH,HEIGHT) instead of a
+    /// capture device, e.g. where an emulator's window is. Needs OpenCV built with GStreamer.
+    #[arg(long, conflicts_with = "capture_window")]
+    screen_region: Option<ScreenRegion>,
+    /// When doing console runs, capture the window with this title instead of a capture device.
+    /// Needs OpenCV built with GStreamer.
+    #[arg(long)]
+    capture_window: Option<String>,
+    /// When doing console runs, the name of the calibration profile to use for the capture device,
+    /// for devices that are used in more than one mode (e.g. 480i and 1080p). Each profile is
+    /// calibrated separately. If not given, the device's default calibration is used.
+    #[arg(long)]
+    calibration_profile: Option<String>,
     /// When doing console runs, force capture calibration even if the specified video capture
     /// device has already been calibrated
     #[arg(short, long, default_value_t = false)]
     force_calibrate: bool,
+    /// When doing console runs, show the calibration preview window even if the saved calibration
+    /// is used, to check that it still lines up
+    #[arg(long, default_value_t = false)]
+    preview_calibration: bool,
+    /// When doing console runs, start the run by loading a save in the given room (e.g. B0112)
+    /// instead of from a new game. Use "detect" to identify the room from the video capture.
+    #[arg(short = 't', long)]
+    start_room: Option<String>,
+    /// When doing console runs, save a small screenshot of the capture to this directory every
+    /// time a split happens. Files are named by split index and time.
+    #[arg(long)]
+    split_screenshots: Option<PathBuf>,
+    /// Read game memory from a GDB remote stub at this address (e.g. localhost:3333) instead of
+    /// searching for a supported emulator
+    #[arg(short = 'd', long)]
+    gdb_address: Option<String>,
+    /// Also look for an emulator whose executable name contains this text and find the game's
+    /// memory by scanning the process. Useful for emulators that aren't supported directly.
+    #[arg(short, long)]
+    emulator_process: Option<String>,
+    /// Read game memory from the shared memory object with this name instead of searching for a
+    /// supported emulator. Useful if the emulator uses a nonstandard name or runs in a sandbox.
+    #[arg(short = 'm', long)]
+    shared_memory: Option<String>,
+    /// Only attach to the emulator with this process ID. Useful if more than one emulator is running.
+    #[arg(short = 'i', long)]
+    emulator_pid: Option<u32>,
+    /// Only attach to emulators whose name (e.g. DuckStation) or executable name contains this text
+    #[arg(short = 'n', long)]
+    emulator_name: Option<String>,
+    /// Comma-separated list of emulator names (e.g. duckstation,pcsx-redux) in order of preference.
+    /// When more than one emulator is running, the autosplitter attaches to the one that comes
+    /// first in this list.
+    #[arg(long, value_delimiter = ',')]
+    emulator_priority: Vec<String>,
     /// Strategy for when to split. If not provided, it will be determined from LiveSplit's split
     /// settings if possible. If the LiveSplit split settings also don't have a valid split type,
     /// defaults to all-doors.
@@ -163,6 +416,106 @@ struct Cli {
     /// What level of logging output to show
     #[arg(short = 'g', long, value_enum, default_value_t = LevelFilterArg::Info)]
     log_level: LevelFilterArg,
+    /// Practice mode: when returning to the main menu during stage B, C, or D, rewind the splits to
+    /// the start of the current stage instead of resetting the whole run
+    #[arg(short, long, default_value_t = false)]
+    stage_resets: bool,
+    /// What to do when you return to the main menu during a run. With resume, the timer keeps
+    /// running, and if you load a save, the splits continue from the room you're in. Starting a
+    /// new game still resets.
+    #[arg(long, value_enum, default_value_t = SaveLoadBehavior::Reset)]
+    save_loads: SaveLoadBehavior,
+    /// Practice mode: never reset the run when you return to the main menu. The run waits for you
+    /// to load a save and continues from there. Starting a new game still starts a new attempt.
+    #[arg(long, default_value_t = false)]
+    no_auto_reset: bool,
+    /// Only split while the timer is running, and never start or reset it. Use this if your
+    /// category's rules require starting and resetting the timer by hand.
+    #[arg(long, default_value_t = false)]
+    split_only: bool,
+    /// Only start the timer when you start a new game and stop it when you beat Dorothy, and leave
+    /// all the splits in between to you. Use this if your splits don't match any built-in route.
+    #[arg(long, default_value_t = false, conflicts_with = "split_only")]
+    start_stop_only: bool,
+    /// If LiveSplit closes or crashes during a run, put the run back in LiveSplit when it comes
+    /// back. The timer restarts from zero, but the splits continue from where they were.
+    #[arg(short = 'w', long, default_value_t = false)]
+    restore_run: bool,
+    /// Before starting a run, check that the game and LiveSplit are set up correctly, and don't
+    /// start the run if they aren't
+    #[arg(short = 'k', long, default_value_t = false)]
+    checklist: bool,
+    /// If the game is lost and the splits loaded in LiveSplit are for the other kind of run
+    /// (emulator or console), switch to watching that kind of run instead of waiting for the game
+    /// to come back
+    #[arg(long, default_value_t = false)]
+    hot_swap: bool,
+    /// After each room change, don't split again for this many milliseconds. Guards against double
+    /// splits when the room ID flickers or a door animation matches more than once on console.
+    #[arg(long, default_value_t = 0)]
+    split_cooldown: u64,
+    /// With all-doors splits, if you go straight back through a door you just came through, undo
+    /// the split instead of splitting again
+    #[arg(long, default_value_t = false)]
+    unsplit_backtrack: bool,
+    /// If LiveSplit's splits don't have the same number of segments as the route, stop splitting
+    /// when the route reaches the last segment instead of ending the run early, and skip to the last
+    /// segment for the final split
+    #[arg(long, default_value_t = false)]
+    strict_segments: bool,
+    /// Make LiveSplit's game time the in-game time: game time is kept paused in LiveSplit and set
+    /// from the game's own timer several times a second. Requires the game version's in-game time
+    /// address to be known.
+    #[arg(long, default_value_t = false)]
+    igt: bool,
+    /// When doing console runs with --split-only, how many milliseconds the video capture lags
+    /// behind the console. Game time is held back by this much at the start of the run to make up
+    /// for the delay. Use the measure-latency command to find it. Can't be combined with --igt.
+    #[arg(long, default_value_t = 0, conflicts_with = "igt")]
+    capture_latency: u64,
+    /// Keep the LiveSplit custom variables GaleriansRoom, GaleriansNextSplit, and GaleriansStatus
+    /// up to date so they can be shown in your layout
+    #[arg(long, default_value_t = false)]
+    layout_variables: bool,
+    /// Save a report of each completed run (final time, stage times, resets, and so on) as a JSON
+    /// file in this directory
+    #[arg(long)]
+    run_reports: Option<PathBuf>,
+    /// Split on the events in this route file instead of the split type's built-in route. Route
+    /// files can be made with the edit-route command.
+    #[arg(long)]
+    route: Option<PathBuf>,
+    /// Time an individual level run of this stage instead of a full-game run. The timer starts
+    /// when you load a save in the stage and stops when you reach the next stage, and the splits
+    /// are the part of the route for that stage. Stage A starts from a new game as usual.
+    #[arg(long, value_enum)]
+    il_stage: Option<Stage>,
+    /// Format of the output printed on stdout. Log messages are always written to stderr.
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Log)]
+    output: OutputFormat,
+    /// Send progress to a race host at this address (e.g. 192.168.1.10:16840)
+    #[arg(long)]
+    race_server: Option<String>,
+    /// Name to show for this runner on the race host. Defaults to the current user's name.
+    #[arg(long)]
+    runner_name: Option<String>,
+}
+
+/// Load the route to name segments for: the given route file, or the split type's built-in route
+fn load_named_route(route: Option<&Path>, split_type: Option<SplitType>) -> Result<(&'static [Event], &'static [RouteEntry])> {
+    match route {
+        Some(path) => {
+            let route = RouteFile::load(path).map_err(|e| anyhow!("Failed to load route {}: {e}", path.display()))?;
+            if let Some(problem) = route.branch_problems().first() {
+                bail!("Route {} has a bad branch: {problem}", path.display());
+            }
+            Ok(route.into_route())
+        }
+        None => match split_type.and_then(|split_type| split_type.splits()) {
+            Some(events) => Ok((events, &[])),
+            None => bail!("Give a route file or a split type with a built-in route"),
+        },
+    }
 }
 
 fn main() -> Result<()> {
@@ -170,15 +523,162 @@ fn main() -> Result<()> {
 
     colog::default_builder().filter_level(args.log_level.into()).init();
 
+    if args.capture_fps.is_some_and(|fps| !(fps > 0.0 && fps.is_finite())) {
+        bail!("--capture-fps must be a positive number");
+    }
+    let device_mode = DeviceMode {
+        api: args.capture_api,
+        frame_size: args.capture_resolution,
+        fps: args.capture_fps,
+        fourcc: args.capture_format,
+        raw_yuv: args.raw_yuv,
+    };
+    let emulator_search = EmulatorSearchOptions {
+        gdb_address: args.gdb_address,
+        emulator_process: args.emulator_process,
+        shared_memory: args.shared_memory,
+        pid: args.emulator_pid,
+        emulator_name: args.emulator_name,
+        emulator_priority: args.emulator_priority,
+    };
+
+    match args.command {
+        Some(Command::Restore { backup }) => return match backup {
+            Some(backup) => {
+                let original = backup::restore_backup(&backup)?;
+                log::info!("Restored {} from {}", original.display(), backup.display());
+                Ok(())
+            }
+            None => backup::list_backups("device.json"),
+        },
+        Some(Command::ValidateRoute { route, segments }) => return route_editor::validate_route(&route, segments),
+        Some(Command::SegmentNames { route }) => {
+            let (events, entries) = load_named_route(route.as_deref(), args.split_type)?;
+            for name in splits::segment_names(events, entries) {
+                println!("{name}");
+            }
+            return Ok(());
+        }
+        Some(Command::GenerateLss { output_file, route }) => {
+            let (events, entries) = load_named_route(route.as_deref(), args.split_type)?;
+            let run_category = args.run_category.unwrap_or(RunCategory::AnyPercent);
+            if args.split_type.is_none() {
+                log::warn!("No split type was given, so the autosplitter won't be able to detect it from these splits");
+            }
+            split_file::save_lss(&output_file, &splits::segment_names(events, entries), args.split_type, run_category)?;
+            log::info!("Saved splits to {}", output_file.display());
+            return Ok(());
+        }
+        Some(Command::MeasureLatency) => {
+            let latency = game::measure_latency(args.capture_device, &device_mode)?;
+            log::info!("Capture latency is about {} ms; use --capture-latency {} to make up for it", latency.as_millis(), latency.as_millis());
+            return Ok(());
+        }
+        Some(Command::RaceHost { port, bind }) => return race::host(bind, port),
+        Some(Command::EditRoute { route }) => return RouteEditor::open(route, emulator_search)?.run(),
+        Some(Command::Capabilities) => {
+            println!("{}", serde_json::to_string_pretty(&Capabilities::get())?);
+            return Ok(());
+        }
+        None => (),
+    }
+
+    let route = match args.route {
+        Some(path) => {
+            let route = RouteFile::load(&path).map_err(|e| anyhow!("Failed to load route {}: {e}", path.display()))?;
+            if route.events.is_empty() {
+                bail!("Route {} has no events", path.display());
+            }
+            if let Some(problem) = route.branch_problems().first() {
+                bail!("Route {} has a bad branch: {problem}", path.display());
+            }
+            match &route.name {
+                Some(name) => log::info!("Loaded route {name} with {} events from {}", route.events.len(), path.display()),
+                None => log::info!("Loaded {} events from {}", route.events.len(), path.display()),
+            }
+            Some(route)
+        }
+        None => None,
+    };
+
+    let mut output = EventOutput::new(args.output);
+    if let Some(race_server) = args.race_server {
+        let runner_name = args.runner_name
+            .or_else(|| std::env::var("USER").ok())
+            .or_else(|| std::env::var("USERNAME").ok())
+            .unwrap_or_else(|| String::from("Runner"));
+        output = output.with_race_reporter(RaceReporter::start(race_server, runner_name));
+    }
+
+    let live_split_port = match (args.live_split_port, std::env::var(LIVE_SPLIT_PORT_VARIABLE)) {
+        (Some(port), _) => Some(port),
+        (None, Ok(port)) => Some(port.trim().parse().map_err(|_| anyhow!("{LIVE_SPLIT_PORT_VARIABLE} should be a port number, not {port}"))?),
+        (None, Err(_)) => None,
+    };
+
+    if !(args.live_split_retry_backoff >= 1.0 && args.live_split_retry_backoff.is_finite()) {
+        bail!("--live-split-retry-backoff must be at least 1");
+    }
+
+    // recorded footage and the screen can only be watched by the capture backend
+    let has_capture_source = args.video_file.is_some() || args.screen_region.is_some() || args.capture_window.is_some();
+    let game_backend = match (args.game_backend, has_capture_source) {
+        (Some(GameBackendType::Emulator), true) => bail!("--video-file, --screen-region, and --capture-window can't be used with the emulator backend"),
+        (None, true) => Some(GameBackendType::Capture),
+        (game_backend, _) => game_backend,
+    };
+
     // create autosplitter
     let update_duration = Duration::from_millis(args.update_frequency);
-    let mut splitter = AutoSplitter::create(
-        update_duration,
-        args.live_split_port,
-        args.capture_device,
-        args.force_calibrate,
-        args.split_type,
-        args.run_category,
-    )?;
+    let mut splitter = AutoSplitter::create(AutoSplitterConfig {
+        update_frequency: update_duration,
+        live_split_host: args.live_split_host,
+        live_split_port,
+        live_split_retry_policy: RetryPolicy {
+            retries: args.live_split_retries,
+            delay: Duration::from_millis(args.live_split_retry_delay),
+            backoff: args.live_split_retry_backoff,
+        },
+        timer_backend: args.timer_backend,
+        game_backend,
+        backend_options: GameBackendOptions {
+            capture_device: args.capture_device,
+            device_mode,
+            deinterlace: args.deinterlace,
+            video_file: args.video_file,
+            screen_region: args.screen_region,
+            capture_window: args.capture_window,
+            calibration_profile: args.calibration_profile,
+            force_calibrate: args.force_calibrate,
+            preview_calibration: args.preview_calibration,
+            start_room: args.start_room,
+            split_screenshots: args.split_screenshots,
+        },
+        emulator_search,
+        requested_split_type: args.split_type,
+        route,
+        il_stage: args.il_stage,
+        requested_run_category: args.run_category,
+        stage_resets: args.stage_resets,
+        save_loads: args.save_loads,
+        no_auto_reset: args.no_auto_reset,
+        split_only: args.split_only,
+        start_stop_only: args.start_stop_only,
+        restore_run: args.restore_run,
+        checklist: args.checklist,
+        hot_swap: args.hot_swap,
+        split_cooldown: Duration::from_millis(args.split_cooldown),
+        unsplit_backtrack: args.unsplit_backtrack,
+        strict_segments: args.strict_segments,
+        game_time: GameTimeOptions {
+            igt_mode: args.igt,
+            capture_latency: Duration::from_millis(args.capture_latency),
+        },
+        reports: ReportOptions {
+            layout_variables: args.layout_variables,
+            run_report_dir: args.run_reports,
+        },
+        output,
+    })?;
     splitter.update()
 }
@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::game::MatchScore;
+use crate::race::RaceReporter;
+use crate::summary::RunSummary;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Only print human-readable log messages
+    Log,
+    /// Also print each state change and split decision as a JSON object (one per line) on stdout
+    JsonEvents,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitReason {
+    RunStart,
+    RoomChange,
+    RouteEvent,
+    FinalBoss,
+    /// The player reached the next stage during an individual level run
+    StageEnd,
+    /// The player picked up an item they hadn't had yet, with randomizer splits
+    ItemAcquired,
+    /// The player won a boss fight, with randomizer splits
+    BossDefeated,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OutputEvent {
+    ConnectionState { state: &'static str },
+    RunState { state: &'static str },
+    SplitType { split_type: &'static str },
+    RunCategory { run_category: &'static str },
+    GameChanged,
+    Split {
+        reason: SplitReason,
+        map: u16,
+        room: u16,
+        /// The name of the route event that was split on, for route event splits
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    /// The last split was undone because the player stepped straight back through the door
+    Unsplit { map: u16, room: u16 },
+    Room { map: u16, room: u16 },
+    Knock { state: &'static str },
+    /// The best score for each image the capture was compared against over the last few frames
+    MatchScores { scores: Vec<MatchScore> },
+    RunSummary { summary: RunSummary },
+    Reset,
+}
+
+#[derive(Serialize)]
+struct OutputRecord<'a> {
+    timestamp: f64,
+    #[serde(flatten)]
+    event: &'a OutputEvent,
+}
+
+#[derive(Debug, Clone)]
+pub struct EventOutput {
+    format: OutputFormat,
+    race: Option<RaceReporter>,
+}
+
+impl EventOutput {
+    pub const fn new(format: OutputFormat) -> Self {
+        Self { format, race: None }
+    }
+
+    /// Also send every event to a race host
+    pub fn with_race_reporter(mut self, race: RaceReporter) -> Self {
+        self.race = Some(race);
+        self
+    }
+
+    pub fn emit(&self, event: OutputEvent) {
+        if self.format != OutputFormat::JsonEvents && self.race.is_none() {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+        let record = OutputRecord { timestamp, event: &event };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("Failed to serialize event: {e}");
+                return;
+            }
+        };
+
+        if let Some(race) = &self.race {
+            race.send(line.clone());
+        }
+
+        if self.format != OutputFormat::JsonEvents {
+            return;
+        }
+
+        // log output goes to stderr, so stdout is reserved for the event stream. we flush after
+        // every line so whatever's on the other end of the pipe sees events as they happen.
+        let mut stdout = io::stdout().lock();
+        let result = stdout.write_all(line.as_bytes())
+            .and_then(|_| stdout.write_all(b"\n"))
+            .and_then(|_| stdout.flush());
+        if let Err(e) = result {
+            log::warn!("Failed to write event to stdout: {e}");
+        }
+    }
+}
@@ -1,48 +1,134 @@
 use std::cell::{RefCell, Ref};
 use std::fmt::Debug;
+use std::io::{self, Write};
 use std::path::Path;
 use std::ptr;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use num_traits::{ConstZero, FromBytes};
 use sysinfo::{Pid, Process, ProcessesToUpdate, ProcessRefreshKind, RefreshKind, System, UpdateKind};
 
+mod gdb;
+use gdb::GdbClient;
+mod pine;
+use pine::{PINE_DEFAULT_SLOT, PineClient};
+mod web_api;
+use web_api::{PCSX_REDUX_WEB_PORT, WebApiClient};
+
 #[cfg(unix)]
 mod unix;
 #[cfg(unix)]
-use unix::UnixSharedMemoryClient as PlatformSharedMemoryClient;
+use unix::{UnixProcessMemoryClient as PlatformProcessMemoryClient, UnixSharedMemoryClient as PlatformSharedMemoryClient, watch_processes};
 
 #[cfg(windows)]
 mod windows;
 #[cfg(windows)]
-use windows::WindowsSharedMemoryClient as PlatformSharedMemoryClient;
+use windows::{WindowsProcessMemoryClient as PlatformProcessMemoryClient, WindowsSharedMemoryClient as PlatformSharedMemoryClient, watch_processes};
+#[cfg(windows)]
+pub use windows::find_window;
 
+// some emulators expose 8MB of RAM like a dev unit, while others only expose a retail console's 2MB
 const EMULATOR_MAX_RAM: usize = 0x800000;
+const PSX_RAM_SIZE: usize = 0x200000;
+// the BIOS copies a jump to the kernel's exception handler to 0x80 in RAM on boot:
+// lui k0, 0; addiu k0, k0, 0xC80; jr k0; nop
+const RAM_SIGNATURE: [u8; 16] = [
+    0x00, 0x00, 0x1A, 0x3C, 0x80, 0x0C, 0x5A, 0x27, 0x08, 0x00, 0x40, 0x03, 0x00, 0x00, 0x00, 0x00,
+];
+const RAM_SIGNATURE_OFFSET: usize = 0x80;
+const BIOS_SIGNATURE: RamSignature = RamSignature { bytes: &RAM_SIGNATURE, offset: RAM_SIGNATURE_OFFSET };
+const SCAN_CHUNK_SIZE: usize = 0x100000;
+// how many times to read a value that keeps changing underneath us before giving up and taking the
+// last read
+const STABLE_READ_ATTEMPTS: usize = 4;
+// .NET emulators show up as the runtime rather than the emulator when not running natively on Windows
+const RUNTIME_HOSTS: [&str; 2] = ["mono", "dotnet"];
+// Windows emulators running under Wine show up as the Wine loader (wine, wine64, wine64-preloader, etc.)
+const WINE_PREFIX: &str = "wine";
+
+/// User-provided hints about where to find the emulator
+#[derive(Debug, Clone, Default)]
+pub struct EmulatorSearchOptions {
+    /// Address of a GDB remote stub to read memory through
+    pub gdb_address: Option<String>,
+    /// Part of the executable name of an emulator that isn't supported directly
+    pub emulator_process: Option<String>,
+    /// Name of a shared memory object containing the emulated RAM
+    pub shared_memory: Option<String>,
+    /// Only attach to the emulator process with this PID
+    pub pid: Option<u32>,
+    /// Only attach to emulators whose name or executable name contains this text
+    pub emulator_name: Option<String>,
+    /// Names of emulators to prefer when more than one is running, most preferred first
+    pub emulator_priority: Vec<String>,
+}
+
+/// Notifications from the OS that processes have started or stopped
+#[derive(Debug)]
+struct ProcessEvents {
+    changed: AtomicBool,
+    /// Whether we're still getting notifications. If not, we have to go back to polling.
+    is_alive: AtomicBool,
+}
+
+impl ProcessEvents {
+    const fn new() -> Self {
+        Self {
+            changed: AtomicBool::new(false),
+            is_alive: AtomicBool::new(true),
+        }
+    }
+
+    fn needs_refresh(&self) -> bool {
+        !self.is_alive.load(Ordering::Relaxed) || self.changed.swap(false, Ordering::Relaxed)
+    }
+}
 
 #[derive(Debug)]
 pub struct Platform {
     system: System,
     last_refresh: Instant,
     refresh_interval: Duration,
+    search_options: EmulatorSearchOptions,
+    process_events: Option<Arc<ProcessEvents>>,
 }
 
 impl Platform {
     fn process_refresh_kind() -> ProcessRefreshKind {
-        ProcessRefreshKind::nothing().with_exe(UpdateKind::OnlyIfNotSet)
+        ProcessRefreshKind::nothing().with_exe(UpdateKind::OnlyIfNotSet).with_cmd(UpdateKind::OnlyIfNotSet)
     }
 
     fn refresh_kind() -> RefreshKind {
         RefreshKind::nothing().with_processes(Self::process_refresh_kind())
     }
 
-    pub fn new(refresh_interval: Duration) -> Self {
+    pub fn new(refresh_interval: Duration, mut search_options: EmulatorSearchOptions) -> Self {
         let system = System::new_with_specifics(Self::refresh_kind());
+        search_options.emulator_process = search_options.emulator_process.map(|name| name.to_lowercase());
+        search_options.emulator_name = search_options.emulator_name.map(|name| name.to_lowercase());
+        for name in &mut search_options.emulator_priority {
+            *name = name.to_lowercase();
+        }
+        let process_events = match watch_processes() {
+            Ok(process_events) => {
+                log::debug!("Watching for process events");
+                Some(process_events)
+            }
+            Err(e) => {
+                log::debug!("{e}; polling for processes instead");
+                None
+            }
+        };
         Self {
             system,
             last_refresh: Instant::now(),
             refresh_interval,
+            search_options,
+            process_events,
         }
     }
 
@@ -56,8 +142,16 @@ impl Platform {
     }
 
     pub fn refresh_if_stale(&mut self) {
-        if (Instant::now() - self.last_refresh) >= self.refresh_interval {
+        if (Instant::now() - self.last_refresh) < self.refresh_interval {
+            return;
+        }
+
+        // if the OS will tell us when processes start and stop, we don't need to look at the
+        // process list until that happens
+        if self.process_events.as_ref().is_none_or(|events| events.needs_refresh()) {
             self.refresh();
+        } else {
+            self.last_refresh = Instant::now();
         }
     }
 
@@ -74,17 +168,40 @@ impl Platform {
 enum EmulatorType {
     DuckStation,
     PcsxRedux,
+    Epsxe,
+    BizHawk,
+    Mednafen,
+    /// An emulator we don't know anything about, identified by a user-provided process name
+    Generic,
 }
 
 impl EmulatorType {
-    const fn all() -> [Self; 2] {
-        [Self::DuckStation, Self::PcsxRedux]
+    const fn all() -> [Self; 5] {
+        [Self::DuckStation, Self::PcsxRedux, Self::Epsxe, Self::BizHawk, Self::Mednafen]
     }
 
-    const fn prefix(&self) -> &'static str {
+    /// Prefix of the name of the shared memory object the emulator exports its RAM in, if any
+    const fn shmem_prefix(&self) -> Option<&'static str> {
         match self {
-            Self::DuckStation => "duckstation_",
-            Self::PcsxRedux => "pcsx-redux-wram-",
+            Self::DuckStation => Some("duckstation_"),
+            Self::PcsxRedux => Some("pcsx-redux-wram-"),
+            Self::Epsxe | Self::BizHawk | Self::Mednafen | Self::Generic => None,
+        }
+    }
+
+    /// Name the emulator uses for its PINE IPC server, if it has one
+    const fn pine_name(&self) -> Option<&'static str> {
+        match self {
+            Self::DuckStation => Some("duckstation"),
+            _ => None,
+        }
+    }
+
+    /// Port the emulator's web server with a debug API listens on by default, if it has one
+    const fn web_api_port(&self) -> Option<u16> {
+        match self {
+            Self::PcsxRedux => Some(PCSX_REDUX_WEB_PORT),
+            _ => None,
         }
     }
 
@@ -92,6 +209,10 @@ impl EmulatorType {
         match self {
             Self::DuckStation => "DuckStation",
             Self::PcsxRedux => "PCSX-Redux",
+            Self::Epsxe => "ePSXe",
+            Self::BizHawk => "BizHawk",
+            Self::Mednafen => "Mednafen",
+            Self::Generic => "emulator",
         }
     }
 
@@ -99,35 +220,51 @@ impl EmulatorType {
         match self {
             Self::DuckStation => "duckstation",
             Self::PcsxRedux => "pcsx-redux",
+            Self::Epsxe => "epsxe",
+            Self::BizHawk => "emuhawk",
+            Self::Mednafen => "mednafen",
+            // matched against the user-provided process name instead
+            Self::Generic => "",
         }
     }
 }
 
+/// Names of the emulators we know how to find without any hints from the user
+pub fn supported_emulators() -> [&'static str; 5] {
+    EmulatorType::all().map(|emulator_type| emulator_type.name())
+}
+
 #[derive(Debug, Clone)]
 struct EmulatorProcess {
     emulator_type: EmulatorType,
     pid: Pid,
+    is_wine: bool,
     platform: Rc<RefCell<Platform>>,
 }
 
 impl EmulatorProcess {
-    const fn new(emulator_type: EmulatorType, pid: Pid, platform: Rc<RefCell<Platform>>) -> Self {
-        Self { emulator_type, pid, platform }
+    const fn new(emulator_type: EmulatorType, pid: Pid, is_wine: bool, platform: Rc<RefCell<Platform>>) -> Self {
+        Self { emulator_type, pid, is_wine, platform }
     }
 
     fn is_alive(&self) -> bool {
         self.platform.acquire().is_pid_alive(self.pid)
     }
 
-    fn shmem_name(&self) -> String {
-        format!("{}{}", self.emulator_type.prefix(), self.pid.as_u32())
+    fn shmem_name(&self) -> Option<String> {
+        if self.is_wine {
+            // Wine keeps named file mappings inside wineserver, where we can't get at them
+            return None;
+        }
+
+        self.emulator_type.shmem_prefix().map(|prefix| format!("{}{}", prefix, self.pid.as_u32()))
     }
 }
 
 pub trait PlatformInterface {
     fn acquire(&self) -> Ref<'_, Platform>;
 
-    fn search_for_emulator(self: &Rc<Self>) -> Option<Emulator>;
+    fn search_for_emulator(self: &Rc<Self>, signatures: &[RamSignature]) -> Option<Emulator>;
 }
 
 impl PlatformInterface for RefCell<Platform> {
@@ -141,103 +278,386 @@ impl PlatformInterface for RefCell<Platform> {
         self.borrow()
     }
 
-    fn search_for_emulator(self: &Rc<Self>) -> Option<Emulator> {
+    fn search_for_emulator(self: &Rc<Self>, signatures: &[RamSignature]) -> Option<Emulator> {
         let platform = self.acquire();
+        if let Some(shmem_name) = &platform.search_options.shared_memory {
+            // the user told us exactly where to find the emulator, so don't go looking for processes
+            return match Emulator::from_shared_memory(shmem_name) {
+                Ok(emulator) => {
+                    log::info!("Opened shared memory {shmem_name}");
+                    Some(emulator)
+                }
+                Err(e) => {
+                    log::debug!("{e}");
+                    None
+                }
+            };
+        }
+
+        if let Some(gdb_address) = &platform.search_options.gdb_address {
+            // likewise for a GDB stub
+            return match Emulator::from_gdb(gdb_address) {
+                Ok(emulator) => {
+                    log::info!("Connected to GDB stub at {gdb_address}");
+                    Some(emulator)
+                }
+                Err(e) => {
+                    log::debug!("Failed to connect to GDB stub at {gdb_address}: {e}");
+                    None
+                }
+            };
+        }
+
+        let mut candidates = Vec::new();
         for (pid, process) in platform.active_processes() {
+            if platform.search_options.pid.is_some_and(|wanted_pid| wanted_pid != pid.as_u32()) {
+                continue;
+            }
+
             let Some(exe_name) = process.exe().and_then(Path::file_name) else {
                 continue;
             };
 
-            let lc_exe_name = exe_name.to_string_lossy().to_lowercase();
+            let mut lc_exe_name = exe_name.to_string_lossy().to_lowercase();
+            let is_wine = lc_exe_name.starts_with(WINE_PREFIX);
+            if is_wine {
+                // the Windows executable being run should be the first element of the command line.
+                // it'll be a Windows path, so we can't rely on Path to split it for us.
+                let Some(windows_exe) = process.cmd().first().map(|arg| arg.to_string_lossy()) else {
+                    continue;
+                };
+                let Some(windows_exe_name) = windows_exe.rsplit(['\\', '/']).next() else {
+                    continue;
+                };
+                lc_exe_name = windows_exe_name.to_lowercase();
+            }
+
+            if RUNTIME_HOSTS.iter().any(|host| lc_exe_name.starts_with(host)) {
+                // the assembly being run should be the first argument
+                let Some(assembly_name) = process.cmd().get(1).and_then(|arg| Path::new(arg).file_name()) else {
+                    continue;
+                };
+                lc_exe_name = assembly_name.to_string_lossy().to_lowercase();
+            }
 
-            for emulator_type in EmulatorType::all() {
-                if !lc_exe_name.contains(emulator_type.exe_substring()) {
+            let is_custom_process = platform.search_options.emulator_process.as_ref().is_some_and(|name| lc_exe_name.contains(name.as_str()));
+            let generic_type = is_custom_process.then_some(EmulatorType::Generic);
+            for emulator_type in EmulatorType::all().into_iter().chain(generic_type) {
+                if emulator_type != EmulatorType::Generic && !lc_exe_name.contains(emulator_type.exe_substring()) {
+                    continue;
+                }
+
+                if let Some(name) = &platform.search_options.emulator_name
+                    && !lc_exe_name.contains(name.as_str())
+                    && !emulator_type.name().to_lowercase().contains(name.as_str())
+                {
                     continue;
                 }
 
                 let emulator_process = EmulatorProcess::new(
                     emulator_type,
                     pid,
+                    is_wine,
                     Rc::clone(self),
                 );
 
-                match Emulator::from_process(emulator_process) {
+                match Emulator::from_process(emulator_process, signatures) {
                     Ok(emulator) => {
-                        log::info!("Detected {}", emulator_type.name());
-                        return Some(emulator);
+                        candidates.push((emulator_type, pid, emulator));
+                        break;
                     }
-                    // both supported emulators spawn multiple processes, so if we log a warning
-                    // every time we see a process that matches the search string but doesn't have a
-                    // corresponding shared memory object, we're just going to be spamming the log
+                    // several of the supported emulators spawn multiple processes, so if we log a
+                    // warning every time we see a process that matches the search string but doesn't
+                    // have a corresponding shared memory object, we're just going to be spamming the
+                    // log
                     Err(e) => log::debug!("Failed to attach to {} process {}: {}", emulator_type.name(), pid, e),
                 }
             }
         }
 
-        None
+        choose_emulator(candidates, &platform.search_options.emulator_priority)
+    }
+}
+
+/// Where an emulator falls in the user's priority list. Emulators that aren't in the list come
+/// after all the ones that are.
+fn emulator_rank(emulator_type: EmulatorType, priority: &[String]) -> usize {
+    let name = emulator_type.name().to_lowercase();
+    priority
+        .iter()
+        .position(|wanted| name.contains(wanted.as_str()))
+        .unwrap_or(priority.len())
+}
+
+/// Pick which emulator to use when there's more than one running, asking the user if necessary
+fn choose_emulator(mut candidates: Vec<(EmulatorType, Pid, Emulator)>, priority: &[String]) -> Option<Emulator> {
+    // only ask about the emulators the user likes best
+    if let Some(best_rank) = candidates.iter().map(|(emulator_type, _, _)| emulator_rank(*emulator_type, priority)).min() {
+        candidates.retain(|(emulator_type, _, _)| emulator_rank(*emulator_type, priority) == best_rank);
+    }
+
+    if candidates.len() > 1 {
+        eprintln!("Found multiple emulators:");
+        for (i, (emulator_type, pid, _)) in candidates.iter().enumerate() {
+            eprintln!("  {}: {} (PID {pid})", i + 1, emulator_type.name());
+        }
+
+        loop {
+            eprint!("Enter the number of the emulator to use: ");
+            let mut response = String::new();
+            if io::stderr().flush().and_then(|_| io::stdin().read_line(&mut response)).is_err() {
+                log::warn!("Failed to read emulator choice; using the first emulator");
+                break;
+            }
+
+            match response.trim().parse::<usize>() {
+                Ok(choice @ 1..) if choice <= candidates.len() => {
+                    candidates.swap(0, choice - 1);
+                    break;
+                }
+                _ => eprintln!("Please enter a number between 1 and {}", candidates.len()),
+            }
+        }
     }
+
+    let (emulator_type, pid, emulator) = candidates.into_iter().next()?;
+    log::info!("Detected {} (PID {pid})", emulator_type.name());
+    Some(emulator)
 }
 
 pub type PlatformRef = Rc<RefCell<Platform>>;
 
 trait SharedMemoryClient: Debug {
-    fn open(name: &str, size: usize) -> Result<Self> where Self: Sized;
+    /// Open the named shared memory object and map up to `max_size` bytes of it
+    fn open(name: &str, max_size: usize) -> Result<Self> where Self: Sized;
 
     fn base(&self) -> *const u8;
 
     fn size(&self) -> usize;
+}
 
-    fn end(&self) -> *const u8 {
-        // SAFETY: if creation of the mapping succeeded in the open method, then self.base() will be
-        // the base address of an allocation of at least self.size() bytes, and the size will not
-        // exceed isize::MAX
-        unsafe { self.base().byte_add(self.size()) }
+trait ProcessMemoryClient: Debug {
+    fn open(pid: Pid) -> Result<Self> where Self: Sized;
+
+    /// Get the base address and size of each readable region of the process's memory
+    fn readable_regions(&self) -> Result<Vec<(usize, usize)>>;
+
+    fn read_into(&self, address: usize, buf: &mut [u8]) -> Result<()>;
+}
+
+/// A sequence of bytes expected at a particular offset in emulated RAM
+#[derive(Debug, Copy, Clone)]
+pub struct RamSignature {
+    bytes: &'static [u8],
+    offset: usize,
+}
+
+impl RamSignature {
+    pub const fn new(bytes: &'static [u8], address: u32) -> Self {
+        Self { bytes, offset: (address & 0x1FFFFF) as usize }
+    }
+
+    /// Get the base address of RAM if this signature was found at the given address in a memory
+    /// region, and RAM at that base would fit in the region
+    fn ram_base(&self, hit: usize, region_base: usize, region_end: usize) -> Option<usize> {
+        let ram_base = hit.checked_sub(self.offset)?;
+        (ram_base >= region_base && ram_base + PSX_RAM_SIZE <= region_end).then_some(ram_base)
+    }
+
+    /// Check whether this signature is where it should be in RAM at the given base address
+    fn is_at(&self, client: &impl ProcessMemoryClient, ram_base: usize) -> bool {
+        let mut buf = vec![0u8; self.bytes.len()];
+        client.read_into(ram_base + self.offset, &mut buf).is_ok() && buf == self.bytes
+    }
+}
+
+/// Scan the memory of an emulator process for the emulated PSX RAM. The BIOS signature is always
+/// checked; additional signatures can be provided to find RAM in emulators that don't keep the
+/// BIOS's data where we expect. When additional signatures are given, a candidate is only accepted
+/// if one of them matches too, since the BIOS signature also turns up in the emulator's copy of the
+/// BIOS ROM.
+fn find_ram_base(client: &impl ProcessMemoryClient, game_signatures: &[RamSignature]) -> Result<usize> {
+    let is_game_ram = |ram_base: usize| game_signatures.is_empty() || game_signatures.iter().any(|signature| signature.is_at(client, ram_base));
+    let signatures: Vec<_> = [BIOS_SIGNATURE].into_iter().chain(game_signatures.iter().copied()).collect();
+    let overlap = signatures.iter().map(|signature| signature.bytes.len()).max().unwrap_or(1) - 1;
+    let mut chunk = vec![0u8; SCAN_CHUNK_SIZE];
+    for (region_base, region_size) in client.readable_regions()? {
+        if region_size < PSX_RAM_SIZE {
+            continue;
+        }
+
+        let region_end = region_base + region_size;
+        let mut address = region_base;
+        while address < region_end {
+            let size = SCAN_CHUNK_SIZE.min(region_end - address);
+            let buf = &mut chunk[..size];
+            if client.read_into(address, buf).is_err() {
+                // the memory map can change out from under us while we're scanning, so just skip
+                // anything we can't read
+                break;
+            }
+
+            for signature in &signatures {
+                let ram_base = buf
+                    .windows(signature.bytes.len())
+                    .enumerate()
+                    .filter(|(_, window)| *window == signature.bytes)
+                    .filter_map(|(i, _)| signature.ram_base(address + i, region_base, region_end))
+                    .find(|ram_base| is_game_ram(*ram_base));
+                if let Some(ram_base) = ram_base {
+                    return Ok(ram_base);
+                }
+            }
+
+            if address + size >= region_end {
+                break;
+            }
+            // overlap the chunks so we don't miss a signature that straddles the boundary
+            address += size - overlap;
+        }
+    }
+
+    bail!("Could not find emulated RAM in process memory");
+}
+
+/// Map as much of the emulated RAM as the emulator exposes, up to the maximum
+fn open_shared_memory(name: &str) -> Result<PlatformSharedMemoryClient> {
+    let shared_memory = PlatformSharedMemoryClient::open(name, EMULATOR_MAX_RAM)?;
+    let size = shared_memory.size();
+    if size < PSX_RAM_SIZE {
+        bail!("Shared memory object {name} is only {size:#X} bytes, which is too small to hold the emulated RAM");
+    }
+    if size != EMULATOR_MAX_RAM {
+        log::debug!("Shared memory object {name} is {size:#X} bytes");
+    }
+
+    Ok(shared_memory)
+}
+
+#[derive(Debug)]
+enum EmulatorMemory {
+    Shared(PlatformSharedMemoryClient),
+    Process {
+        client: PlatformProcessMemoryClient,
+        ram_base: usize,
+    },
+    Pine(PineClient),
+    WebApi(WebApiClient),
+    Gdb(GdbClient),
+}
+
+impl EmulatorMemory {
+    fn open(process: &EmulatorProcess, signatures: &[RamSignature]) -> Result<Self> {
+        // the user may not have enabled shared memory export, or it may not be visible to us (e.g.
+        // due to sandboxing), so fall back to whatever IPC the emulator supports
+        if let Some(shmem_name) = process.shmem_name() {
+            match open_shared_memory(&shmem_name) {
+                Ok(shared_memory) => return Ok(Self::Shared(shared_memory)),
+                Err(e) => log::debug!("{e}"),
+            }
+        }
+
+        if let Some(pine_name) = process.emulator_type.pine_name() {
+            log::debug!("Trying PINE");
+            // Windows builds use TCP for PINE even under Wine
+            match PineClient::connect(pine_name, PINE_DEFAULT_SLOT, process.is_wine) {
+                Ok(client) => return Ok(Self::Pine(client)),
+                Err(e) => log::debug!("{e}"),
+            }
+        }
+
+        if let Some(port) = process.emulator_type.web_api_port() {
+            log::debug!("Trying web API");
+            match WebApiClient::connect(port) {
+                Ok(client) => return Ok(Self::WebApi(client)),
+                Err(e) => log::debug!("{e}"),
+            }
+        }
+
+        // as a last resort, go looking for the emulated RAM in the emulator's memory ourselves
+        let client = PlatformProcessMemoryClient::open(process.pid)?;
+        let ram_base = find_ram_base(&client, signatures)?;
+        log::debug!("Found emulated RAM at {ram_base:#X} in process {}", process.pid);
+        Ok(Self::Process { client, ram_base })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::Shared(shared_memory) => shared_memory.size(),
+            Self::Process { .. } | Self::Pine(_) | Self::WebApi(_) | Self::Gdb(_) => PSX_RAM_SIZE,
+        }
+    }
+
+    /// Whether reads come straight out of RAM that the emulator may be writing to at the same time.
+    /// The remote protocols have the emulator answer requests itself, so their reads can't be torn.
+    const fn is_live(&self) -> bool {
+        matches!(self, Self::Shared(_) | Self::Process { .. })
     }
 }
 
 #[derive(Debug)]
 pub struct Emulator {
-    shared_memory: PlatformSharedMemoryClient,
-    process: EmulatorProcess,
+    memory: EmulatorMemory,
+    // a GDB stub could be running anywhere, so we don't necessarily have a process to watch
+    process: Option<EmulatorProcess>,
 }
 
 impl Emulator {
-    fn from_process(process: EmulatorProcess) -> Result<Self> {
-        let shared_memory = PlatformSharedMemoryClient::open(&process.shmem_name(), EMULATOR_MAX_RAM)?;
+    fn from_process(process: EmulatorProcess, signatures: &[RamSignature]) -> Result<Self> {
+        let memory = EmulatorMemory::open(&process, signatures)?;
+
+        Ok(Self {
+            memory,
+            process: Some(process),
+        })
+    }
+
+    fn from_shared_memory(name: &str) -> Result<Self> {
+        // accept a path to the object as well as its name
+        let name = name.strip_prefix("/dev/shm/").unwrap_or(name);
+        Ok(Self {
+            memory: EmulatorMemory::Shared(open_shared_memory(name)?),
+            process: None,
+        })
+    }
 
+    fn from_gdb(address: &str) -> Result<Self> {
         Ok(Self {
-            shared_memory,
-            process,
+            memory: EmulatorMemory::Gdb(GdbClient::connect(address)?),
+            process: None,
         })
     }
     
     /// Check whether the emulator process providing this memory is still alive
     pub fn check_pulse(&self) -> bool {
-        self.process.is_alive()
+        match (&self.process, &self.memory) {
+            (Some(process), _) => process.is_alive(),
+            (None, EmulatorMemory::Gdb(client)) => client.is_connected(),
+            // we don't know which process is on the other end of shared memory the user pointed us
+            // at, so we just have to trust that it's still there
+            (None, EmulatorMemory::Shared(_)) => true,
+            (None, _) => false,
+        }
     }
 
-    fn address_to_pointer(&self, address: u32) -> *const u8 {
+    fn offset_for_range(&self, address: u32, size: usize) -> usize {
         let offset = (address & 0x1FFFFFF) as usize;
-        if offset >= self.shared_memory.size() {
+        let memory_size = self.memory.size();
+        // with only 2MB of RAM, the RAM is mirrored throughout the 8MB RAM region
+        let offset = if offset < EMULATOR_MAX_RAM && memory_size.is_power_of_two() {
+            offset & (memory_size - 1)
+        } else {
+            offset
+        };
+        if offset >= memory_size {
             panic!("Attempted to read from an address beyond the end of emulated RAM: address {address:08X}");
         }
 
-        let base = self.shared_memory.base();
-        // SAFETY: we've checked above that offset is within the size of the allocation
-        unsafe { base.byte_add(offset) }
-    }
-
-    fn pointer_for_range(&self, address: u32, size: usize) -> *const u8 {
-        let data_start = self.address_to_pointer(address);
-        let memory_end = self.shared_memory.end();
-        // SAFETY: address_to_pointer guarantees that the returned pointer will be within the
-        // allocation and less than the end pointer
-        let bytes_available = unsafe { memory_end.byte_offset_from_unsigned(data_start) };
-        if size > bytes_available {
+        if size > memory_size - offset {
             panic!("Attempted to read a number of bytes that would pass the end of emulated RAM: address {address:08X}, size {size}");
         }
 
-        data_start
+        offset
     }
 
     pub fn read<const N: usize>(&self, address: u32) -> [u8; N] {
@@ -248,22 +668,95 @@ impl Emulator {
 
     pub fn read_into(&self, address: u32, buf: &mut [u8]) {
         let size = buf.len();
-        let src = self.pointer_for_range(address, size);
-        let dest = buf.as_mut_ptr();
-        // SAFETY: pointer_for_range guarantees that it's safe to copy at least `size` bytes from
-        // the returned pointer. there's no way the provided buffer slice could overlap with the
-        // source data without additional unsafe abuse of the shared memory object outside of this
-        // function.
-        unsafe {
-            ptr::copy_nonoverlapping(src, dest, size);
+        let offset = self.offset_for_range(address, size);
+        match &self.memory {
+            EmulatorMemory::Shared(shared_memory) => {
+                let dest = buf.as_mut_ptr();
+                // SAFETY: offset_for_range guarantees that it's safe to copy at least `size` bytes
+                // from `offset` bytes into the mapping. there's no way the provided buffer slice
+                // could overlap with the source data without additional unsafe abuse of the shared
+                // memory object outside of this function.
+                unsafe {
+                    let src = shared_memory.base().byte_add(offset);
+                    ptr::copy_nonoverlapping(src, dest, size);
+                }
+            }
+            EmulatorMemory::Process { client, ram_base } => {
+                if let Err(e) = client.read_into(ram_base + offset, buf) {
+                    // this most likely means the process has gone away, which check_pulse will
+                    // pick up on. in the meantime, don't give the caller stale data.
+                    log::debug!("Failed to read emulator memory at {address:08X}: {e}");
+                    buf.fill(0);
+                }
+            }
+            EmulatorMemory::Pine(client) => {
+                // PINE expects a CPU address, so make sure we're in KSEG0
+                if let Err(e) = client.read_into(0x80000000 | offset as u32, buf) {
+                    log::debug!("Failed to read emulator memory at {address:08X}: {e}");
+                    buf.fill(0);
+                }
+            }
+            EmulatorMemory::WebApi(client) => {
+                if let Err(e) = client.read_into(offset, buf) {
+                    log::debug!("Failed to read emulator memory at {address:08X}: {e}");
+                    buf.fill(0);
+                }
+            }
+            EmulatorMemory::Gdb(client) => {
+                // GDB also wants a CPU address. if the read fails, the client marks itself as
+                // disconnected, and check_pulse will pick up on that.
+                if let Err(e) = client.read_into(0x80000000 | offset as u32, buf) {
+                    log::debug!("Failed to read emulator memory at {address:08X}: {e}");
+                    buf.fill(0);
+                }
+            }
         }
     }
 
+    /// Read a value that the emulator might be in the middle of writing. We keep reading until we
+    /// get the same bytes twice in a row so that we don't act on a value that's half old and half
+    /// new.
+    fn read_stable<const N: usize>(&self, address: u32) -> [u8; N] {
+        let mut bytes = self.read(address);
+        // a single byte can't be torn
+        if N == 1 || !self.memory.is_live() {
+            return bytes;
+        }
+
+        for _ in 1..STABLE_READ_ATTEMPTS {
+            let again = self.read(address);
+            if again == bytes {
+                break;
+            }
+            bytes = again;
+        }
+
+        bytes
+    }
+
     pub fn read_num<const N: usize, T: FromBytes<Bytes = [u8; N]>>(&self, address: u32) -> T {
-        let bytes: T::Bytes = self.read(address);
+        let bytes: T::Bytes = self.read_stable(address);
         T::from_le_bytes(&bytes)
     }
 
+    /// Like read_nums, but keeps reading until we get the same values twice in a row, like read_num
+    pub fn read_nums_stable<const M: usize, const N: usize, T: FromBytes<Bytes = [u8; N]> + ConstZero + PartialEq>(&self, address: u32) -> [T; M] {
+        let mut nums = self.read_nums(address);
+        if !self.memory.is_live() {
+            return nums;
+        }
+
+        for _ in 1..STABLE_READ_ATTEMPTS {
+            let again = self.read_nums(address);
+            if again == nums {
+                break;
+            }
+            nums = again;
+        }
+
+        nums
+    }
+
     pub fn read_nums<const M: usize, const N: usize, T: FromBytes<Bytes = [u8; N]> + ConstZero>(&self, address: u32) -> [T; M] {
         let mut out = [T::ZERO; M];
 
@@ -283,19 +776,15 @@ impl Emulator {
         let buf_elements = buf_size / N;
         let size = M * N;
         let mut bytes_remaining = size;
-        let mut src = self.pointer_for_range(address, size);
-        let dest = buf.as_mut_ptr();
+        // validate the whole range up front so we don't panic partway through
+        self.offset_for_range(address, size);
+        let mut src = address;
         let mut i = 0usize;
         while i < M {
             let bytes_to_read = buf_size.min(bytes_remaining);
             let end = (i + buf_elements).min(M);
 
-            // SAFETY: pointer_for_range guarantees that it's safe to copy at least `size` bytes
-            // from the returned pointer. it's impossible that the ranges could overlap as the
-            // destination buffer is a local stack variable that was just created.
-            unsafe {
-                ptr::copy_nonoverlapping(src, dest, bytes_to_read);
-            }
+            self.read_into(src, &mut buf[..bytes_to_read]);
 
             for (num, bytes) in out[i..end].iter_mut().zip(buf.chunks_exact(N)) {
                 let mut bytes_for_num = [0u8; N];
@@ -305,9 +794,7 @@ impl Emulator {
 
             i += buf_elements;
             bytes_remaining -= bytes_to_read;
-            // SAFETY: we keep track of the number of bytes remaining in the region that we
-            // validated we could read from to ensure we don't go past the end.
-            src = unsafe { src.byte_add(bytes_to_read) };
+            src += bytes_to_read as u32;
         }
 
         out
@@ -0,0 +1,145 @@
+use std::cell::{Cell, RefCell};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+
+const GDB_TIMEOUT: Duration = Duration::from_secs(1);
+// stubs advertise how big a packet they'll accept, but this is small enough that any reasonable
+// stub should be able to handle it
+const MAX_READ_SIZE: usize = 0x200;
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn decode_hex(hex: &[u8]) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        bail!("Odd number of hex digits in GDB reply");
+    }
+
+    hex.chunks_exact(2)
+        .map(|pair| {
+            str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| anyhow!("Invalid hex digits in GDB reply"))
+        })
+        .collect()
+}
+
+/// Expand run-length encoding in a packet from the stub
+fn decode_rle(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().copied();
+    while let Some(b) = bytes.next() {
+        if b != b'*' {
+            decoded.push(b);
+            continue;
+        }
+
+        let (Some(&last), Some(count)) = (decoded.last(), bytes.next()) else {
+            bail!("Invalid run-length encoding in GDB reply");
+        };
+        let repeat = (count as usize).checked_sub(29).ok_or_else(|| anyhow!("Invalid run-length encoding in GDB reply"))?;
+        decoded.extend(std::iter::repeat_n(last, repeat));
+    }
+
+    Ok(decoded)
+}
+
+/// Client for an emulator's GDB remote protocol stub
+#[derive(Debug)]
+pub struct GdbClient {
+    address: String,
+    connection: RefCell<BufReader<TcpStream>>,
+    is_connected: Cell<bool>,
+}
+
+impl GdbClient {
+    pub fn connect(address: &str) -> Result<Self> {
+        let Some(addr) = address.to_socket_addrs()?.next() else {
+            bail!("Could not resolve GDB stub address {address}");
+        };
+        let stream = TcpStream::connect_timeout(&addr, GDB_TIMEOUT)?;
+        stream.set_read_timeout(Some(GDB_TIMEOUT))?;
+        stream.set_write_timeout(Some(GDB_TIMEOUT))?;
+
+        let client = Self {
+            address: String::from(address),
+            connection: RefCell::new(BufReader::new(stream)),
+            is_connected: Cell::new(true),
+        };
+        // make sure there's actually a stub on the other end
+        client.request(b"?")?;
+
+        Ok(client)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.is_connected.get()
+    }
+
+    fn request(&self, command: &[u8]) -> Result<Vec<u8>> {
+        let result = self.exchange(command);
+        if result.is_err() {
+            self.is_connected.set(false);
+        }
+        result
+    }
+
+    fn exchange(&self, command: &[u8]) -> Result<Vec<u8>> {
+        let mut connection = self.connection.borrow_mut();
+
+        let mut packet = Vec::with_capacity(command.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(command);
+        packet.extend_from_slice(format!("#{:02x}", checksum(command)).as_bytes());
+        connection.get_mut().write_all(&packet)?;
+
+        // skip over the acknowledgement and anything else until the start of the reply
+        let mut discard = Vec::new();
+        connection.read_until(b'$', &mut discard)?;
+        if discard.last() != Some(&b'$') {
+            bail!("GDB stub at {} closed the connection", self.address);
+        }
+
+        let mut reply = Vec::new();
+        connection.read_until(b'#', &mut reply)?;
+        if reply.pop() != Some(b'#') {
+            bail!("GDB stub at {} closed the connection", self.address);
+        }
+
+        let mut reply_checksum = [0u8; 2];
+        connection.read_exact(&mut reply_checksum)?;
+        let reply_checksum = decode_hex(&reply_checksum)?[0];
+        if reply_checksum != checksum(&reply) {
+            connection.get_mut().write_all(b"-")?;
+            bail!("Bad checksum in reply from GDB stub at {}", self.address);
+        }
+        connection.get_mut().write_all(b"+")?;
+
+        decode_rle(&reply)
+    }
+
+    pub fn read_into(&self, address: u32, buf: &mut [u8]) -> Result<()> {
+        for (i, chunk) in buf.chunks_mut(MAX_READ_SIZE).enumerate() {
+            let chunk_address = address + (i * MAX_READ_SIZE) as u32;
+            let command = format!("m{chunk_address:x},{:x}", chunk.len());
+            let reply = self.request(command.as_bytes())?;
+            if reply.first() == Some(&b'E') {
+                bail!("GDB stub at {} failed to read memory at {chunk_address:08X}: {}", self.address, String::from_utf8_lossy(&reply));
+            }
+
+            let data = decode_hex(&reply)?;
+            if data.len() != chunk.len() {
+                bail!("Expected {} bytes from GDB stub at {} but got {}", chunk.len(), self.address, data.len());
+            }
+            chunk.copy_from_slice(&data);
+        }
+
+        Ok(())
+    }
+}
+
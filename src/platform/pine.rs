@@ -0,0 +1,144 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+pub const PINE_DEFAULT_SLOT: u16 = 28011;
+const PINE_TIMEOUT: Duration = Duration::from_secs(1);
+const PINE_HEADER_SIZE: usize = 4;
+const PINE_READ8: u8 = 0;
+const PINE_READ64: u8 = 3;
+const PINE_RESULT_OK: u8 = 0;
+
+/// PINE uses Unix sockets on Unix-likes and TCP on Windows. Windows builds running under Wine
+/// still use TCP, though.
+#[derive(Debug)]
+enum PineStream {
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl PineStream {
+    fn set_timeouts(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+            Self::Tcp(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+        }
+    }
+
+    fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => (&*stream).write_all(buf),
+            Self::Tcp(stream) => (&*stream).write_all(buf),
+        }
+    }
+
+    fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(stream) => (&*stream).read_exact(buf),
+            Self::Tcp(stream) => (&*stream).read_exact(buf),
+        }
+    }
+}
+
+/// Client for the PINE IPC protocol supported by DuckStation and PCSX2
+#[derive(Debug)]
+pub struct PineClient {
+    name: String,
+    stream: PineStream,
+}
+
+impl PineClient {
+    #[cfg(unix)]
+    fn open_stream(name: &str, slot: u16) -> Result<PineStream> {
+        let runtime_dir = std::env::var("XDG_RUNTIME_DIR")
+            .or_else(|_| std::env::var("TMPDIR"))
+            .unwrap_or_else(|_| String::from("/tmp"));
+        let path = if slot == PINE_DEFAULT_SLOT {
+            format!("{runtime_dir}/{name}.sock")
+        } else {
+            format!("{runtime_dir}/{name}.sock.{slot}")
+        };
+
+        Ok(PineStream::Unix(UnixStream::connect(path)?))
+    }
+
+    #[cfg(windows)]
+    fn open_stream(name: &str, slot: u16) -> Result<PineStream> {
+        Self::open_tcp_stream(name, slot)
+    }
+
+    fn open_tcp_stream(_name: &str, slot: u16) -> Result<PineStream> {
+        Ok(PineStream::Tcp(TcpStream::connect(("127.0.0.1", slot))?))
+    }
+
+    /// Connect to the PINE server of an emulator. If `force_tcp` is true, connect over TCP even
+    /// on platforms that normally use Unix sockets.
+    pub fn connect(name: &str, slot: u16, force_tcp: bool) -> Result<Self> {
+        let stream = if force_tcp {
+            Self::open_tcp_stream(name, slot)
+        } else {
+            Self::open_stream(name, slot)
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => bail!("Failed to connect to {name} PINE server on slot {slot}: {e}"),
+        };
+        stream.set_timeouts(PINE_TIMEOUT)?;
+
+        Ok(Self {
+            name: String::from(name),
+            stream,
+        })
+    }
+
+    pub fn read_into(&self, address: u32, buf: &mut [u8]) -> Result<()> {
+        // batch up the whole read into a single message, reading 8 bytes at a time where we can
+        let mut request = vec![0u8; PINE_HEADER_SIZE];
+        let mut offset = 0usize;
+        while offset < buf.len() {
+            let opcode = if buf.len() - offset >= 8 { PINE_READ64 } else { PINE_READ8 };
+            request.push(opcode);
+            request.extend_from_slice(&(address + offset as u32).to_le_bytes());
+            offset += if opcode == PINE_READ64 { 8 } else { 1 };
+        }
+        let request_size = request.len() as u32;
+        request[..PINE_HEADER_SIZE].copy_from_slice(&request_size.to_le_bytes());
+
+        let stream = &self.stream;
+        stream.write_all(&request)?;
+
+        let mut header = [0u8; PINE_HEADER_SIZE];
+        stream.read_exact(&mut header)?;
+        let reply_size = u32::from_le_bytes(header) as usize;
+        // always consume the whole reply so the stream stays in sync even if something went wrong
+        let mut reply = vec![0u8; reply_size.saturating_sub(PINE_HEADER_SIZE)];
+        stream.read_exact(&mut reply)?;
+
+        // the reply is a result code followed by the data
+        match reply.split_first() {
+            Some((&PINE_RESULT_OK, data)) if data.len() == buf.len() => {
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            Some((&PINE_RESULT_OK, data)) => {
+                bail!("Expected {} bytes from {} PINE server but got {}", buf.len(), self.name, data.len())
+            }
+            _ => bail!("{} PINE server failed to read {} bytes at {address:08X}", self.name, buf.len()),
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use std::ffi::{CString, c_void};
+use std::fs;
 use std::io::Error;
 use std::ptr;
 
 use anyhow::{bail, Result};
+use sysinfo::Pid;
 
-use super::SharedMemoryClient;
+use super::{ProcessMemoryClient, SharedMemoryClient};
 
 unsafe fn close_shm(name: &str, fd: libc::c_int) {
     if fd == -1 {
@@ -30,7 +32,7 @@ pub(super) struct UnixSharedMemoryClient {
 }
 
 impl SharedMemoryClient for UnixSharedMemoryClient {
-    fn open(name: &str, size: usize) -> Result<Self> {
+    fn open(name: &str, max_size: usize) -> Result<Self> {
         let c_name = CString::new(name)?;
         let shm_fd = unsafe {
             libc::shm_open(c_name.as_ptr(), libc::O_RDONLY, 0)
@@ -40,6 +42,22 @@ impl SharedMemoryClient for UnixSharedMemoryClient {
             bail!("Failed to open shared memory object {name}: {errno}");
         }
 
+        // mapping past the end of the object would give us a SIGBUS when we read there
+        let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+        let status = unsafe { libc::fstat(shm_fd, stat.as_mut_ptr()) };
+        if status == -1 {
+            let errno = Error::last_os_error();
+            unsafe { close_shm(name, shm_fd) };
+            bail!("Failed to get size of shared memory object {name}: {errno}");
+        }
+        // SAFETY: fstat succeeded, so it filled in the struct
+        let object_size = unsafe { stat.assume_init() }.st_size.max(0) as usize;
+        let size = object_size.min(max_size);
+        if size == 0 {
+            unsafe { close_shm(name, shm_fd) };
+            bail!("Shared memory object {name} is empty");
+        }
+
         let base = unsafe {
             libc::mmap(ptr::null_mut(), size, libc::PROT_READ, libc::MAP_SHARED, shm_fd, 0)
         };
@@ -80,4 +98,214 @@ impl Drop for UnixSharedMemoryClient {
             close_shm(&self.name, self.shm_fd);
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub(super) struct UnixProcessMemoryClient {
+    pid: libc::pid_t,
+}
+
+impl ProcessMemoryClient for UnixProcessMemoryClient {
+    fn open(pid: Pid) -> Result<Self> {
+        // there's nothing to open; permission to read the process's memory is checked on every read
+        Ok(Self {
+            pid: pid.as_u32() as libc::pid_t,
+        })
+    }
+
+    fn readable_regions(&self) -> Result<Vec<(usize, usize)>> {
+        let maps = fs::read_to_string(format!("/proc/{}/maps", self.pid))?;
+        let mut regions = Vec::new();
+        for line in maps.lines() {
+            let mut fields = line.split_whitespace();
+            let (Some(range), Some(permissions)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+
+            if !permissions.starts_with('r') {
+                continue;
+            }
+
+            let Some((start, end)) = range.split_once('-') else {
+                continue;
+            };
+            let (Ok(start), Ok(end)) = (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) else {
+                continue;
+            };
+
+            regions.push((start, end - start));
+        }
+
+        Ok(regions)
+    }
+
+    fn read_into(&self, address: usize, buf: &mut [u8]) -> Result<()> {
+        let size = buf.len();
+        let local = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut c_void,
+            iov_len: size,
+        };
+        let remote = libc::iovec {
+            iov_base: address as *mut c_void,
+            iov_len: size,
+        };
+
+        // SAFETY: the local iovec points to a buffer we have exclusive access to that's exactly as
+        // large as the length we're giving. the remote iovec is in another process and is
+        // validated by the kernel.
+        let bytes_read = unsafe { libc::process_vm_readv(self.pid, &local, 1, &remote, 1, 0) };
+        if bytes_read == -1 {
+            let errno = Error::last_os_error();
+            bail!("Failed to read {size} bytes at {address:#X} from process {}: {errno}", self.pid);
+        }
+
+        if bytes_read as usize != size {
+            bail!("Only read {bytes_read} of {size} bytes at {address:#X} from process {}", self.pid);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod proc_connector {
+    use std::io::Error;
+    use std::mem;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    use anyhow::{bail, Result};
+
+    use super::super::ProcessEvents;
+
+    const CN_IDX_PROC: u32 = 1;
+    const CN_VAL_PROC: u32 = 1;
+    const PROC_CN_MCAST_LISTEN: u32 = 1;
+    const PROC_EVENT_EXEC: u32 = 0x00000002;
+    const PROC_EVENT_EXIT: u32 = 0x80000000;
+    const RECV_BUFFER_SIZE: usize = 4096;
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone)]
+    struct CnMsg {
+        idx: u32,
+        val: u32,
+        seq: u32,
+        ack: u32,
+        len: u16,
+        flags: u16,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone)]
+    struct ListenMessage {
+        header: libc::nlmsghdr,
+        message: CnMsg,
+        op: u32,
+    }
+
+    struct NetlinkSocket(libc::c_int);
+
+    impl Drop for NetlinkSocket {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.0) };
+        }
+    }
+
+    fn subscribe() -> Result<NetlinkSocket> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_CONNECTOR) };
+        if fd == -1 {
+            bail!("Failed to create netlink socket: {}", Error::last_os_error());
+        }
+        let socket = NetlinkSocket(fd);
+
+        // SAFETY: sockaddr_nl is plain old data, so all zeroes is a valid value
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_pid = std::process::id();
+        addr.nl_groups = CN_IDX_PROC;
+        let status = unsafe {
+            libc::bind(fd, &addr as *const _ as *const libc::sockaddr, mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+        };
+        if status == -1 {
+            bail!("Failed to bind netlink socket: {}", Error::last_os_error());
+        }
+
+        // SAFETY: nlmsghdr is plain old data
+        let mut header: libc::nlmsghdr = unsafe { mem::zeroed() };
+        header.nlmsg_len = mem::size_of::<ListenMessage>() as u32;
+        header.nlmsg_type = libc::NLMSG_DONE as u16;
+        header.nlmsg_pid = std::process::id();
+        let request = ListenMessage {
+            header,
+            message: CnMsg {
+                idx: CN_IDX_PROC,
+                val: CN_VAL_PROC,
+                seq: 0,
+                ack: 0,
+                len: mem::size_of::<u32>() as u16,
+                flags: 0,
+            },
+            op: PROC_CN_MCAST_LISTEN,
+        };
+        let sent = unsafe {
+            libc::send(fd, &request as *const _ as *const libc::c_void, mem::size_of::<ListenMessage>(), 0)
+        };
+        if sent == -1 {
+            bail!("Failed to subscribe to process events: {}", Error::last_os_error());
+        }
+
+        Ok(socket)
+    }
+
+    /// Get the type of a process event message, if that's what the message is
+    fn event_type(message: &[u8]) -> Option<u32> {
+        let offset = mem::size_of::<libc::nlmsghdr>() + mem::size_of::<CnMsg>();
+        let bytes = message.get(offset..offset + 4)?;
+        Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+    }
+
+    /// Listen for processes starting and exiting using the kernel's process events connector.
+    /// This needs CAP_NET_ADMIN, so it won't be available for most users.
+    pub fn watch_processes() -> Result<Arc<ProcessEvents>> {
+        let socket = subscribe()?;
+        let events = Arc::new(ProcessEvents::new());
+
+        let thread_events = Arc::clone(&events);
+        thread::spawn(move || {
+            let mut buf = [0u8; RECV_BUFFER_SIZE];
+            loop {
+                let received = unsafe { libc::recv(socket.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+                if received <= 0 {
+                    let errno = Error::last_os_error();
+                    if errno.raw_os_error() == Some(libc::ENOBUFS) {
+                        // we fell behind and lost some events, so we have to assume something
+                        // changed
+                        thread_events.changed.store(true, Ordering::Relaxed);
+                        continue;
+                    }
+
+                    log::debug!("Process event listener stopped: {errno}");
+                    break;
+                }
+
+                if matches!(event_type(&buf[..received as usize]), Some(PROC_EVENT_EXEC | PROC_EVENT_EXIT)) {
+                    thread_events.changed.store(true, Ordering::Relaxed);
+                }
+            }
+
+            thread_events.is_alive.store(false, Ordering::Relaxed);
+        });
+
+        Ok(events)
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(super) use proc_connector::watch_processes;
+
+#[cfg(not(target_os = "linux"))]
+pub(super) fn watch_processes() -> Result<std::sync::Arc<super::ProcessEvents>> {
+    bail!("Process events are not supported on this platform");
+}
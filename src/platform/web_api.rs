@@ -0,0 +1,80 @@
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+
+pub const PCSX_REDUX_WEB_PORT: u16 = 8080;
+const RAM_PATH: &str = "/api/v1/cpu/ram/raw";
+const WEB_API_TIMEOUT: Duration = Duration::from_secs(1);
+// the API only lets us download all of RAM at once, so we keep a copy around for about a frame. the
+// game can't change anything in less time than that, and it covers all the reads made during an
+// update without downloading RAM again for each one.
+const SNAPSHOT_MAX_AGE: Duration = Duration::from_millis(16);
+
+#[derive(Debug)]
+struct RamSnapshot {
+    taken: Instant,
+    data: Vec<u8>,
+}
+
+/// Client for the PCSX-Redux web server's debug API
+#[derive(Debug)]
+pub struct WebApiClient {
+    addr: SocketAddr,
+    snapshot: RefCell<RamSnapshot>,
+}
+
+impl WebApiClient {
+    pub fn connect(port: u16) -> Result<Self> {
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let data = fetch_ram(addr)?;
+
+        Ok(Self {
+            addr,
+            snapshot: RefCell::new(RamSnapshot { taken: Instant::now(), data }),
+        })
+    }
+
+    pub fn read_into(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        let mut snapshot = self.snapshot.borrow_mut();
+        if snapshot.taken.elapsed() > SNAPSHOT_MAX_AGE {
+            snapshot.data = fetch_ram(self.addr)?;
+            snapshot.taken = Instant::now();
+        }
+
+        let Some(data) = snapshot.data.get(offset..offset + buf.len()) else {
+            bail!("Read of {} bytes at offset {offset:#X} is outside the RAM returned by the web API", buf.len());
+        };
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+fn fetch_ram(addr: SocketAddr) -> Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&addr, WEB_API_TIMEOUT)?;
+    stream.set_read_timeout(Some(WEB_API_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEB_API_TIMEOUT))?;
+    write!(stream, "GET {RAM_PATH} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n")?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("Malformed response from PCSX-Redux web API"))?;
+    let headers = String::from_utf8_lossy(&response[..header_end]).to_lowercase();
+
+    let status = headers.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        bail!("PCSX-Redux web API returned {status}");
+    }
+
+    if headers.contains("transfer-encoding: chunked") {
+        bail!("Chunked responses from the PCSX-Redux web API are not supported");
+    }
+
+    Ok(response.split_off(header_end + 4))
+}
@@ -1,9 +1,27 @@
+use std::ffi::c_void;
+use std::mem;
+
 use anyhow::{bail, Result};
+use sysinfo::Pid;
 use windows::core::{PCWSTR, HSTRING};
 use windows::Win32::Foundation::{HANDLE, CloseHandle, GetLastError};
-use windows::Win32::System::Memory::{FILE_MAP_READ, MEMORY_MAPPED_VIEW_ADDRESS, OpenFileMappingW, MapViewOfFile, UnmapViewOfFile};
+use windows::Win32::System::Diagnostics::Debug::ReadProcessMemory;
+use windows::Win32::System::Memory::{
+    FILE_MAP_READ, MEM_COMMIT, MEMORY_BASIC_INFORMATION, MEMORY_MAPPED_VIEW_ADDRESS, PAGE_GUARD, PAGE_NOACCESS,
+    OpenFileMappingW, MapViewOfFile, UnmapViewOfFile, VirtualQuery, VirtualQueryEx,
+};
+use windows::Win32::System::RemoteDesktop::{WTS_CURRENT_SERVER_HANDLE, WTS_SESSION_INFOW, WTSEnumerateSessionsW, WTSFreeMemory};
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ};
+use windows::Win32::UI::WindowsAndMessaging::FindWindowW;
+
+use super::{ProcessMemoryClient, SharedMemoryClient};
 
-use super::SharedMemoryClient;
+/// Find the handle of the top-level window with the given title
+pub fn find_window(title: &str) -> Result<u64> {
+    let wide_title = HSTRING::from(title);
+    let window = unsafe { FindWindowW(PCWSTR::null(), PCWSTR(wide_title.as_ptr())) }?;
+    Ok(window.0 as u64)
+}
 
 unsafe fn close_handle(name: &str, handle: HANDLE) {
     if let Err(e) = unsafe { CloseHandle(handle) } {
@@ -11,6 +29,34 @@ unsafe fn close_handle(name: &str, handle: HANDLE) {
     }
 }
 
+/// IDs of the sessions on this machine, for finding mappings created in other sessions
+fn session_ids() -> Vec<u32> {
+    let mut sessions: *mut WTS_SESSION_INFOW = std::ptr::null_mut();
+    let mut count = 0u32;
+    if let Err(e) = unsafe { WTSEnumerateSessionsW(Some(WTS_CURRENT_SERVER_HANDLE), 0, 1, &mut sessions, &mut count) } {
+        log::debug!("Failed to enumerate sessions: {e}");
+        return Vec::new();
+    }
+
+    // SAFETY: WTSEnumerateSessionsW succeeded, so it gave us an array of `count` sessions
+    let ids = unsafe { std::slice::from_raw_parts(sessions, count as usize) }.iter().map(|session| session.SessionId).collect();
+    unsafe { WTSFreeMemory(sessions as *mut c_void) };
+    ids
+}
+
+/// Names the mapping might be found under. Mappings created by a process running elevated or in
+/// another session (e.g. as a service) live outside our session's Local\ namespace.
+fn mapping_names(name: &str) -> Vec<String> {
+    if name.contains('\\') {
+        // the name already specifies a namespace
+        return vec![String::from(name)];
+    }
+
+    let mut names = vec![String::from(name), format!("Global\\{name}")];
+    names.extend(session_ids().into_iter().map(|id| format!("Session\\{id}\\{name}")));
+    names
+}
+
 #[derive(Debug)]
 pub(super) struct WindowsSharedMemoryClient {
     name: String,
@@ -20,16 +66,31 @@ pub(super) struct WindowsSharedMemoryClient {
 }
 
 impl SharedMemoryClient for WindowsSharedMemoryClient {
-    fn open(name: &str, size: usize) -> Result<Self> {
-        let wide_name = HSTRING::from(name);
-        let p_name = PCWSTR(wide_name.as_ptr());
-        let handle = match unsafe { OpenFileMappingW(FILE_MAP_READ.0, false, p_name) } {
-            Ok(handle) => handle,
-            Err(e) => bail!("Failed to open shared memory mapping {name}: {e}"),
+    fn open(name: &str, max_size: usize) -> Result<Self> {
+        let mut handle = None;
+        let mut last_error = None;
+        for mapping_name in mapping_names(name) {
+            let wide_name = HSTRING::from(mapping_name.as_str());
+            let p_name = PCWSTR(wide_name.as_ptr());
+            match unsafe { OpenFileMappingW(FILE_MAP_READ.0, false, p_name) } {
+                Ok(mapping) => {
+                    log::debug!("Opened shared memory mapping {mapping_name}");
+                    handle = Some(mapping);
+                    break;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        let Some(handle) = handle else {
+            match last_error {
+                Some(e) => bail!("Failed to open shared memory mapping {name}: {e}"),
+                None => bail!("Failed to open shared memory mapping {name}"),
+            }
         };
 
+        // map the whole thing, since asking for more than the mapping holds fails
         let base = unsafe {
-            MapViewOfFile(handle, FILE_MAP_READ, 0, 0, size)
+            MapViewOfFile(handle, FILE_MAP_READ, 0, 0, 0)
         };
         if base.Value.is_null() {
             let error = unsafe { GetLastError() };
@@ -38,6 +99,15 @@ impl SharedMemoryClient for WindowsSharedMemoryClient {
             bail!("Failed to map shared memory {}: {} ({:08X})", name, hresult.message(), error.0);
         }
 
+        let mut info = MEMORY_BASIC_INFORMATION::default();
+        let info_size = unsafe { VirtualQuery(Some(base.Value as *const c_void), &mut info, mem::size_of::<MEMORY_BASIC_INFORMATION>()) };
+        let size = if info_size == 0 {
+            // if we can't tell how big the view is, assume it's as big as it's allowed to be
+            max_size
+        } else {
+            info.RegionSize.min(max_size)
+        };
+
         Ok(Self {
             name: String::from(name),
             handle,
@@ -67,4 +137,82 @@ impl Drop for WindowsSharedMemoryClient {
             close_handle(&self.name, self.handle);
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub(super) struct WindowsProcessMemoryClient {
+    pid: u32,
+    handle: HANDLE,
+}
+
+impl ProcessMemoryClient for WindowsProcessMemoryClient {
+    fn open(pid: Pid) -> Result<Self> {
+        let pid = pid.as_u32();
+        let handle = match unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid) } {
+            Ok(handle) => handle,
+            Err(e) => bail!("Failed to open process {pid}: {e}"),
+        };
+
+        Ok(Self { pid, handle })
+    }
+
+    fn readable_regions(&self) -> Result<Vec<(usize, usize)>> {
+        let mut regions = Vec::new();
+        let mut address = 0usize;
+        loop {
+            let mut info = MEMORY_BASIC_INFORMATION::default();
+            let info_size = unsafe {
+                VirtualQueryEx(self.handle, Some(address as *const c_void), &mut info, mem::size_of::<MEMORY_BASIC_INFORMATION>())
+            };
+            if info_size == 0 {
+                // we've walked off the end of the address space
+                break;
+            }
+
+            let base = info.BaseAddress as usize;
+            let size = info.RegionSize;
+            let is_readable = info.State == MEM_COMMIT && info.Protect.0 & (PAGE_NOACCESS.0 | PAGE_GUARD.0) == 0;
+            if is_readable {
+                regions.push((base, size));
+            }
+
+            match base.checked_add(size) {
+                Some(next) if size > 0 => address = next,
+                _ => break,
+            }
+        }
+
+        Ok(regions)
+    }
+
+    fn read_into(&self, address: usize, buf: &mut [u8]) -> Result<()> {
+        let size = buf.len();
+        let mut bytes_read = 0usize;
+        if let Err(e) = unsafe {
+            ReadProcessMemory(self.handle, address as *const c_void, buf.as_mut_ptr() as *mut c_void, size, Some(&mut bytes_read as *mut usize))
+        } {
+            bail!("Failed to read {size} bytes at {address:#X} from process {}: {e}", self.pid);
+        }
+
+        if bytes_read != size {
+            bail!("Only read {bytes_read} of {size} bytes at {address:#X} from process {}", self.pid);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for WindowsProcessMemoryClient {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { CloseHandle(self.handle) } {
+            log::error!("Failed to close handle to process {}: {}", self.pid, e);
+        }
+    }
+}
+
+// TODO: WMI or ETW could tell us when processes start and stop, but both require a fair amount of
+//  COM/tracing setup (and ETW needs admin), so for now we always poll on Windows. the README notes
+//  this limitation, so update it too if this gets implemented.
+pub(super) fn watch_processes() -> Result<std::sync::Arc<super::ProcessEvents>> {
+    bail!("Process events are not supported on Windows");
+}
@@ -0,0 +1,423 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::SplitType;
+
+const RACE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long the race host waits to hear from a connection before giving up on it
+const RACE_READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often a runner's autosplitter checks in with the race host when it has nothing else to send
+const RACE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// Longest line the race host will read. Runner events and HTTP request lines are much shorter.
+const MAX_LINE_LENGTH: u64 = 16 * 1024;
+/// Most HTTP headers the race host will read before responding
+const MAX_HEADERS: usize = 100;
+const RACE_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Galerians race</title>
+<style>
+body { font-family: sans-serif; background: #111; color: #eee; }
+table { border-collapse: collapse; }
+td, th { padding: 4px 12px; text-align: left; }
+.disconnected { color: #777; }
+</style>
+</head>
+<body>
+<table>
+<thead><tr><th>Runner</th><th>State</th><th>Splits</th><th>Last split</th><th>Map</th><th>Room</th></tr></thead>
+<tbody id="runners"></tbody>
+</table>
+<script>
+async function update() {
+    try {
+        const runners = await (await fetch('/state')).json();
+        const rows = runners.map(r => {
+            const tr = document.createElement('tr');
+            if (!r.connected) tr.className = 'disconnected';
+            const progress = r.segment_count === null ? r.splits : `${r.splits} / ${r.segment_count}`;
+            for (const value of [r.runner, r.run_state, progress, r.last_split ?? '-', r.map ?? '-', r.room ?? '-']) {
+                const td = document.createElement('td');
+                td.textContent = value;
+                tr.appendChild(td);
+            }
+            return tr;
+        });
+        document.getElementById('runners').replaceChildren(...rows);
+    } catch (e) {}
+}
+update();
+setInterval(update, 1000);
+</script>
+</body>
+</html>
+"#;
+
+/// First line an autosplitter sends to the race host, before its event stream
+#[derive(Debug, Serialize, Deserialize)]
+struct RaceHello {
+    runner: String,
+}
+
+/// What the race host knows about a single runner
+#[derive(Debug, Clone, Default, Serialize)]
+struct RunnerState {
+    runner: String,
+    connected: bool,
+    run_state: String,
+    split_type: Option<String>,
+    segment_count: Option<usize>,
+    splits: usize,
+    /// Name of the route event the runner last split on
+    last_split: Option<String>,
+    map: Option<u16>,
+    room: Option<u16>,
+    updated: f64,
+}
+
+impl RunnerState {
+    fn new(runner: String) -> Self {
+        Self {
+            runner,
+            run_state: String::from("not_started"),
+            ..Self::default()
+        }
+    }
+
+    fn apply(&mut self, event: &Value) {
+        let field = |name: &str| event.get(name).and_then(Value::as_str);
+        let number = |name: &str| event.get(name).and_then(Value::as_u64).and_then(|n| u16::try_from(n).ok());
+
+        if let Some(timestamp) = event.get("timestamp").and_then(Value::as_f64) {
+            self.updated = timestamp;
+        }
+
+        match field("event") {
+            Some("run_state") => if let Some(state) = field("state") {
+                self.run_state = String::from(state);
+            },
+            Some("split_type") => if let Some(split_type) = field("split_type") {
+                self.split_type = Some(String::from(split_type));
+                self.segment_count = SplitType::try_from(split_type).ok().and_then(|split_type| split_type.segment_count());
+            },
+            Some("split") => {
+                if field("reason") == Some("run_start") {
+                    self.splits = 0;
+                } else {
+                    self.splits += 1;
+                }
+                self.last_split = field("name").map(String::from);
+                self.map = number("map");
+                self.room = number("room");
+            }
+            Some("unsplit") => {
+                self.splits = self.splits.saturating_sub(1);
+                self.map = number("map");
+                self.room = number("room");
+            }
+            Some("room") => {
+                self.map = number("map");
+                self.room = number("room");
+            }
+            // sent when an autosplitter (re)connects, in case the host missed some of its events
+            Some("progress") => {
+                self.splits = event.get("splits").and_then(Value::as_u64).map_or(0, |splits| splits as usize);
+                self.last_split = field("last_split").map(String::from);
+                self.map = number("map");
+                self.room = number("room");
+            }
+            Some("reset") => {
+                self.splits = 0;
+                self.last_split = None;
+                self.map = None;
+                self.room = None;
+            }
+            _ => (),
+        }
+    }
+}
+
+type RaceState = Arc<Mutex<BTreeMap<String, RunnerState>>>;
+
+/// Sends this autosplitter's events to a race host on a background thread so that network
+/// problems don't hold up splitting
+#[derive(Debug, Clone)]
+pub struct RaceReporter {
+    events: Sender<String>,
+}
+
+impl RaceReporter {
+    pub fn start(host: String, runner: String) -> Self {
+        let (events, receiver) = mpsc::channel::<String>();
+
+        thread::spawn(move || {
+            let mut connection: Option<TcpStream> = None;
+            // keep track of what the host should know about us, so we can catch it up on anything it
+            // missed while it couldn't be reached
+            let mut sent_state = RunnerState::new(runner.clone());
+            loop {
+                let line = match receiver.recv_timeout(RACE_KEEP_ALIVE) {
+                    Ok(line) => line,
+                    // let the host know we're still here, so it doesn't time us out
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Some(stream) = &mut connection && let Err(e) = stream.write_all(b"\n") {
+                            log::warn!("Lost connection to race host {host}: {e}");
+                            connection = None;
+                        }
+                        continue;
+                    }
+                    // the thread exits once all the senders are dropped and the channel closes
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                if let Ok(event) = serde_json::from_str::<Value>(&line) {
+                    sent_state.apply(&event);
+                }
+
+                let lines = match &connection {
+                    Some(_) => vec![line],
+                    // the catch-up events already include this one
+                    None => catch_up_events(&sent_state).iter().map(Value::to_string).collect(),
+                };
+                let stream = match &mut connection {
+                    Some(stream) => stream,
+                    None => match connect_to_host(&host, &runner) {
+                        Ok(stream) => {
+                            log::info!("Connected to race host {host}");
+                            connection.insert(stream)
+                        }
+                        Err(e) => {
+                            log::debug!("Could not connect to race host {host}: {e}");
+                            continue;
+                        }
+                    },
+                };
+
+                for line in lines {
+                    if let Err(e) = stream.write_all(line.as_bytes()).and_then(|_| stream.write_all(b"\n")) {
+                        log::warn!("Lost connection to race host {host}: {e}");
+                        connection = None;
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self { events }
+    }
+
+    pub fn send(&self, line: String) {
+        // if the thread is gone, there's nothing to be done about it
+        let _ = self.events.send(line);
+    }
+}
+
+/// Events that bring a race host up to date with everything the runner has sent so far
+fn catch_up_events(state: &RunnerState) -> Vec<Value> {
+    let mut events = Vec::new();
+    if let Some(split_type) = &state.split_type {
+        events.push(json!({"event": "split_type", "timestamp": state.updated, "split_type": split_type}));
+    }
+    events.push(json!({"event": "run_state", "timestamp": state.updated, "state": state.run_state}));
+    events.push(json!({
+        "event": "progress",
+        "timestamp": state.updated,
+        "splits": state.splits,
+        "last_split": state.last_split,
+        "map": state.map,
+        "room": state.room,
+    }));
+    events
+}
+
+fn connect_to_host(host: &str, runner: &str) -> Result<TcpStream> {
+    let Some(addr) = host.to_socket_addrs()?.next() else {
+        bail!("Could not resolve race host address {host}");
+    };
+    let mut stream = TcpStream::connect_timeout(&addr, RACE_TIMEOUT)?;
+    stream.set_write_timeout(Some(RACE_TIMEOUT))?;
+
+    let hello = serde_json::to_string(&RaceHello { runner: String::from(runner) })?;
+    stream.write_all(hello.as_bytes())?;
+    stream.write_all(b"\n")?;
+
+    Ok(stream)
+}
+
+/// Read a line from a race connection, giving up if it's unreasonably long so that a bad peer can't
+/// make us buffer as much as it likes
+fn read_line(reader: &mut BufReader<TcpStream>, line: &mut String) -> Result<usize> {
+    let length = reader.by_ref().take(MAX_LINE_LENGTH).read_line(line)?;
+    if length as u64 == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        bail!("Line is longer than {MAX_LINE_LENGTH} bytes");
+    }
+    Ok(length)
+}
+
+fn serve_http(request_line: &str, mut reader: BufReader<TcpStream>, state: &RaceState) -> Result<()> {
+    // we don't care about any of the headers, but we need to read them before responding
+    for _ in 0..MAX_HEADERS {
+        let mut header = String::new();
+        if read_line(&mut reader, &mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body) = match path {
+        "/" | "/index.html" => ("200 OK", "text/html; charset=utf-8", String::from(RACE_PAGE)),
+        "/state" => {
+            let runners: Vec<RunnerState> = state.lock().map(|runners| runners.values().cloned().collect()).unwrap_or_default();
+            ("200 OK", "application/json", serde_json::to_string(&runners)?)
+        }
+        _ => ("404 Not Found", "text/plain", String::from("Not found")),
+    };
+
+    let stream = reader.get_mut();
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn handle_runner(hello: &str, mut reader: BufReader<TcpStream>, state: &RaceState) -> Result<()> {
+    let RaceHello { runner } = serde_json::from_str(hello)?;
+    log::info!("Runner {runner} connected");
+    if let Ok(mut runners) = state.lock() {
+        runners.entry(runner.clone()).or_insert_with(|| RunnerState::new(runner.clone())).connected = true;
+    }
+
+    let mut line = String::new();
+    loop {
+        match read_line(&mut reader, &mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => {
+                log::warn!("Dropping runner {runner}: {e}");
+                break;
+            }
+        }
+
+        // blank lines just keep the connection alive
+        if !line.trim().is_empty() {
+            match serde_json::from_str::<Value>(&line) {
+                Ok(event) => if let Ok(mut runners) = state.lock() && let Some(runner_state) = runners.get_mut(&runner) {
+                    runner_state.apply(&event);
+                },
+                Err(e) => log::warn!("Invalid event from runner {runner}: {e}"),
+            }
+        }
+        line.clear();
+    }
+
+    log::info!("Runner {runner} disconnected");
+    if let Ok(mut runners) = state.lock() && let Some(runner_state) = runners.get_mut(&runner) {
+        runner_state.connected = false;
+    }
+
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, state: &RaceState) -> Result<()> {
+    // runners check in regularly, so anyone who goes quiet for this long isn't coming back
+    stream.set_read_timeout(Some(RACE_READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(RACE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut first_line = String::new();
+    read_line(&mut reader, &mut first_line)?;
+
+    // browsers and autosplitters share a port. browsers always start with an HTTP request line,
+    // and autosplitters always start with a JSON object.
+    if first_line.starts_with('{') {
+        handle_runner(&first_line, reader, state)
+    } else {
+        serve_http(&first_line, reader, state)
+    }
+}
+
+/// Run as a race host, collecting events from other autosplitters on the given address and port
+/// and serving a race overview on the same port
+pub fn host(address: IpAddr, port: u16) -> Result<()> {
+    let listener = TcpListener::bind((address, port))?;
+    log::info!("Race host listening on {address} port {port}; open http://localhost:{port}/ to see the race");
+    if address.is_loopback() {
+        log::info!("Only autosplitters on this computer can join; use --bind 0.0.0.0 to let other computers join");
+    }
+
+    let state: RaceState = Arc::new(Mutex::new(BTreeMap::new()));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept race connection: {e}");
+                continue;
+            }
+        };
+
+        let state = Arc::clone(&state);
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &state) {
+                log::debug!("Race connection error: {e}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_up_events() {
+        let mut sent_state = RunnerState::new(String::from("runner"));
+        for event in [
+            json!({"event": "split_type", "split_type": "KeyEvents"}),
+            json!({"event": "run_state", "state": "active"}),
+            json!({"event": "split", "reason": "run_start"}),
+            json!({"event": "split", "reason": "route_event", "name": "Security Card", "map": 0, "room": 2}),
+        ] {
+            sent_state.apply(&event);
+        }
+
+        let mut host_state = RunnerState::new(String::from("runner"));
+        for event in catch_up_events(&sent_state) {
+            host_state.apply(&event);
+        }
+
+        assert_eq!(host_state.split_type.as_deref(), Some("KeyEvents"));
+        assert_eq!(host_state.run_state, "active");
+        assert_eq!(host_state.splits, 1);
+        assert_eq!(host_state.last_split.as_deref(), Some("Security Card"));
+        assert_eq!((host_state.map, host_state.room), (Some(0), Some(2)));
+    }
+
+    #[test]
+    fn test_read_line_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut reader = BufReader::new(listener.accept().unwrap().0);
+        client.write_all(b"{\"event\": \"room\"}\n").unwrap();
+        client.write_all(&vec![b'x'; MAX_LINE_LENGTH as usize + 1]).unwrap();
+
+        let mut line = String::new();
+        read_line(&mut reader, &mut line).unwrap();
+        assert_eq!(line, "{\"event\": \"room\"}\n");
+        line.clear();
+        assert!(read_line(&mut reader, &mut line).is_err());
+    }
+}
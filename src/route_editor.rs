@@ -0,0 +1,400 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::Path;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::autosplitter::PROCESS_REFRESH_INTERVAL;
+use crate::game::{EmulatorGame, Game, GameState, Item, Map, NUM_FLAGS, NUM_ITEMS, Stage, can_read_boss_health, can_read_cutscene_id, room_links};
+use crate::platform::{EmulatorSearchOptions, Platform, PlatformRef};
+use crate::splits::{Event, RouteAction, RouteEntry, RouteFile};
+
+const EDITOR_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const HELP: &str = "\
+Commands:
+  add [N]          add candidate event N to the end of the route (default: the most recent one)
+  room             add the current room to the end of the route
+  candidates       list the events seen since the last one was added
+  list             list the events in the route
+  move FROM TO     move route event FROM to position TO
+  note N TEXT      set the note for route event N
+  action N ACTION  set what route event N does: split, skip, pause_game_time, resume_game_time, or log
+  delete N         remove route event N
+  save             write the route to the file
+  quit             write the route to the file and exit
+  help             show this message";
+
+/// Find problems with a single route event that would stop it from ever splitting correctly
+fn check_event(event: &Event, number: usize, errors: &mut Vec<String>) {
+    match *event {
+        Event::Rooms([]) => errors.push(format!("Event {number}: room list is empty")),
+        Event::Flag(_, flag, _) if flag >= NUM_FLAGS => errors.push(format!("Event {number}: flag {flag} is out of range (0-{})", NUM_FLAGS - 1)),
+        Event::FlagCleared(_, flag) if flag >= NUM_FLAGS => errors.push(format!("Event {number}: flag {flag} is out of range (0-{})", NUM_FLAGS - 1)),
+        Event::BossDefeated(boss) if !can_read_boss_health(boss) => {
+            errors.push(format!("Event {number}: {boss:?}'s HP can't be read in any supported game version yet"));
+        }
+        Event::Cutscene(_) if !can_read_cutscene_id() => {
+            errors.push(format!("Event {number}: cutscenes can't be detected in any supported game version yet"));
+        }
+        Event::DoorCount(0) => errors.push(format!("Event {number}: door count must be at least 1")),
+        Event::ItemsCollected(items) if items == 0 || items > NUM_ITEMS => {
+            errors.push(format!("Event {number}: item count {items} is out of range (1-{NUM_ITEMS})"));
+        }
+        Event::All([]) | Event::Any([]) => errors.push(format!("Event {number}: event list is empty")),
+        Event::All(events) | Event::Any(events) => {
+            for event in events {
+                check_event(event, number, errors);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The parts of the game state that can become route events
+#[derive(Debug, Default)]
+struct GameSnapshot {
+    room: (u16, u16),
+    flags: HashSet<(Stage, u32)>,
+    items: Vec<Item>,
+    cutscene: Option<u16>,
+}
+
+impl GameSnapshot {
+    fn take(game: &EmulatorGame) -> Self {
+        let flags = Stage::all()
+            .into_iter()
+            .flat_map(|stage| game.set_flags(stage).into_iter().map(move |flag| (stage, flag)))
+            .collect();
+
+        Self {
+            room: (game.map_id(), game.room_id()),
+            flags,
+            items: game.inventory(),
+            cutscene: game.cutscene_id(),
+        }
+    }
+
+    /// Events that have happened between the previous snapshot and this one
+    fn new_events(&self, previous: &Self) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        if self.room != previous.room && let Some(map) = Map::from_id(self.room.0) {
+            events.push(Event::Room(map, self.room.1));
+        }
+
+        let mut new_flags: Vec<_> = self.flags.difference(&previous.flags).copied().collect();
+        new_flags.sort_by_key(|(stage, flag)| (*stage as u32, *flag));
+        events.extend(new_flags.into_iter().map(|(stage, flag)| Event::Flag(stage, flag, 1)));
+
+        let mut cleared_flags: Vec<_> = previous.flags.difference(&self.flags).copied().collect();
+        cleared_flags.sort_by_key(|(stage, flag)| (*stage as u32, *flag));
+        events.extend(cleared_flags.into_iter().map(|(stage, flag)| Event::FlagCleared(stage, flag)));
+
+        events.extend(self.items.iter().filter(|item| !previous.items.contains(item)).map(|item| Event::Item(*item)));
+        events.extend(previous.items.iter().filter(|item| !self.items.contains(item)).map(|item| Event::ItemUsed(*item)));
+
+        if self.cutscene != previous.cutscene && let Some(id) = self.cutscene {
+            events.push(Event::Cutscene(id));
+        }
+
+        events
+    }
+}
+
+fn parse_index(arg: Option<&str>, len: usize) -> Result<usize> {
+    let arg = arg.ok_or_else(|| anyhow!("Missing event number"))?;
+    match arg.parse::<usize>() {
+        Ok(index @ 1..) if index <= len => Ok(index - 1),
+        _ => bail!("Event number must be between 1 and {len}"),
+    }
+}
+
+/// Read commands from stdin on a background thread so we can keep watching the game while waiting
+/// for the user
+fn read_commands() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Check a route file for mistakes. Problems that would stop the route from working are errors;
+/// rooms that aren't next to each other are only warnings, since a route can have events in
+/// between that aren't rooms, or deliberately skip doors.
+pub fn validate_route(path: &Path, expected_segments: Option<usize>) -> Result<()> {
+    // loading the file already checks that every map, item, stage, and boss is one we know about
+    let route = RouteFile::load(path)?;
+    let (links, room_names) = room_links()?;
+    let room_name = |(map, room): (Map, u16)| room_names.get(&(map, room)).map_or_else(|| format!("{map:?} {room}"), String::clone);
+    let is_known_room = |room: (Map, u16)| links.iter().any(|(source, dest)| *source == room || *dest == room);
+
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut last_rooms: Option<Vec<(Map, u16)>> = None;
+    for (i, entry) in route.events.iter().enumerate() {
+        let number = i + 1;
+        let rooms = match entry.event {
+            Event::Room(map, room) => Some(vec![(map, room)]),
+            Event::Rooms(rooms) => Some(rooms.to_vec()),
+            _ => None,
+        };
+
+        check_event(&entry.event, number, &mut errors);
+
+        let Some(rooms) = rooms else {
+            last_rooms = None;
+            continue;
+        };
+
+        for room in &rooms {
+            if !is_known_room(*room) {
+                errors.push(format!("Event {number}: {:?} {} is not a known room", room.0, room.1));
+            }
+        }
+
+        if let Some(last_rooms) = &last_rooms {
+            let is_adjacent = last_rooms.iter().any(|source| rooms.iter().any(|dest| links.contains(&(*source, *dest))));
+            if !is_adjacent {
+                warnings.push(format!(
+                    "Event {number}: there's no door from {} to {}",
+                    room_name(last_rooms[0]),
+                    rooms.first().map_or_else(String::new, |room| room_name(*room)),
+                ));
+            }
+        }
+        last_rooms = Some(rooms);
+    }
+
+    errors.extend(route.branch_problems());
+    for (i, branch) in route.branches.iter().enumerate() {
+        for alternative in &branch.alternatives {
+            for entry in alternative {
+                let mut branch_errors = Vec::new();
+                check_event(&entry.event, branch.start, &mut branch_errors);
+                errors.extend(branch_errors.into_iter().map(|error| format!("Branch {}: {error}", i + 1)));
+            }
+        }
+    }
+
+    // the last split of the run is on defeating the final boss, which isn't in the route
+    let segments = route.segment_count();
+    if let Some(expected) = expected_segments && expected != segments {
+        errors.push(format!("The route needs {segments} segments in LiveSplit ({} split events plus the final boss), not {expected}", segments - 1));
+    }
+
+    for warning in &warnings {
+        println!("warning: {warning}");
+    }
+    for error in &errors {
+        println!("error: {error}");
+    }
+
+    if !errors.is_empty() {
+        bail!("{} has {} error(s)", path.display(), errors.len());
+    }
+    println!("{} is valid: {} events, {segments} segments, {} warning(s)", path.display(), route.events.len(), warnings.len());
+    Ok(())
+}
+
+/// Builds a route file from events that happen while the user plays through the route
+#[derive(Debug)]
+pub struct RouteEditor {
+    path: PathBuf,
+    route: RouteFile,
+    platform: PlatformRef,
+    game: EmulatorGame,
+    last_snapshot: GameSnapshot,
+    is_connected: bool,
+    last_reconnect_attempt: Instant,
+    /// Events seen since the last time one was added to the route
+    candidates: Vec<Event>,
+}
+
+impl RouteEditor {
+    /// Open the route at the given path, or start a new one if the file doesn't exist yet
+    pub fn open(path: PathBuf, emulator_search: EmulatorSearchOptions) -> Result<Self> {
+        let route = if path.exists() {
+            let route = RouteFile::load(&path)?;
+            log::info!("Loaded {} events from {}", route.events.len(), path.display());
+            route
+        } else {
+            RouteFile::default()
+        };
+
+        let platform = Rc::new(RefCell::new(Platform::new(PROCESS_REFRESH_INTERVAL, emulator_search)));
+        let game = EmulatorGame::connect(&platform);
+        let last_snapshot = GameSnapshot::take(&game);
+
+        Ok(Self {
+            path,
+            route,
+            platform,
+            game,
+            last_snapshot,
+            is_connected: true,
+            last_reconnect_attempt: Instant::now(),
+            candidates: Vec::new(),
+        })
+    }
+
+    fn check_game(&mut self) {
+        if !self.is_connected {
+            if self.last_reconnect_attempt.elapsed() < RECONNECT_INTERVAL {
+                return;
+            }
+
+            self.last_reconnect_attempt = Instant::now();
+            if self.game.reconnect(&self.platform).is_err() {
+                return;
+            }
+
+            log::info!("Game is back");
+            self.is_connected = true;
+            self.last_snapshot = GameSnapshot::take(&self.game);
+            return;
+        }
+
+        match self.game.update(None) {
+            GameState::Connected => (),
+            GameState::GameChanged => log::info!("Game version changed"),
+            GameState::Disconnected => {
+                log::warn!("Lost game; waiting for it to come back...");
+                self.is_connected = false;
+                return;
+            }
+        }
+
+        let snapshot = GameSnapshot::take(&self.game);
+        for event in snapshot.new_events(&self.last_snapshot) {
+            self.candidates.push(event);
+            eprintln!("  {}: {}", self.candidates.len(), event);
+        }
+        self.last_snapshot = snapshot;
+    }
+
+    fn add_event(&mut self, event: Event) {
+        eprintln!("Added {} as route event {}", event, self.route.events.len() + 1);
+        self.route.events.push(RouteEntry { event, note: None, action: RouteAction::Split });
+        self.candidates.clear();
+    }
+
+    fn list_route(&self) {
+        if self.route.events.is_empty() {
+            eprintln!("The route is empty");
+        }
+
+        for (i, entry) in self.route.events.iter().enumerate() {
+            let action = if entry.action == RouteAction::Split { String::new() } else { format!(" [{}]", entry.action.as_str()) };
+            match &entry.note {
+                Some(note) => eprintln!("{:4}: {}{action} ({note})", i + 1, entry.event),
+                None => eprintln!("{:4}: {}{action}", i + 1, entry.event),
+            }
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        self.route.save(&self.path)?;
+        eprintln!("Saved {} events to {}", self.route.events.len(), self.path.display());
+        Ok(())
+    }
+
+    /// Carry out a command from the user. Returns false when it's time to quit.
+    fn run_command(&mut self, line: &str) -> Result<bool> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut args = rest.split_whitespace();
+
+        match command {
+            "" => (),
+            "add" | "a" => {
+                let index = match args.next() {
+                    Some(arg) => parse_index(Some(arg), self.candidates.len())?,
+                    None => self.candidates.len().checked_sub(1).ok_or_else(|| anyhow!("Nothing has happened to add"))?,
+                };
+                self.add_event(self.candidates[index]);
+            }
+            "room" | "r" => {
+                let (map_id, room) = self.last_snapshot.room;
+                let map = Map::from_id(map_id).ok_or_else(|| anyhow!("Not in a valid room"))?;
+                self.add_event(Event::Room(map, room));
+            }
+            "candidates" | "c" => {
+                for (i, event) in self.candidates.iter().enumerate() {
+                    eprintln!("  {}: {}", i + 1, event);
+                }
+            }
+            "list" | "l" => self.list_route(),
+            "move" | "m" => {
+                let from = parse_index(args.next(), self.route.events.len())?;
+                let to = parse_index(args.next(), self.route.events.len())?;
+                let entry = self.route.events.remove(from);
+                self.route.events.insert(to, entry);
+                self.list_route();
+            }
+            "note" | "n" => {
+                let index = parse_index(args.next(), self.route.events.len())?;
+                let note = rest.trim_start().split_once(char::is_whitespace).map_or("", |(_, note)| note.trim());
+                self.route.events[index].note = (!note.is_empty()).then(|| String::from(note));
+            }
+            "action" => {
+                let index = parse_index(args.next(), self.route.events.len())?;
+                let action = args.next().ok_or_else(|| anyhow!("Missing action"))?;
+                self.route.events[index].action = RouteAction::try_from(action)?;
+            }
+            "delete" | "d" => {
+                let index = parse_index(args.next(), self.route.events.len())?;
+                let entry = self.route.events.remove(index);
+                eprintln!("Removed {}", entry.event);
+            }
+            "save" | "s" => self.save()?,
+            "quit" | "q" => {
+                self.save()?;
+                return Ok(false);
+            }
+            "help" | "h" | "?" => eprintln!("{HELP}"),
+            _ => bail!("Unknown command {command}; type help for a list of commands"),
+        }
+
+        Ok(true)
+    }
+
+    pub fn run(mut self) -> Result<()> {
+        eprintln!("Play through the route. Things that could be route events will be listed as they happen.");
+        eprintln!("{HELP}");
+
+        let commands = read_commands();
+        loop {
+            self.check_game();
+
+            loop {
+                match commands.try_recv() {
+                    Ok(line) => match self.run_command(&line) {
+                        Ok(true) => (),
+                        Ok(false) => return Ok(()),
+                        Err(e) => eprintln!("{e}"),
+                    },
+                    Err(TryRecvError::Empty) => break,
+                    // stdin was closed, so there won't be any more commands
+                    Err(TryRecvError::Disconnected) => return self.save(),
+                }
+            }
+
+            thread::sleep(EDITOR_UPDATE_INTERVAL);
+        }
+    }
+}
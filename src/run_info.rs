@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+
+use crate::lss::RetryPolicy;
+use crate::timer::{TimerBackend, TimerBackendType};
+
+#[derive(Debug, Clone, Default)]
+pub struct SegmentInfo {
+    pub name: String,
+    pub comparison_time: Option<String>,
+}
+
+/// Information about the run loaded in LiveSplit that isn't needed in order to split but is useful
+/// to have on hand
+#[derive(Debug, Clone, Default)]
+pub struct RunInfo {
+    pub attempt_count: Option<i64>,
+    pub completed_count: Option<i64>,
+    pub segment_count: Option<i64>,
+    /// Segments we've seen so far, by split index. The LiveSplit server only tells us about the
+    /// current segment, so this gets filled in as the run progresses.
+    pub segments: BTreeMap<i64, SegmentInfo>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CacheRequest {
+    /// Refresh everything we can find out about the run
+    Refresh,
+    /// Fetch information about the current segment
+    CurrentSegment,
+}
+
+/// Cache of run information that's fetched from LiveSplit on a background thread, so that
+/// gathering it doesn't add latency to splitting
+#[derive(Debug)]
+pub struct RunInfoCache {
+    info: Arc<Mutex<RunInfo>>,
+    requests: Sender<CacheRequest>,
+}
+
+impl RunInfoCache {
+    pub fn start(timer_backend: TimerBackendType, live_split_host: String, live_split_port: u16, retry_policy: RetryPolicy) -> Self {
+        let info = Arc::new(Mutex::new(RunInfo::default()));
+        let (requests, receiver) = mpsc::channel();
+
+        let thread_info = Arc::clone(&info);
+        thread::spawn(move || {
+            // we use our own connection so we don't interfere with the main one
+            let mut live_split = None;
+            // the thread exits once the cache is dropped and the channel closes
+            while let Ok(request) = receiver.recv() {
+                let connection = match &mut live_split {
+                    Some(connection) => connection,
+                    None => match timer_backend.open_direct(&live_split_host, live_split_port, retry_policy) {
+                        Ok(connection) => live_split.insert(connection),
+                        Err(e) => {
+                            log::debug!("Run info cache could not connect to LiveSplit: {e}");
+                            continue;
+                        }
+                    },
+                };
+
+                if let Err(e) = handle_request(connection.as_mut(), request, &thread_info) {
+                    log::debug!("Failed to update run info cache: {e}");
+                    if !connection.is_connected() {
+                        live_split = None;
+                    }
+                }
+            }
+        });
+
+        Self { info, requests }
+    }
+
+    fn request(&self, request: CacheRequest) {
+        // if the thread is gone, there's nothing to be done about it, and it's not important
+        // enough to complain about
+        let _ = self.requests.send(request);
+    }
+
+    pub fn refresh(&self) {
+        self.request(CacheRequest::Refresh);
+    }
+
+    pub fn update_current_segment(&self) {
+        self.request(CacheRequest::CurrentSegment);
+    }
+
+    pub fn info(&self) -> RunInfo {
+        self.info.lock().map(|info| info.clone()).unwrap_or_default()
+    }
+
+    pub fn segment(&self, split_index: i64) -> Option<SegmentInfo> {
+        self.info.lock().ok().and_then(|info| info.segments.get(&split_index).cloned())
+    }
+}
+
+fn fetch_current_segment(live_split: &mut dyn TimerBackend, info: &Mutex<RunInfo>) -> Result<()> {
+    let split_index = live_split.get_split_index()?;
+    if split_index < 0 {
+        return Ok(());
+    }
+
+    let name = live_split.get_current_split_name()?;
+    let comparison_time = live_split.get_comparison_split_time()?;
+    if let Ok(mut info) = info.lock() {
+        info.segments.insert(split_index, SegmentInfo { name, comparison_time });
+    }
+
+    Ok(())
+}
+
+fn handle_request(live_split: &mut dyn TimerBackend, request: CacheRequest, info: &Mutex<RunInfo>) -> Result<()> {
+    if request == CacheRequest::Refresh {
+        let attempt_count = live_split.get_attempt_count()?;
+        let completed_count = live_split.get_completed_count()?;
+        let segment_count = live_split.get_segment_count()?;
+        log::debug!("LiveSplit attempts: {attempt_count}, completed: {completed_count}");
+        if let Ok(mut info) = info.lock() {
+            info.attempt_count = Some(attempt_count);
+            info.completed_count = Some(completed_count);
+            info.segment_count = segment_count;
+        }
+    }
+
+    fetch_current_segment(live_split, info)
+}
@@ -0,0 +1,64 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::{RunCategory, SplitType};
+
+/// The name of the game as it appears in LiveSplit
+pub const GAME_NAME: &str = "Galerians";
+
+/// Escape text for use in an XML element or attribute
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Build the contents of a LiveSplit split file with the given segment names, laid out the same way
+/// as the split files included with the autosplitter. The split type is stored in the file's custom
+/// variables so the autosplitter can detect it, if there is one.
+pub fn generate_lss(segment_names: &[String], split_type: Option<SplitType>, run_category: RunCategory) -> String {
+    let is_console = split_type.is_some_and(|split_type| split_type.is_console());
+    let (uses_emulator, platform) = if is_console { ("False", "Console") } else { ("True", "Emulator") };
+
+    let mut lss = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Run version=\"1.7.0\">\n");
+    // writing to a String can't fail
+    let _ = writeln!(lss, "  <GameIcon />\n  <GameName>{GAME_NAME}</GameName>");
+    let _ = writeln!(lss, "  <CategoryName>{}</CategoryName>", escape_xml(run_category.as_str()));
+    lss.push_str("  <LayoutPath>\n  </LayoutPath>\n  <Metadata>\n    <Run id=\"\" />\n");
+    let _ = writeln!(lss, "    <Platform usesEmulator=\"{uses_emulator}\">PlayStation</Platform>");
+    lss.push_str("    <Region>\n    </Region>\n    <Variables>\n");
+    let _ = writeln!(lss, "      <Variable name=\"Platform\">{platform}</Variable>");
+    lss.push_str("    </Variables>\n    <CustomVariables>\n");
+    if let Some(split_type) = split_type {
+        let _ = writeln!(lss, "      <Variable name=\"GaleriansSplitType\">{}</Variable>", split_type.as_str());
+    }
+    let _ = writeln!(lss, "      <Variable name=\"GaleriansCategory\">{}</Variable>", escape_xml(run_category.as_str()));
+    lss.push_str("    </CustomVariables>\n  </Metadata>\n");
+    lss.push_str("  <Offset>00:00:00</Offset>\n  <AttemptCount>0</AttemptCount>\n  <AttemptHistory />\n  <Segments>\n");
+    for name in segment_names {
+        lss.push_str("    <Segment>\n");
+        let _ = writeln!(lss, "      <Name>{}</Name>", escape_xml(name));
+        lss.push_str("      <Icon />\n      <SplitTimes>\n        <SplitTime name=\"Personal Best\" />\n      </SplitTimes>\n");
+        lss.push_str("      <BestSegmentTime />\n      <SegmentHistory />\n    </Segment>\n");
+    }
+    lss.push_str("  </Segments>\n  <AutoSplitterSettings />\n</Run>\n");
+
+    lss
+}
+
+/// Write a LiveSplit split file for the given segments
+pub fn save_lss(path: impl AsRef<Path>, segment_names: &[String], split_type: Option<SplitType>, run_category: RunCategory) -> Result<()> {
+    fs::write(path, generate_lss(segment_names, split_type, run_category))?;
+    Ok(())
+}
@@ -1,33 +1,467 @@
-use crate::game::{Item, Map, Stage};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use anyhow::{anyhow, Result};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::backup::backup_file;
+use crate::game::{Boss, Item, KnockState, Map, NUM_ITEMS, Stage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Event {
     Room(Map, u16),
     // there are two rooms in the game (A1401 and A1310) that are mapped twice. I'm not sure if the
-    // game actually uses both mappings, but we'll check for both just to cover our bases.
-    // actually, there's also a third room, A14RH, which is mapped four times, but that room is
-    // unused, so we won't worry about it.
-    Room2((Map, u16), (Map, u16)),
-    Flag(Stage, u32),
+    // game actually uses both mappings, but we'll check for all of them just to cover our bases.
+    // there's also a third room, A14RH, which is mapped four times, but that room is unused.
+    /// Any of several mappings of the same room
+    #[serde(alias = "room2")]
+    Rooms(#[serde(deserialize_with = "deserialize_leaked")] &'static [(Map, u16)]),
+    /// A story flag is set. The last value is how many updates in a row the flag has to stay set
+    /// before it counts, for flags that flicker while a script is running.
+    #[serde(deserialize_with = "deserialize_flag")]
+    Flag(Stage, u32, u32),
+    /// A story flag that was set has been cleared
+    FlagCleared(Stage, u32),
     Item(Item),
+    /// An item the player was carrying has been used up
+    ItemUsed(Item),
+    /// The player has made at least the given amount of progress on the secret knock
+    Knock(KnockState),
+    /// The boss's HP has gone from above zero to zero
+    BossDefeated(Boss),
+    /// The FMV or scripted cutscene with the given ID has started playing
+    Cutscene(u16),
+    /// The player has gone through at least this many doors since the run started
+    DoorCount(u32),
+    /// The player has picked up at least this many different items since the run started, whether
+    /// or not they're still carrying them
+    ItemsCollected(u32),
+    /// Every one of the events is happening at once, e.g. a flag is set while the player is in a
+    /// particular room
+    All(#[serde(deserialize_with = "deserialize_leaked")] &'static [Event]),
+    /// At least one of the events is happening
+    Any(#[serde(deserialize_with = "deserialize_leaked")] &'static [Event]),
+}
+
+impl Event {
+    /// Check whether this is a room event for the given room. A composite event counts if any of
+    /// its events is for the room.
+    pub fn matches_room(&self, room: (u16, u16)) -> bool {
+        match self {
+            Self::Room(map, room_id) => (*map as u16, *room_id) == room,
+            Self::Rooms(rooms) => rooms.iter().any(|(map, room_id)| (*map as u16, *room_id) == room),
+            Self::All(events) | Self::Any(events) => events.iter().any(|event| event.matches_room(room)),
+            _ => false,
+        }
+    }
+
+    /// The stage a room event's room is in
+    pub fn room_stage(&self) -> Option<Stage> {
+        match self {
+            Self::Room(map, _) => Some(map.stage()),
+            Self::Rooms(rooms) => rooms.first().map(|(map, _)| map.stage()),
+            _ => None,
+        }
+    }
+
+    /// Check whether the event, or any of the events a composite event is made of, matches the
+    /// predicate
+    pub fn contains(&self, predicate: &impl Fn(&Event) -> bool) -> bool {
+        match self {
+            Self::All(events) | Self::Any(events) => events.iter().any(|event| event.contains(predicate)),
+            _ => predicate(self),
+        }
+    }
+
+    /// How many updates in a row the event has to be seen before we split on it
+    pub fn required_updates(&self) -> u32 {
+        match self {
+            Self::Flag(_, _, updates) => *updates,
+            Self::All(events) | Self::Any(events) => events.iter().map(Self::required_updates).max().unwrap_or(1),
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Room(map, room) => write!(f, "room {map:?} {room}"),
+            Self::Rooms(rooms) => {
+                let rooms: Vec<_> = rooms.iter().map(|(map, room)| format!("{map:?} {room}")).collect();
+                write!(f, "room {}", rooms.join(" or "))
+            }
+            Self::Flag(stage, flag, _) => write!(f, "flag {stage:?} {flag}"),
+            Self::FlagCleared(stage, flag) => write!(f, "flag {stage:?} {flag} cleared"),
+            Self::Item(item) => write!(f, "{}", item.name()),
+            Self::ItemUsed(item) => write!(f, "{} used", item.name()),
+            Self::Knock(state) => write!(f, "knock {}", state.as_str()),
+            Self::BossDefeated(boss) => write!(f, "{boss:?} defeated"),
+            Self::Cutscene(id) => write!(f, "cutscene {id}"),
+            Self::DoorCount(doors) => write!(f, "door {doors}"),
+            Self::ItemsCollected(items) => write!(f, "{items} items collected"),
+            Self::All(events) => write!(f, "all of ({})", events.iter().map(Self::to_string).collect::<Vec<_>>().join(", ")),
+            Self::Any(events) => write!(f, "any of ({})", events.iter().map(Self::to_string).collect::<Vec<_>>().join(", ")),
+        }
+    }
+}
+
+/// The part of a full-game route that covers a single stage, for individual level runs. Entering
+/// the stage starts the run and entering the next stage ends it, so neither of those room events is
+/// included.
+pub fn stage_range(route: &[Event], stage: Stage) -> Range<usize> {
+    let start = if stage == Stage::A {
+        0
+    } else {
+        route.iter().position(|event| event.room_stage() == Some(stage)).map_or(route.len(), |i| i + 1)
+    };
+    let end = match stage.next() {
+        Some(next) => route[start..].iter().position(|event| event.room_stage() == Some(next)).map_or(route.len(), |i| start + i),
+        None => route.len(),
+    };
+    start..end
+}
+
+/// Divide a full-game route into a section for each stage. The room event for entering the next
+/// stage is the last event of the previous stage's section, since that's the split that ends the
+/// stage.
+pub fn route_sections(route: &[Event]) -> Vec<(Stage, Range<usize>)> {
+    let mut sections = Vec::new();
+    let mut stage = Stage::A;
+    let mut start = 0;
+    for (i, event) in route.iter().enumerate() {
+        if let Some(next) = stage.next() && event.room_stage() == Some(next) {
+            sections.push((stage, start..i + 1));
+            stage = next;
+            start = i + 1;
+        }
+    }
+    sections.push((stage, start..route.len()));
+    sections
+}
+
+/// Names for the LiveSplit segments of a route, grouped into subsplits by stage the way LiveSplit's
+/// subsplits component expects: each segment in a stage starts with a dash except the last, which
+/// has the stage name in braces. `entries` has the route file's notes and actions, if the route came
+/// from a file.
+pub fn segment_names(route: &[Event], entries: &[RouteEntry]) -> Vec<String> {
+    let mut names = Vec::new();
+    let sections = route_sections(route);
+    let last_section = sections.len() - 1;
+    for (i, (stage, range)) in sections.into_iter().enumerate() {
+        let mut section: Vec<String> = range
+            .filter(|i| entries.get(*i).is_none_or(|entry| entry.action.has_segment()))
+            .map(|i| entries.get(i).and_then(|entry| entry.note.clone()).unwrap_or_else(|| route[i].to_string()))
+            .collect();
+        if i == last_section {
+            // the final split isn't in the route
+            section.push(String::from(if stage == Stage::D { "Dorothy" } else { "Finish" }));
+        }
+
+        let Some(last) = section.pop() else {
+            continue;
+        };
+        names.extend(section.into_iter().map(|name| format!("-{name}")));
+        names.push(format!("{{Stage {stage:?}}} {last}"));
+    }
+    names
+}
+
+/// Routes are loaded once and kept for the rest of the program, so lists inside events can just be
+/// leaked to give them the same lifetime as the built-in routes
+fn deserialize_leaked<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<&'static [T], D::Error> {
+    let items: Vec<T> = Vec::deserialize(deserializer)?;
+    Ok(Box::leak(items.into_boxed_slice()))
+}
+
+/// Flag events can leave out the number of updates, which defaults to 1. It can't be 0, since the
+/// flag has to be seen at least once before we split on it.
+fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(Stage, u32, u32), D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FlagFields {
+        Debounced(Stage, u32, u32),
+        Plain(Stage, u32),
+    }
+
+    match FlagFields::deserialize(deserializer)? {
+        FlagFields::Debounced(_, _, 0) => Err(de::Error::custom("flag must be seen for at least 1 update")),
+        FlagFields::Debounced(stage, flag, updates) => Ok((stage, flag, updates)),
+        FlagFields::Plain(stage, flag) => Ok((stage, flag, 1)),
+    }
+}
+
+/// What the autosplitter does when the player reaches a route event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteAction {
+    #[default]
+    Split,
+    /// Skip the split in LiveSplit so that the segment doesn't get a time
+    Skip,
+    PauseGameTime,
+    ResumeGameTime,
+    /// Just log the event. This is useful as a checkpoint to keep track of where the player is in
+    /// the route without needing a segment in LiveSplit.
+    Log,
+}
+
+impl RouteAction {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Split => "split",
+            Self::Skip => "skip",
+            Self::PauseGameTime => "pause_game_time",
+            Self::ResumeGameTime => "resume_game_time",
+            Self::Log => "log",
+        }
+    }
+
+    /// Whether the action moves LiveSplit on to the next segment
+    pub const fn has_segment(&self) -> bool {
+        matches!(self, Self::Split | Self::Skip)
+    }
+
+    fn is_split(&self) -> bool {
+        *self == Self::Split
+    }
+}
+
+impl TryFrom<&str> for RouteAction {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value {
+            "split" => Ok(Self::Split),
+            "skip" => Ok(Self::Skip),
+            "pause_game_time" => Ok(Self::PauseGameTime),
+            "resume_game_time" => Ok(Self::ResumeGameTime),
+            "log" => Ok(Self::Log),
+            _ => Err(anyhow!("Unknown route action: {value}")),
+        }
+    }
+}
+
+/// A route event along with a note about what it's for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteEntry {
+    pub event: Event,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "RouteAction::is_split")]
+    pub action: RouteAction,
+}
+
+fn segments(entries: &[RouteEntry]) -> usize {
+    entries.iter().filter(|entry| entry.action.has_segment()).count()
+}
+
+/// A point in a route where the player can take one of several paths
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteBranch {
+    /// Number of the first route event in the main path, counting from 1 as the route editor does
+    pub start: usize,
+    /// Number of route events in the main path. This can be 0 for a detour the player might or
+    /// might not take.
+    pub length: usize,
+    /// Paths the player might take instead of the main path. Each one has to have the same number of
+    /// segments as the main path so that the splits still line up.
+    pub alternatives: Vec<Vec<RouteEntry>>,
+}
+
+impl RouteBranch {
+    /// The path for the given choice, or None for the main path
+    fn alternative(&self, choice: usize) -> Option<&[RouteEntry]> {
+        choice.checked_sub(1).and_then(|i| self.alternatives.get(i)).map(Vec::as_slice)
+    }
+}
+
+/// A route saved to a file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Whether the route is meant for console runs
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub console: bool,
+    /// The main path through the route
+    pub events: Vec<RouteEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<RouteBranch>,
+}
+
+impl RouteFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Number of segments LiveSplit needs for the route, including the final split that isn't in the
+    /// route
+    pub fn segment_count(&self) -> usize {
+        segments(&self.events) + 1
+    }
+
+    /// Check whether any event in the route, including the alternative paths, matches the predicate
+    pub fn has_event(&self, predicate: impl Fn(&Event) -> bool) -> bool {
+        self.events
+            .iter()
+            .chain(self.branches.iter().flat_map(|branch| branch.alternatives.iter().flatten()))
+            .any(|entry| entry.event.contains(&predicate))
+    }
+
+    /// Find problems with the route's branches that would make it impossible to follow
+    pub fn branch_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut previous_end = 0;
+        for (i, branch) in self.branches.iter().enumerate() {
+            let number = i + 1;
+            let Some(start) = branch.start.checked_sub(1) else {
+                problems.push(format!("Branch {number}: events are numbered from 1"));
+                continue;
+            };
+            let end = start + branch.length;
+            if end > self.events.len() {
+                problems.push(format!("Branch {number}: the main path goes past the end of the route"));
+                continue;
+            }
+            if start < previous_end {
+                problems.push(format!("Branch {number}: overlaps the branch before it"));
+            }
+            previous_end = end;
+
+            if branch.alternatives.is_empty() {
+                problems.push(format!("Branch {number}: there are no alternative paths"));
+            }
+            let main_segments = segments(&self.events[start..end]);
+            for (j, alternative) in branch.alternatives.iter().enumerate() {
+                if alternative.is_empty() {
+                    problems.push(format!("Branch {number}: alternative {} is empty", j + 1));
+                } else if segments(alternative) != main_segments {
+                    problems.push(format!(
+                        "Branch {number}: alternative {} has {} segments, but the main path has {main_segments}",
+                        j + 1,
+                        segments(alternative),
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// The route's events with the given path taken at each branch: 0 for the main path, or the
+    /// number of the alternative. Branches without a choice take the main path.
+    fn entries_with_choices(&self, choices: &[usize]) -> Vec<RouteEntry> {
+        let mut entries = Vec::new();
+        let mut next = 0;
+        for (i, branch) in self.branches.iter().enumerate() {
+            let start = branch.start - 1;
+            entries.extend_from_slice(&self.events[next..start]);
+            match choices.get(i).and_then(|choice| branch.alternative(*choice)) {
+                Some(alternative) => entries.extend_from_slice(alternative),
+                None => entries.extend_from_slice(&self.events[start..start + branch.length]),
+            }
+            next = start + branch.length;
+        }
+        entries.extend_from_slice(&self.events[next..]);
+        entries
+    }
+
+    /// Turn the main path of the route into a list of events, plus the entries they came from, that
+    /// live as long as the built-in routes. This should only be done once per route, since they're
+    /// never freed.
+    pub fn into_route(self) -> (&'static [Event], &'static [RouteEntry]) {
+        leak_entries(self.entries_with_choices(&[]))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Err(e) = backup_file(path) {
+            log::warn!("Failed to back up {}: {e}", path.display());
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+fn leak_entries(entries: Vec<RouteEntry>) -> (&'static [Event], &'static [RouteEntry]) {
+    let events: Vec<_> = entries.iter().map(|entry| entry.event).collect();
+    (Box::leak(events.into_boxed_slice()), Box::leak(entries.into_boxed_slice()))
+}
+
+/// A route file in use by the autosplitter, which keeps each combination of branches the player has
+/// taken so that it only has to be built once
+#[derive(Debug)]
+pub struct LoadedRoute {
+    file: RouteFile,
+    routes: HashMap<Vec<usize>, (&'static [Event], &'static [RouteEntry])>,
+}
+
+impl LoadedRoute {
+    pub fn new(file: RouteFile) -> Self {
+        Self { file, routes: HashMap::new() }
+    }
+
+    pub fn branches(&self) -> &[RouteBranch] {
+        &self.file.branches
+    }
+
+    /// The route with the given path taken at each branch
+    pub fn route(&mut self, choices: &[usize]) -> (&'static [Event], &'static [RouteEntry]) {
+        *self.routes.entry(choices.to_vec()).or_insert_with(|| leak_entries(self.file.entries_with_choices(choices)))
+    }
+
+    /// Where each branch starts in the route with the given path taken at each branch
+    pub fn branch_starts(&self, choices: &[usize]) -> Vec<usize> {
+        let mut shift = 0isize;
+        self.file.branches.iter().enumerate().map(|(i, branch)| {
+            let start = (branch.start - 1).saturating_add_signed(shift);
+            let length = choices.get(i).and_then(|choice| branch.alternative(*choice)).map_or(branch.length, <[_]>::len);
+            shift += length as isize - branch.length as isize;
+            start
+        }).collect()
+    }
 }
 
 macro_rules! room {
     ($map:ident $room:expr) => {Event::Room(Map::$map, $room)};
 }
 
-macro_rules! room2 {
-    ($map:ident $room:expr, $map2:ident $room2:expr) => {Event::Room2((Map::$map, $room), (Map::$map2, $room2))};
+macro_rules! rooms {
+    ($($map:ident $room:expr),+) => {Event::Rooms(&[$((Map::$map, $room)),+])};
 }
 
 macro_rules! flag {
-    ($stage:ident $flag:expr) => {Event::Flag(Stage::$stage, $flag)};
+    ($stage:ident $flag:expr) => {Event::Flag(Stage::$stage, $flag, 1)};
+    ($stage:ident $flag:expr, debounce $updates:expr) => {Event::Flag(Stage::$stage, $flag, $updates)};
 }
 
 macro_rules! item {
     ($name:ident) => {Event::Item(Item::$name)};
 }
 
+macro_rules! knock {
+    ($state:ident) => {Event::Knock(KnockState::$state)};
+}
+
+const fn items_collected_splits<const N: usize>() -> [Event; N] {
+    let mut splits = [Event::ItemsCollected(0); N];
+    let mut i = 0;
+    while i < N {
+        splits[i] = Event::ItemsCollected(i as u32 + 1);
+        i += 1;
+    }
+    splits
+}
+
+/// 100% splits on every new key item or file picked up, in whatever order the runner gets them
+pub const HUNDRED_PERCENT_SPLITS: [Event; NUM_ITEMS as usize] = items_collected_splits();
+
 pub const KEY_EVENT_SPLITS: [Event; 45] = [
     // Stage A
     item!(SecurityCard),
@@ -64,8 +498,45 @@ pub const KEY_EVENT_SPLITS: [Event; 45] = [
     // Event::Room(Map::YourHouse1F, 10), // B0111 (Birdman)
     room!(Hotel1F 0), // C0101; end of Stage B
     // Stage C
-    flag!(C 5), // learned secret knock
-    flag!(C 17), // successfully performed secret knock
+    knock!(Learned),
+    knock!(Succeeded),
+    flag!(C 10), // Crovic
+    flag!(C 144), // Priest
+    flag!(C 143), // bomb guy
+    flag!(C 54), // defeat enemy in 3F hall
+    flag!(C 145), // Suzan
+    flag!(C 142), // gun guy
+    flag!(C 47), // defeat enemies in room 305
+    flag!(C 35), // defeat enemies in room 301
+    flag!(C 95), // take phone call in room 205
+    flag!(C 23), // defeat enemies in 2F hall
+    flag!(C 11), // defeat enemy in room 202
+    room!(Hotel3F 4), // C0305 (Rainheart)
+    room!(Hotel3F 6), // C0307 (post-Rainheart)
+    room!(Hotel1F 5), // C1101 (Rita)
+    room!(MushroomTower 0), // D0001; end of Stage C
+    // Stage D
+    room!(MushroomTower 4), // D1001 (Cain)
+    room!(MushroomTower 7), // D1004 (Dorothy)
+];
+
+/// The first room the player can only reach by beating each boss, for split types that split on
+/// bosses in whatever order they happen. Dorothy isn't here, since beating her ends the run.
+pub const BOSS_DEFEAT_ROOMS: [(Boss, Event); 4] = [
+    (Boss::Birdman, room!(Hotel1F 0)), // C0101; end of Stage B
+    (Boss::Rainheart, room!(Hotel3F 6)), // C0307 (post-Rainheart)
+    (Boss::Rita, room!(MushroomTower 0)), // D0001; end of Stage C
+    (Boss::Cain, room!(MushroomTower 7)), // D1004 (Dorothy)
+];
+
+/// Key events for replay mode. Carried-over items mean the item pickups in stages A and B don't
+/// happen the same way as in a new game, so those stages only split at the end.
+pub const REPLAY_KEY_EVENT_SPLITS: [Event; 21] = [
+    room!(YourHouse1F 11), // B0112; end of Stage A
+    room!(Hotel1F 0), // C0101; end of Stage B
+    // Stage C
+    knock!(Learned),
+    knock!(Succeeded),
     flag!(C 10), // Crovic
     flag!(C 144), // Priest
     flag!(C 143), // bomb guy
@@ -111,7 +582,7 @@ pub const DOOR_SPLITS: [Event; 176] = [
     room!(Hospital14F 10), // A14RA
     room!(Hospital15F 14), // A15RC
     room!(Hospital15F 13), // A15RB (use special PPEC office key)
-    room2!(Hospital15F 7, Hospital14F 5), // A1401 (reformat security card)
+    rooms!(Hospital15F 7, Hospital14F 5), // A1401 (reformat security card)
     room!(Hospital15F 13), // A15RB
     room!(Hospital15F 14), // A15RC
     room!(Hospital14F 10), // A14RA (use reformatted security card)
@@ -120,23 +591,23 @@ pub const DOOR_SPLITS: [Event; 176] = [
     room!(Hospital13F 15), // A13RA
     room!(Hospital13F 0), // A1301 (control room key, photo of parents)
     room!(Hospital13F 15), // A13RA
-    room2!(Hospital13F 9, Hospital13F 10), // A1310
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310
     room!(Hospital13F 16), // A13RB
     room!(Hospital13F 1), // A1302 (test lab key)
     room!(Hospital13F 16), // A13RB
-    room2!(Hospital13F 9, Hospital13F 10), // A1310
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310
     room!(Hospital14F 13), // A14RG
     room!(Hospital14F 2), // A1403 (research lab key)
     room!(Hospital14F 13), // A14RG
-    room2!(Hospital13F 9, Hospital13F 10), // A1310 (use test lab key)
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310 (use test lab key)
     room!(Hospital13F 5), // A1306 (two-headed snake)
-    room2!(Hospital13F 9, Hospital13F 10), // A1310 (use control room key)
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310 (use control room key)
     room!(Hospital13F 7), // A1308 (unlock Clinic Chief's office, use research lab key)
     room!(Hospital13F 8), // A1309 (two-headed monkey)
     room!(Hospital13F 7), // A1308 (unlock armory)
-    room2!(Hospital13F 9, Hospital13F 10), // A1310
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310
     room!(Hospital13F 11), // A1312 (two-headed wolf)
-    room2!(Hospital13F 9, Hospital13F 10), // A1310
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310
     room!(Hospital13F 15), // A13RA
     room!(Hospital13F 2), // A1303 (two-headed eagle)
     room!(Hospital13F 3), // A1304
@@ -300,7 +771,7 @@ pub const CONSOLE_DOOR_SPLITS: [Event; 171] = [
     room!(Hospital14F 10), // A14RA
     room!(Hospital15F 14), // A15RC
     room!(Hospital15F 13), // A15RB (use special PPEC office key)
-    room2!(Hospital15F 7, Hospital14F 5), // A1401 (reformat security card)
+    rooms!(Hospital15F 7, Hospital14F 5), // A1401 (reformat security card)
     room!(Hospital15F 13), // A15RB
     room!(Hospital15F 14), // A15RC
     room!(Hospital14F 10), // A14RA (use reformatted security card)
@@ -309,23 +780,23 @@ pub const CONSOLE_DOOR_SPLITS: [Event; 171] = [
     room!(Hospital13F 15), // A13RA
     room!(Hospital13F 0), // A1301 (control room key, photo of parents)
     room!(Hospital13F 15), // A13RA
-    room2!(Hospital13F 9, Hospital13F 10), // A1310
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310
     room!(Hospital13F 16), // A13RB
     room!(Hospital13F 1), // A1302 (test lab key)
     room!(Hospital13F 16), // A13RB
-    room2!(Hospital13F 9, Hospital13F 10), // A1310
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310
     room!(Hospital14F 13), // A14RG
     room!(Hospital14F 2), // A1403 (research lab key)
     room!(Hospital14F 13), // A14RG
-    room2!(Hospital13F 9, Hospital13F 10), // A1310 (use test lab key)
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310 (use test lab key)
     room!(Hospital13F 5), // A1306 (two-headed snake)
-    room2!(Hospital13F 9, Hospital13F 10), // A1310 (use control room key)
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310 (use control room key)
     room!(Hospital13F 7), // A1308 (unlock Clinic Chief's office, use research lab key)
     room!(Hospital13F 8), // A1309 (two-headed monkey)
     room!(Hospital13F 7), // A1308 (unlock armory)
-    room2!(Hospital13F 9, Hospital13F 10), // A1310
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310
     room!(Hospital13F 11), // A1312 (two-headed wolf)
-    room2!(Hospital13F 9, Hospital13F 10), // A1310
+    rooms!(Hospital13F 9, Hospital13F 10), // A1310
     room!(Hospital13F 15), // A13RA
     room!(Hospital13F 2), // A1303 (two-headed eagle)
     room!(Hospital13F 3), // A1304
@@ -453,4 +924,126 @@ pub const CONSOLE_DOOR_SPLITS: [Event; 171] = [
     // a split
     room!(MushroomTower 4), // D1001 (Cain)
     room!(MushroomTower 7), // D1004 (Dorothy)
-];
\ No newline at end of file
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flag_updates_default() {
+        let event: Event = serde_json::from_str(r#"{"flag": [2, 40]}"#).unwrap();
+        assert_eq!(event, Event::Flag(Stage::C, 40, 1));
+        assert_eq!(event.required_updates(), 1);
+    }
+
+    #[test]
+    fn test_flag_updates() {
+        let event: Event = serde_json::from_str(r#"{"flag": [2, 40, 5]}"#).unwrap();
+        assert_eq!(event, Event::Flag(Stage::C, 40, 5));
+        assert_eq!(event.required_updates(), 5);
+    }
+
+    #[test]
+    fn test_flag_zero_updates() {
+        assert!(serde_json::from_str::<Event>(r#"{"flag": [2, 40, 0]}"#).is_err());
+    }
+
+    #[test]
+    fn test_room2_alias() {
+        let event: Event = serde_json::from_str(r#"{"room2": [[1, 5], [2, 7]]}"#).unwrap();
+        assert_eq!(event, Event::Rooms(&[(Map::Hospital14F, 5), (Map::Hospital13F, 7)]));
+        assert!(event.matches_room((2, 7)));
+        assert!(!event.matches_room((2, 5)));
+    }
+
+    #[test]
+    fn test_all_any() {
+        let event: Event = serde_json::from_str(r#"{"all": [{"flag": [0, 12, 3]}, {"any": [{"room": [1, 5]}, {"item": 1}]}]}"#).unwrap();
+        assert_eq!(
+            event,
+            Event::All(&[
+                Event::Flag(Stage::A, 12, 3),
+                Event::Any(&[Event::Room(Map::Hospital14F, 5), Event::Item(Item::SecurityCard)]),
+            ])
+        );
+        assert_eq!(event.required_updates(), 3);
+        assert!(event.matches_room((1, 5)));
+        assert!(event.contains(&|event| matches!(event, Event::Item(Item::SecurityCard))));
+        assert!(!event.contains(&|event| matches!(event, Event::Any(_))));
+    }
+
+    #[test]
+    fn test_stage_range() {
+        let route = [
+            Event::Room(Map::Hospital14F, 0),
+            Event::Flag(Stage::A, 12, 1),
+            Event::Room(Map::YourHouse1F, 0),
+            Event::Item(Item::SecurityCard),
+            Event::Rooms(&[(Map::Hotel1F, 0), (Map::Hotel1F, 1)]),
+            Event::Flag(Stage::C, 40, 1),
+            Event::Room(Map::MushroomTower, 0),
+            Event::BossDefeated(Boss::Cain),
+        ];
+        assert_eq!(stage_range(&route, Stage::A), 0..2);
+        assert_eq!(stage_range(&route, Stage::B), 3..4);
+        assert_eq!(stage_range(&route, Stage::C), 5..6);
+        assert_eq!(stage_range(&route, Stage::D), 7..8);
+        assert_eq!(stage_range(&route[..4], Stage::C), 4..4);
+    }
+
+    fn entry(event: Event, action: RouteAction) -> RouteEntry {
+        RouteEntry { event, note: None, action }
+    }
+
+    fn branching_route(alternative: Vec<RouteEntry>) -> RouteFile {
+        RouteFile {
+            events: vec![
+                entry(Event::Room(Map::Hospital15F, 1), RouteAction::Split),
+                entry(Event::Room(Map::Hospital15F, 2), RouteAction::Split),
+                entry(Event::Room(Map::Hospital15F, 3), RouteAction::Log),
+                entry(Event::Room(Map::Hospital15F, 4), RouteAction::Split),
+            ],
+            branches: vec![RouteBranch { start: 2, length: 2, alternatives: vec![alternative] }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_branches() {
+        let route = branching_route(vec![entry(Event::Room(Map::Hospital15F, 5), RouteAction::Split)]);
+        assert!(route.branch_problems().is_empty());
+
+        let main: Vec<_> = route.entries_with_choices(&[]).into_iter().map(|entry| entry.event).collect();
+        assert_eq!(main, route.events.iter().map(|entry| entry.event).collect::<Vec<_>>());
+        let alternative: Vec<_> = route.entries_with_choices(&[1]).into_iter().map(|entry| entry.event).collect();
+        assert_eq!(
+            alternative,
+            [Event::Room(Map::Hospital15F, 1), Event::Room(Map::Hospital15F, 5), Event::Room(Map::Hospital15F, 4)]
+        );
+    }
+
+    #[test]
+    fn test_branch_problems() {
+        let route = branching_route(vec![
+            entry(Event::Room(Map::Hospital15F, 5), RouteAction::Split),
+            entry(Event::Room(Map::Hospital15F, 6), RouteAction::Skip),
+        ]);
+        assert_eq!(route.branch_problems(), ["Branch 1: alternative 1 has 2 segments, but the main path has 1"]);
+
+        let mut route = branching_route(Vec::new());
+        route.branches.push(RouteBranch { start: 3, length: 1, alternatives: Vec::new() });
+        route.branches.push(RouteBranch { start: 4, length: 5, alternatives: Vec::new() });
+        route.branches.push(RouteBranch { start: 0, length: 0, alternatives: Vec::new() });
+        assert_eq!(
+            route.branch_problems(),
+            [
+                "Branch 1: alternative 1 is empty",
+                "Branch 2: overlaps the branch before it",
+                "Branch 2: there are no alternative paths",
+                "Branch 3: the main path goes past the end of the route",
+                "Branch 4: events are numbered from 1",
+            ]
+        );
+    }
+}
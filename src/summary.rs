@@ -0,0 +1,244 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::game::Stage;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageTime {
+    pub stage: Stage,
+    pub seconds: f64,
+    /// Number of splits made during the stage, including the one for reaching the next stage
+    pub splits: usize,
+}
+
+/// How long it took from first seeing each split's event in the game to telling the timer to split
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyStats {
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencyStats {
+    fn from_latencies(latencies: &[Duration]) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+
+        let mut millis: Vec<f64> = latencies.iter().map(|latency| latency.as_secs_f64() * 1000.0).collect();
+        millis.sort_by(f64::total_cmp);
+        let p95_index = (millis.len() * 95).div_ceil(100).saturating_sub(1);
+
+        Some(Self {
+            min_ms: millis[0],
+            mean_ms: millis.iter().sum::<f64>() / millis.len() as f64,
+            p95_ms: millis[p95_index],
+            max_ms: millis[millis.len() - 1],
+        })
+    }
+}
+
+/// Report on a finished run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunSummary {
+    /// The final time according to the timer, if it could be retrieved
+    pub final_time: Option<String>,
+    /// Wall-clock time from the start of the run to the end, as measured by the autosplitter
+    pub real_time_seconds: f64,
+    /// The in-game time at the end of the run, if the game can tell
+    pub game_time_seconds: Option<f64>,
+    /// Time spent loading during the run, if the game can tell when it's loading
+    pub load_time_seconds: Option<f64>,
+    pub stage_times: Vec<StageTime>,
+    pub splits: usize,
+    /// Number of times the player returned to the main menu (usually from dying) and the run
+    /// continued from the start of the stage
+    pub stage_restarts: u32,
+    /// Number of runs reset since the last completed run, or since the autosplitter started
+    pub resets: u32,
+    pub latency: Option<LatencyStats>,
+}
+
+impl RunSummary {
+    pub fn log(&self) {
+        log::info!("Run summary:");
+        match &self.final_time {
+            Some(final_time) => log::info!("  Final time: {final_time} (real time {:.3}s)", self.real_time_seconds),
+            None => log::info!("  Real time: {:.3}s", self.real_time_seconds),
+        }
+        if let Some(game_time) = self.game_time_seconds {
+            log::info!("  Game time: {game_time:.3}s");
+        }
+        if let Some(load_time) = self.load_time_seconds {
+            log::info!("  Load time: {load_time:.3}s");
+        }
+        for stage_time in &self.stage_times {
+            log::info!("  Stage {:?}: {:.3}s ({} splits)", stage_time.stage, stage_time.seconds, stage_time.splits);
+        }
+        log::info!("  Splits: {}", self.splits);
+        log::info!("  Stage restarts: {}", self.stage_restarts);
+        log::info!("  Resets since the last completed run: {}", self.resets);
+        if let Some(latency) = &self.latency {
+            log::info!(
+                "  Split latency: min {:.1} ms, mean {:.1} ms, 95% {:.1} ms, max {:.1} ms",
+                latency.min_ms,
+                latency.mean_ms,
+                latency.p95_ms,
+                latency.max_ms,
+            );
+        }
+    }
+
+    /// Write the summary to a JSON file in the given directory, named by the time the run ended.
+    /// An existing summary is never overwritten. Returns the path of the file.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let json = serde_json::to_string_pretty(self)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let mut attempt = 0;
+        loop {
+            let name = if attempt == 0 { format!("run_{timestamp}.json") } else { format!("run_{timestamp}_{attempt}.json") };
+            let path = dir.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(json.as_bytes())?;
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Collects statistics about the current run as it happens
+#[derive(Debug, Clone, Default)]
+pub struct RunStats {
+    start: Option<Instant>,
+    /// When the player entered each stage, in order
+    stage_starts: Vec<(Stage, Instant)>,
+    /// Number of splits in each stage, in the same order as the stage starts
+    stage_splits: Vec<usize>,
+    stage_restarts: u32,
+    /// Number of runs reset since the last one that was completed. Unlike everything else, this
+    /// carries over from one run to the next.
+    resets: u32,
+    split_latencies: Vec<Duration>,
+}
+
+impl RunStats {
+    pub fn start(&mut self) {
+        *self = Self {
+            start: Some(Instant::now()),
+            resets: self.resets,
+            ..Self::default()
+        };
+    }
+
+    pub const fn reset(&mut self) {
+        self.resets += 1;
+    }
+
+    pub fn enter_stage(&mut self, stage: Stage) {
+        // after a stage restart, the time spent on the failed attempt still counts towards the stage
+        if self.stage_starts.iter().any(|(entered, _)| *entered == stage) {
+            return;
+        }
+
+        // the intro counts as part of the first stage
+        let entered = match self.start {
+            Some(start) if self.stage_starts.is_empty() => start,
+            _ => Instant::now(),
+        };
+        self.stage_starts.push((stage, entered));
+        self.stage_splits.push(0);
+    }
+
+    pub const fn restart_stage(&mut self) {
+        self.stage_restarts += 1;
+    }
+
+    pub fn split(&mut self, latency: Duration) {
+        self.split_latencies.push(latency);
+        if let Some(splits) = self.stage_splits.last_mut() {
+            *splits += 1;
+        }
+    }
+
+    pub fn unsplit(&mut self) {
+        self.split_latencies.pop();
+        if let Some(splits) = self.stage_splits.last_mut() {
+            *splits = splits.saturating_sub(1);
+        }
+    }
+
+    pub fn finish(&mut self, final_time: Option<String>, load_time: Option<Duration>, game_time: Option<Duration>) -> RunSummary {
+        let end = Instant::now();
+        let start = self.start.unwrap_or(end);
+
+        let stage_times = self.stage_starts
+            .iter()
+            .enumerate()
+            .map(|(i, (stage, entered))| {
+                let left = self.stage_starts.get(i + 1).map_or(end, |(_, next_entered)| *next_entered);
+                StageTime {
+                    stage: *stage,
+                    seconds: left.duration_since(*entered).as_secs_f64(),
+                    splits: self.stage_splits.get(i).copied().unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        RunSummary {
+            final_time,
+            real_time_seconds: end.duration_since(start).as_secs_f64(),
+            game_time_seconds: game_time.map(|game_time| game_time.as_secs_f64()),
+            load_time_seconds: load_time.map(|load_time| load_time.as_secs_f64()),
+            stage_times,
+            splits: self.split_latencies.len(),
+            stage_restarts: self.stage_restarts,
+            resets: std::mem::take(&mut self.resets),
+            latency: LatencyStats::from_latencies(&self.split_latencies),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_stats_empty() {
+        assert_eq!(LatencyStats::from_latencies(&[]), None);
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        let latencies: Vec<_> = (1..=20).rev().map(|ms| Duration::from_millis(ms * 10)).collect();
+        let stats = LatencyStats::from_latencies(&latencies).unwrap();
+        assert_eq!(stats, LatencyStats { min_ms: 10.0, mean_ms: 105.0, p95_ms: 190.0, max_ms: 200.0 });
+
+        let stats = LatencyStats::from_latencies(&[Duration::from_millis(16)]).unwrap();
+        assert_eq!(stats, LatencyStats { min_ms: 16.0, mean_ms: 16.0, p95_ms: 16.0, max_ms: 16.0 });
+    }
+
+    #[test]
+    fn test_save_doesnt_overwrite() {
+        let dir = std::env::temp_dir().join(format!("galerians-summary-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let summary = RunStats::default().finish(None, None, None);
+
+        let first = summary.save(&dir).unwrap();
+        let second = summary.save(&dir).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
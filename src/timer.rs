@@ -0,0 +1,142 @@
+use std::fmt::Debug;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+
+use crate::lss::{LiveSplit, LiveSplitEndpoint, RetryPolicy};
+
+mod background;
+use background::BackgroundTimer;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimerPhase {
+    NotRunning,
+    Running,
+    Ended,
+    Paused,
+}
+
+impl TimerPhase {
+    pub fn try_from_raw(s: &[u8]) -> Option<TimerPhase> {
+        match s {
+            b"NotRunning" => Some(TimerPhase::NotRunning),
+            b"Running" => Some(TimerPhase::Running),
+            b"Ended" => Some(TimerPhase::Ended),
+            b"Paused" => Some(TimerPhase::Paused),
+            _ => None,
+        }
+    }
+}
+
+/// Format a time the way LiveSplit's server expects it
+pub fn format_time(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!("{}:{:02}:{:02}.{:03}", millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000)
+}
+
+/// A timer that the autosplitter can control
+pub trait TimerBackend: Debug {
+    fn is_connected(&self) -> bool;
+
+    fn try_reconnect(&mut self) -> Result<()>;
+
+    /// Check that the timer is still there. If it doesn't answer right away, the connection is
+    /// considered lost.
+    fn ping(&mut self) -> Result<()>;
+
+    fn split(&mut self) -> Result<()>;
+
+    fn reset(&mut self) -> Result<()>;
+
+    fn unsplit(&mut self) -> Result<()>;
+
+    fn skip_split(&mut self) -> Result<()>;
+
+    fn get_split_index(&mut self) -> Result<i64>;
+
+    /// Get the total number of segments in the loaded splits, if the timer supports it
+    fn get_segment_count(&mut self) -> Result<Option<i64>>;
+
+    fn get_timer_phase(&mut self) -> Result<TimerPhase>;
+
+    /// Set the timer's game time, for games whose in-game time we can read
+    fn set_game_time(&mut self, time: Duration) -> Result<()>;
+
+    /// Start tracking game time separately from real time, for timers that need to be told
+    fn init_game_time(&mut self) -> Result<()>;
+
+    /// Stop the timer's game time while real time keeps going
+    fn pause_game_time(&mut self) -> Result<()>;
+
+    fn resume_game_time(&mut self) -> Result<()>;
+
+    /// Get the timer's current time, formatted the way the timer displays it
+    fn get_current_time(&mut self) -> Result<String>;
+
+    /// The final time of a finished run, or None if the timer can't tell us
+    fn get_final_time(&mut self) -> Result<Option<String>>;
+
+    fn get_attempt_count(&mut self) -> Result<i64>;
+
+    fn get_completed_count(&mut self) -> Result<i64>;
+
+    fn get_current_split_name(&mut self) -> Result<String>;
+
+    /// Get the names of all the segments in the loaded splits, if the timer supports it
+    fn get_split_names(&mut self) -> Result<Option<Vec<String>>>;
+
+    fn get_comparison_split_time(&mut self) -> Result<Option<String>>;
+
+    fn get_custom_variable_value(&mut self, variable_name: &str) -> Result<Option<String>>;
+
+    /// Set one of the run's custom variables, e.g. so that it can be shown in the layout
+    fn set_custom_variable(&mut self, variable_name: &str, value: &str) -> Result<()>;
+
+    /// Set the name of the game the splits are for
+    fn set_game_name(&mut self, game_name: &str) -> Result<()>;
+
+    /// Set the name of the category the splits are for
+    fn set_category_name(&mut self, category_name: &str) -> Result<()>;
+}
+
+// TODO: a backend that runs an embedded livesplit-core timer, so the autosplitter can be used without
+//  LiveSplit at all, hasn't been written yet. it needs the livesplit-core crate and some way of
+//  showing the timer to the runner.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum TimerBackendType {
+    /// LiveSplit Server over TCP
+    Tcp,
+    /// LiveSplit's named pipe (Windows only)
+    Pipe,
+    /// LiveSplit Server over WebSocket
+    #[value(alias = "ws")]
+    WebSocket,
+}
+
+impl TimerBackendType {
+    /// Open a connection to the timer that talks to it on a background thread, so that waiting on
+    /// the timer doesn't hold up the caller. The host and port are ignored for backends that don't
+    /// use them.
+    pub fn open(&self, host: &str, port: u16, retry_policy: RetryPolicy) -> Result<Box<dyn TimerBackend>> {
+        Ok(Box::new(BackgroundTimer::open(*self, String::from(host), port, retry_policy)?))
+    }
+
+    /// Open a connection to the timer that's used directly from the calling thread
+    pub fn open_direct(&self, host: &str, port: u16, retry_policy: RetryPolicy) -> Result<Box<dyn TimerBackend>> {
+        let endpoint = match self {
+            Self::Pipe => LiveSplitEndpoint::Pipe,
+            Self::Tcp | Self::WebSocket => {
+                let addr = (host, port).to_socket_addrs()?.next().ok_or_else(|| anyhow!("Could not resolve LiveSplit host {host}"))?;
+                if *self == Self::Tcp {
+                    LiveSplitEndpoint::Tcp(addr)
+                } else {
+                    LiveSplitEndpoint::WebSocket(addr)
+                }
+            }
+        };
+
+        Ok(Box::new(LiveSplit::open(endpoint)?.with_retry_policy(retry_policy).detect_protocol()?))
+    }
+}
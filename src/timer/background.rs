@@ -0,0 +1,278 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::{TimerBackend, TimerBackendType, TimerPhase};
+use crate::lss::RetryPolicy;
+
+/// How often the timer thread checks LiveSplit's split index and timer phase when it has nothing
+/// else to do
+const POLL_INTERVAL: Duration = Duration::from_millis(15);
+/// How long to wait for the timer thread before deciding it's stuck. This is longer than it takes
+/// the connection to give up on an unresponsive server.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Command = Box<dyn FnOnce(&mut dyn TimerBackend) -> Result<()> + Send>;
+type Query = Box<dyn FnOnce(&mut dyn TimerBackend) + Send>;
+
+enum Request {
+    /// Something for the timer to do that doesn't have an answer, so there's no need to wait for it
+    Command(Command),
+    /// A question for the timer. The query sends its own answer back.
+    Query(Query),
+}
+
+/// What the timer thread last saw of LiveSplit's state
+#[derive(Debug, Clone, Copy)]
+struct TimerState {
+    is_connected: bool,
+    /// Whether the split index and timer phase could be read the last time we tried
+    is_valid: bool,
+    split_index: i64,
+    phase: TimerPhase,
+    /// Number of commands that had been carried out when the state was read
+    commands_done: u64,
+}
+
+type SharedState = Arc<(Mutex<TimerState>, Condvar)>;
+
+fn poll(timer: &mut dyn TimerBackend, commands_done: u64, state: &SharedState) {
+    let polled = if timer.is_connected() {
+        timer.get_split_index().and_then(|split_index| Ok((split_index, timer.get_timer_phase()?)))
+    } else {
+        Err(anyhow!("LiveSplit is not connected"))
+    };
+
+    let (lock, changed) = &**state;
+    if let Ok(mut state) = lock.lock() {
+        state.is_connected = timer.is_connected();
+        state.is_valid = polled.is_ok();
+        if let Ok((split_index, phase)) = polled {
+            state.split_index = split_index;
+            state.phase = phase;
+        }
+        state.commands_done = commands_done;
+        changed.notify_all();
+    }
+}
+
+fn run(mut timer: Box<dyn TimerBackend>, requests: Receiver<Request>, state: SharedState) {
+    let mut commands_done = 0;
+    loop {
+        match requests.recv_timeout(POLL_INTERVAL) {
+            Ok(Request::Command(command)) => {
+                if let Err(e) = command(timer.as_mut()) {
+                    log::debug!("LiveSplit command failed: {e}");
+                }
+                commands_done += 1;
+            }
+            Ok(Request::Query(query)) => query(timer.as_mut()),
+            Err(RecvTimeoutError::Timeout) => (),
+            // the thread exits once the timer is dropped and the channel closes
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        poll(timer.as_mut(), commands_done, &state);
+    }
+}
+
+/// A timer that talks to LiveSplit on its own thread, so that a slow server doesn't hold up
+/// watching the game. Commands are sent off without waiting for them to finish. The split index and
+/// timer phase are checked in the background and read from the last check, after any commands we
+/// sent have been carried out. Everything else waits for LiveSplit to answer.
+#[derive(Debug)]
+pub struct BackgroundTimer {
+    requests: Sender<Request>,
+    state: SharedState,
+    commands_sent: u64,
+}
+
+impl BackgroundTimer {
+    pub fn open(timer_backend: TimerBackendType, host: String, port: u16, retry_policy: RetryPolicy) -> Result<Self> {
+        let (requests, receiver) = mpsc::channel();
+        let (opened, open_result) = mpsc::channel();
+        let state: SharedState = Arc::new((
+            Mutex::new(TimerState {
+                is_connected: false,
+                is_valid: false,
+                split_index: -1,
+                phase: TimerPhase::NotRunning,
+                commands_done: 0,
+            }),
+            Condvar::new(),
+        ));
+
+        let thread_state = Arc::clone(&state);
+        thread::spawn(move || {
+            // the connection is made on the thread because it can't be sent between threads
+            match timer_backend.open_direct(&host, port, retry_policy) {
+                Ok(mut timer) => {
+                    poll(timer.as_mut(), 0, &thread_state);
+                    let _ = opened.send(Ok(()));
+                    run(timer, receiver, thread_state);
+                }
+                Err(e) => {
+                    let _ = opened.send(Err(e));
+                }
+            }
+        });
+
+        open_result.recv()??;
+        Ok(Self { requests, state, commands_sent: 0 })
+    }
+
+    fn command(&mut self, command: impl FnOnce(&mut dyn TimerBackend) -> Result<()> + Send + 'static) -> Result<()> {
+        if !self.is_connected() {
+            bail!("LiveSplit connection lost");
+        }
+
+        self.requests.send(Request::Command(Box::new(command))).map_err(|_| anyhow!("LiveSplit thread is gone"))?;
+        self.commands_sent += 1;
+        Ok(())
+    }
+
+    fn query<T: Send + 'static>(&mut self, query: impl FnOnce(&mut dyn TimerBackend) -> Result<T> + Send + 'static) -> Result<T> {
+        let (answer, answer_result) = mpsc::channel();
+        let query = move |timer: &mut dyn TimerBackend| {
+            let result = query(timer);
+            let _ = answer.send((result, timer.is_connected()));
+        };
+        self.requests.send(Request::Query(Box::new(query))).map_err(|_| anyhow!("LiveSplit thread is gone"))?;
+
+        let (result, is_connected) = answer_result.recv_timeout(RESPONSE_TIMEOUT).map_err(|_| anyhow!("Timed out waiting for LiveSplit"))?;
+        // the caller checks whether the connection was lost as soon as it sees an error, so this
+        // can't wait for the next poll
+        if let Ok(mut state) = self.state.0.lock() {
+            state.is_connected = is_connected;
+        }
+        result
+    }
+
+    /// The state of the timer once every command we've sent has been carried out
+    fn current_state(&self) -> Result<TimerState> {
+        let (lock, changed) = &*self.state;
+        let state = lock.lock().map_err(|_| anyhow!("LiveSplit thread panicked"))?;
+        let (state, timeout) = changed
+            .wait_timeout_while(state, RESPONSE_TIMEOUT, |state| state.commands_done < self.commands_sent)
+            .map_err(|_| anyhow!("LiveSplit thread panicked"))?;
+        if timeout.timed_out() {
+            bail!("Timed out waiting for LiveSplit");
+        }
+        if !state.is_connected {
+            bail!("LiveSplit connection lost");
+        }
+        if !state.is_valid {
+            bail!("Failed to read LiveSplit's state");
+        }
+        Ok(*state)
+    }
+}
+
+impl TimerBackend for BackgroundTimer {
+    fn is_connected(&self) -> bool {
+        self.state.0.lock().is_ok_and(|state| state.is_connected)
+    }
+
+    fn try_reconnect(&mut self) -> Result<()> {
+        self.query(|timer| timer.try_reconnect())
+    }
+
+    fn ping(&mut self) -> Result<()> {
+        self.query(|timer| timer.ping())
+    }
+
+    fn split(&mut self) -> Result<()> {
+        self.command(|timer| timer.split())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.command(|timer| timer.reset())
+    }
+
+    fn unsplit(&mut self) -> Result<()> {
+        self.command(|timer| timer.unsplit())
+    }
+
+    fn skip_split(&mut self) -> Result<()> {
+        self.command(|timer| timer.skip_split())
+    }
+
+    fn get_split_index(&mut self) -> Result<i64> {
+        Ok(self.current_state()?.split_index)
+    }
+
+    fn get_segment_count(&mut self) -> Result<Option<i64>> {
+        self.query(|timer| timer.get_segment_count())
+    }
+
+    fn get_timer_phase(&mut self) -> Result<TimerPhase> {
+        Ok(self.current_state()?.phase)
+    }
+
+    fn set_game_time(&mut self, time: Duration) -> Result<()> {
+        self.command(move |timer| timer.set_game_time(time))
+    }
+
+    fn init_game_time(&mut self) -> Result<()> {
+        self.command(|timer| timer.init_game_time())
+    }
+
+    fn pause_game_time(&mut self) -> Result<()> {
+        self.command(|timer| timer.pause_game_time())
+    }
+
+    fn resume_game_time(&mut self) -> Result<()> {
+        self.command(|timer| timer.resume_game_time())
+    }
+
+    fn get_current_time(&mut self) -> Result<String> {
+        self.query(|timer| timer.get_current_time())
+    }
+
+    fn get_final_time(&mut self) -> Result<Option<String>> {
+        self.query(|timer| timer.get_final_time())
+    }
+
+    fn get_attempt_count(&mut self) -> Result<i64> {
+        self.query(|timer| timer.get_attempt_count())
+    }
+
+    fn get_completed_count(&mut self) -> Result<i64> {
+        self.query(|timer| timer.get_completed_count())
+    }
+
+    fn get_current_split_name(&mut self) -> Result<String> {
+        self.query(|timer| timer.get_current_split_name())
+    }
+
+    fn get_split_names(&mut self) -> Result<Option<Vec<String>>> {
+        self.query(|timer| timer.get_split_names())
+    }
+
+    fn get_comparison_split_time(&mut self) -> Result<Option<String>> {
+        self.query(|timer| timer.get_comparison_split_time())
+    }
+
+    fn get_custom_variable_value(&mut self, variable_name: &str) -> Result<Option<String>> {
+        let variable_name = String::from(variable_name);
+        self.query(move |timer| timer.get_custom_variable_value(&variable_name))
+    }
+
+    fn set_custom_variable(&mut self, variable_name: &str, value: &str) -> Result<()> {
+        let (variable_name, value) = (String::from(variable_name), String::from(value));
+        self.command(move |timer| timer.set_custom_variable(&variable_name, &value))
+    }
+
+    fn set_game_name(&mut self, game_name: &str) -> Result<()> {
+        let game_name = String::from(game_name);
+        self.command(move |timer| timer.set_game_name(&game_name))
+    }
+
+    fn set_category_name(&mut self, category_name: &str) -> Result<()> {
+        let category_name = String::from(category_name);
+        self.command(move |timer| timer.set_category_name(&category_name))
+    }
+}